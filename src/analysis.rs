@@ -0,0 +1,116 @@
+//! Analysis of the position on the board in the background,
+//! so the window stays responsive while the engine thinks.
+
+use crate::board::{Board, Color, Move};
+use crate::search::{mate_in, Limits, Search, SearchInfo};
+use crate::syzygy::Tablebase;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// An engine that keeps searching whatever position it was last given.
+pub struct Analysis {
+    /// None while the search is running on its own thread.
+    search: Option<Search>,
+    thread: Option<JoinHandle<Search>>,
+    updates: Option<Receiver<SearchInfo>>,
+    /// set to stop the running search.
+    stop: Arc<AtomicBool>,
+    /// the deepest completed search of the current position.
+    pub latest: Option<SearchInfo>,
+    /// the endgame tablebases the search uses.
+    pub tablebase: Option<Arc<Tablebase>>,
+    /// how many threads the search uses.
+    pub threads: usize,
+    /// how many of the best moves get a line of their own.
+    pub multi_pv: usize,
+}
+
+impl Analysis {
+    /// creates an analysis that is not searching anything yet.
+    pub fn new() -> Self {
+        let search = Search::default();
+        Self {
+            stop: search.stop.clone(),
+            search: Some(search),
+            thread: None,
+            updates: None,
+            latest: None,
+            tablebase: None,
+            threads: 1,
+            multi_pv: 1,
+        }
+    }
+
+    /// stops analyzing the old position and starts on a new one.
+    pub fn start(&mut self, board: &Board) {
+        self.stop();
+        let mut search = self.search.take().unwrap();
+        search.tablebase = self.tablebase.clone();
+        search.threads = self.threads;
+        search.multi_pv = self.multi_pv;
+        self.stop.store(false, Ordering::Relaxed);
+        let mut board = board.clone();
+        let (sender, updates) = mpsc::channel();
+        self.updates = Some(updates);
+        self.thread = Some(thread::spawn(move || {
+            search.search(&mut board, Limits::default(), &mut |info| {
+                let _ = sender.send(info.clone());
+            });
+            search
+        }));
+    }
+
+    /// stops the search and forgets its results.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            self.search = Some(thread.join().unwrap());
+        }
+        self.updates = None;
+        self.latest = None;
+    }
+
+    /// picks up any depths finished since the last call without waiting.
+    pub fn poll(&mut self) {
+        if let Some(updates) = self.updates.as_ref() {
+            for info in updates.try_iter() {
+                self.latest = Some(info);
+            }
+        }
+    }
+}
+
+/// turns a score for the side to move into a score for white.
+pub fn white_score(board: &Board, score: i32) -> i32 {
+    if board.color == Color::White {
+        score
+    } else {
+        -score
+    }
+}
+
+/// writes a score for white the way chess players read it, like "+0.35" or "#-3".
+pub fn format_score(score: i32) -> String {
+    match mate_in(score) {
+        Some(n) => format!("#{n}"),
+        None => format!("{:+.2}", score as f64 / 100.),
+    }
+}
+
+/// writes a line of moves in SAN with move numbers, like "12. Nf3 Nc6 13. d4".
+pub fn line_to_san(board: &Board, line: &[Move]) -> String {
+    let mut board = board.clone();
+    let mut words = vec![];
+    for (i, m) in line.iter().enumerate() {
+        if board.color == Color::White {
+            words.push(format!("{}.", board.fullmove_number));
+        } else if i == 0 {
+            words.push(format!("{}...", board.fullmove_number));
+        }
+        words.push(board.san(*m));
+        board.make_move(*m);
+    }
+    words.join(" ")
+}
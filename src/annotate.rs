@@ -0,0 +1,215 @@
+//! Annotation of finished games. The engine searches every position, marks the moves
+//! that lost ground with annotation glyphs and adds what it would have played instead.
+
+use crate::board::{Board, Color};
+use crate::pgn::{Game, Variation};
+use crate::search::{mate_in, Limits, Search};
+
+/// How many centipawns a move has to lose to be an inaccuracy, a mistake and a blunder.
+const INACCURACY: i32 = 50;
+const MISTAKE: i32 = 100;
+const BLUNDER: i32 = 200;
+/// Mate scores count as this many centipawns when working out what a move lost.
+const MATE_SCORE: i32 = 1000;
+
+/// How well one side played.
+#[derive(Clone, Copy, Default)]
+pub struct Side {
+    pub moves: u32,
+    pub inaccuracies: u32,
+    pub mistakes: u32,
+    pub blunders: u32,
+    /// the average number of centipawns a move lost.
+    pub average_loss: f64,
+    /// from 0 to 100, worked out from how much each move lowered the chances of winning.
+    pub accuracy: f64,
+}
+
+/// What the engine found when annotating a game.
+pub struct Annotation {
+    /// indexed by Color::index.
+    pub sides: [Side; 2],
+    /// the score for white in centipawns before each move and after the last one.
+    pub scores: Vec<i32>,
+}
+
+impl Annotation {
+    /// describes how both sides played, like "White: accuracy 91.2%, ...".
+    pub fn summary(&self) -> String {
+        let describe = |name: &str, side: &Side| {
+            format!(
+                "{name}: accuracy {:.1}%, {} inaccuracies, {} mistakes, {} blunders, \
+                 average centipawn loss {:.0}.",
+                side.accuracy, side.inaccuracies, side.mistakes, side.blunders, side.average_loss
+            )
+        };
+        format!(
+            "{} {}",
+            describe("White", &self.sides[Color::White.index()]),
+            describe("Black", &self.sides[Color::Black.index()])
+        )
+    }
+}
+
+/// turns a score into centipawns, counting mates as MATE_SCORE.
+fn centipawns(score: i32) -> i32 {
+    match mate_in(score) {
+        Some(n) if n > 0 => MATE_SCORE,
+        Some(_) => -MATE_SCORE,
+        None => score.clamp(-MATE_SCORE, MATE_SCORE),
+    }
+}
+
+/// the chance of winning in percent for a side that is ahead by the centipawns,
+/// fitted to games between strong players.
+fn win_chance(centipawns: i32) -> f64 {
+    50. + 50. * (2. / (1. + (-0.00368208 * centipawns as f64).exp()) - 1.)
+}
+
+/// searches every position of a game and annotates its moves, marking the ones that lost
+/// ground with a glyph, a comment and the engine's line, and the game with a summary.
+/// progress is called with the number of positions searched so far.
+pub fn annotate(
+    game: &mut Game,
+    search: &mut Search,
+    limits: Limits,
+    progress: &mut dyn FnMut(usize),
+) -> Result<Annotation, String> {
+    let count = game.moves.len();
+    game.comments.resize(count, String::new());
+    game.nags.resize(count, 0);
+    game.variations.resize_with(count, Vec::new);
+    let mut board = Board::from_fen(&game.start_fen)?;
+    search.clear();
+
+    // the score for the side to move and the best line of each position
+    let mut lines = vec![];
+    let mut scores = vec![];
+    for i in 0..=count {
+        let line = if board.is_checkmate() {
+            (-MATE_SCORE, vec![])
+        } else if board.legal_moves().is_empty() || board.is_draw() {
+            (0, vec![])
+        } else {
+            let info = search.search(&mut board, limits, &mut |_| ());
+            (centipawns(info.score), info.pv)
+        };
+        scores.push(match board.color {
+            Color::White => line.0,
+            Color::Black => -line.0,
+        });
+        lines.push(line);
+        progress(i + 1);
+        if i < count {
+            board.make_move(game.moves[i]);
+        }
+    }
+
+    let mut board = Board::from_fen(&game.start_fen)?;
+    let mut sides = [Side::default(); 2];
+    let mut losses = [0; 2];
+    let mut accuracies = [0.; 2];
+    for (i, &m) in game.moves.iter().enumerate() {
+        let (best, ref line) = lines[i];
+        let after = -lines[i + 1].0;
+        // the search can score the move it prefers a little differently a ply later
+        let loss = if line.first() == Some(&m) {
+            0
+        } else {
+            (best - after).max(0)
+        };
+        let side = &mut sides[board.color.index()];
+        side.moves += 1;
+        losses[board.color.index()] += loss;
+        let accuracy =
+            103.1668 * (-0.04354 * (win_chance(best) - win_chance(best - loss))).exp() - 3.1669;
+        accuracies[board.color.index()] += accuracy.clamp(0., 100.);
+
+        let (nag, name) = if loss >= BLUNDER {
+            side.blunders += 1;
+            (4, "Blunder")
+        } else if loss >= MISTAKE {
+            side.mistakes += 1;
+            (2, "Mistake")
+        } else if loss >= INACCURACY {
+            side.inaccuracies += 1;
+            (6, "Inaccuracy")
+        } else {
+            (0, "")
+        };
+        if nag != 0 && !line.is_empty() {
+            game.nags[i] = nag;
+            let comment = &mut game.comments[i];
+            if !comment.is_empty() {
+                comment.push(' ');
+            }
+            *comment += &format!("{name}. {} was best.", board.san(line[0]));
+            let variations = &mut game.variations[i];
+            if !variations.iter().any(|v| v.moves.first() == line.first()) {
+                variations.push(Variation::new(line.clone()));
+            }
+        }
+        board.make_move(m);
+    }
+    for (i, side) in sides.iter_mut().enumerate() {
+        if side.moves > 0 {
+            side.average_loss = losses[i] as f64 / side.moves as f64;
+            side.accuracy = accuracies[i] / side.moves as f64;
+        } else {
+            side.accuracy = 100.;
+        }
+    }
+
+    let annotation = Annotation { sides, scores };
+    if !game.comment.is_empty() {
+        game.comment.push(' ');
+    }
+    game.comment += &annotation.summary();
+    game.set_tag("Annotator", "Chess");
+    Ok(annotation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn;
+    use crate::search::MATE;
+
+    #[test]
+    fn marks_blunders() {
+        let text = "1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0";
+        let mut game = pgn::read_games(text).remove(0).unwrap();
+        let mut search = Search::default();
+        let limits = Limits {
+            depth: Some(3),
+            ..Limits::default()
+        };
+        let mut searched = 0;
+        let annotation = annotate(&mut game, &mut search, limits, &mut |n| searched = n).unwrap();
+        assert_eq!(searched, 8);
+        assert_eq!(annotation.scores.len(), 8);
+        assert_eq!(annotation.scores[7], MATE_SCORE);
+
+        // 3... Nf6 allows the mate
+        assert_eq!(game.nags[5], 4);
+        assert!(game.comments[5].starts_with("Blunder. "));
+        assert_eq!(game.variations[5].len(), 1);
+        let black = annotation.sides[Color::Black.index()];
+        assert_eq!((black.moves, black.blunders), (3, 1));
+        let white = annotation.sides[Color::White.index()];
+        assert_eq!(white.blunders, 0);
+        assert!(white.accuracy > black.accuracy);
+        assert!(game.comment.starts_with("White: accuracy"));
+        assert_eq!(game.tag("Annotator"), Some("Chess"));
+    }
+
+    #[test]
+    fn counts_mates_as_a_fixed_loss() {
+        assert_eq!(centipawns(MATE - 3), MATE_SCORE);
+        assert_eq!(centipawns(-MATE + 2), -MATE_SCORE);
+        assert_eq!(centipawns(5000), MATE_SCORE);
+        assert_eq!(centipawns(-30), -30);
+        assert_eq!(win_chance(0), 50.);
+        assert!(win_chance(300) > 75.);
+    }
+}
@@ -0,0 +1,1145 @@
+//! The rules of chess.
+//! Holds the board representation, legal move generation, and the notations
+//! used to read and write positions and moves (FEN, SAN and UCI).
+
+use crate::nnue::{Accumulator, Network};
+use crate::zobrist::RANDOM64;
+use crate::BOARD_SIZE;
+use std::sync::Arc;
+
+/// The position of a square on the board.
+/// The first number is the file (x) and the second is the rank (y).
+/// [0, 0] is a1 and [7, 7] is h8.
+pub type Pos = [i8; 2];
+
+/// The FEN of the normal starting position.
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// The two different colors a chess piece can be.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    White,
+}
+
+impl Color {
+    /// the other team.
+    pub fn opponent(self) -> Self {
+        match self {
+            Color::Black => Color::White,
+            Color::White => Color::Black,
+        }
+    }
+
+    /// a number that can be used to index arrays with one entry per color.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// An enum that represents a spot on a chess board.
+/// Holds the team information and which type of piece it is.
+/// Alternatively, It could represent and empty space on the chessboard.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Piece {
+    Empty,
+    Black(Type),
+    White(Type),
+}
+
+impl Piece {
+    /// creates a piece of the given color and type.
+    pub fn new(color: Color, kind: Type) -> Self {
+        match color {
+            Color::Black => Piece::Black(kind),
+            Color::White => Piece::White(kind),
+        }
+    }
+
+    /// the team of the piece, or None for an empty square.
+    pub fn color(self) -> Option<Color> {
+        match self {
+            Piece::Empty => None,
+            Piece::Black(_) => Some(Color::Black),
+            Piece::White(_) => Some(Color::White),
+        }
+    }
+
+    /// the type of the piece, or None for an empty square.
+    pub fn kind(self) -> Option<Type> {
+        match self {
+            Piece::Empty => None,
+            Piece::Black(kind) | Piece::White(kind) => Some(kind),
+        }
+    }
+
+    /// the piece written as a FEN letter.
+    pub fn from_char(c: char) -> Option<Self> {
+        let kind = Type::from_char(c.to_ascii_uppercase())?;
+        if c.is_ascii_uppercase() {
+            Some(Piece::White(kind))
+        } else {
+            Some(Piece::Black(kind))
+        }
+    }
+}
+
+/// An enum that represents each type of chess piece there is.
+/// Does not identify team at all.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    Pawn,
+    Rook,
+    Knight,
+    Bishop,
+    Queen,
+    King,
+}
+
+impl Type {
+    /// a number that can be used to index arrays with one entry per type.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// the upper case letter used for the type in SAN and FEN.
+    pub fn to_char(self) -> char {
+        match self {
+            Type::Pawn => 'P',
+            Type::Rook => 'R',
+            Type::Knight => 'N',
+            Type::Bishop => 'B',
+            Type::Queen => 'Q',
+            Type::King => 'K',
+        }
+    }
+
+    /// the type written as an upper case letter.
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'P' => Some(Type::Pawn),
+            'R' => Some(Type::Rook),
+            'N' => Some(Type::Knight),
+            'B' => Some(Type::Bishop),
+            'Q' => Some(Type::Queen),
+            'K' => Some(Type::King),
+            _ => None,
+        }
+    }
+
+    /// the position of the type in the Polyglot piece order.
+    fn polyglot_index(self) -> usize {
+        match self {
+            Type::Pawn => 0,
+            Type::Knight => 1,
+            Type::Bishop => 2,
+            Type::Rook => 3,
+            Type::Queen => 4,
+            Type::King => 5,
+        }
+    }
+}
+
+/// A single move of a piece from one square to another.
+/// Castling is written as the king moving two squares.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub from: Pos,
+    pub to: Pos,
+    /// the type a pawn turns into when it reaches the last rank.
+    pub promotion: Option<Type>,
+}
+
+impl Move {
+    /// creates a move without a promotion.
+    pub fn new(from: Pos, to: Pos) -> Self {
+        Self {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+
+    /// writes the move in the long algebraic notation used by UCI, like "e2e4" or "e7e8q".
+    pub fn to_uci(self) -> String {
+        let mut s = pos_to_string(self.from) + &pos_to_string(self.to);
+        if let Some(kind) = self.promotion {
+            s.push(kind.to_char().to_ascii_lowercase());
+        }
+        s
+    }
+}
+
+/// the index of a square from 0 (a1) to 63 (h8).
+pub fn square_index(pos: Pos) -> usize {
+    pos[1] as usize * BOARD_SIZE + pos[0] as usize
+}
+
+/// true if a point is outside of the chess board
+pub fn point_out_of_bounds(pos: Pos) -> bool {
+    pos[0] < 0 || pos[0] >= BOARD_SIZE as i8 || pos[1] < 0 || pos[1] >= BOARD_SIZE as i8
+}
+
+/// writes a square in algebraic notation, like "e4".
+pub fn pos_to_string(pos: Pos) -> String {
+    let file = (b'a' + pos[0] as u8) as char;
+    let rank = (b'1' + pos[1] as u8) as char;
+    format!("{file}{rank}")
+}
+
+/// reads a square in algebraic notation, like "e4".
+pub fn parse_pos(s: &str) -> Option<Pos> {
+    let bytes = s.as_bytes();
+    if bytes.len() != 2 {
+        return None;
+    }
+    let pos = [bytes[0] as i8 - b'a' as i8, bytes[1] as i8 - b'1' as i8];
+    if point_out_of_bounds(pos) {
+        return None;
+    }
+    Some(pos)
+}
+
+const KNIGHT_OFFSETS: [Pos; 8] = [
+    [1, 2],
+    [2, 1],
+    [2, -1],
+    [1, -2],
+    [-1, -2],
+    [-2, -1],
+    [-2, 1],
+    [-1, 2],
+];
+const KING_OFFSETS: [Pos; 8] = [
+    [1, 0],
+    [1, 1],
+    [0, 1],
+    [-1, 1],
+    [-1, 0],
+    [-1, -1],
+    [0, -1],
+    [1, -1],
+];
+const ROOK_DIRECTIONS: [Pos; 4] = [[1, 0], [-1, 0], [0, 1], [0, -1]];
+const BISHOP_DIRECTIONS: [Pos; 4] = [[1, 1], [-1, 1], [1, -1], [-1, -1]];
+const PROMOTION_TYPES: [Type; 4] = [Type::Queen, Type::Rook, Type::Bishop, Type::Knight];
+
+/// Castling rights, in the order white king side, white queen side,
+/// black king side and black queen side.
+const CASTLING_CHARS: [char; 4] = ['K', 'Q', 'k', 'q'];
+/// The starting square of the rook used by each castling right.
+const CASTLING_ROOKS: [Pos; 4] = [[7, 0], [0, 0], [7, 7], [0, 7]];
+
+/// Everything needed to take back a move.
+#[derive(Copy, Clone)]
+struct Undo {
+    /// None for a null move.
+    mv: Option<Move>,
+    moved: Piece,
+    captured: Piece,
+    en_passant_capture: bool,
+    castling: [bool; 4],
+    en_passant: Option<Pos>,
+    halfmove_clock: u32,
+    hash: u64,
+}
+
+/// A chess position along with the moves that led to it.
+#[derive(Clone)]
+pub struct Board {
+    /// the pieces on the board, indexed by [y][x].
+    pub squares: [[Piece; BOARD_SIZE]; BOARD_SIZE],
+    /// which team currently has a turn.
+    pub color: Color,
+    /// which castling moves are still allowed, see CASTLING_CHARS.
+    pub castling: [bool; 4],
+    /// the square a pawn skipped over with a double step on the last move.
+    pub en_passant: Option<Pos>,
+    /// the number of moves since the last capture or pawn move.
+    pub halfmove_clock: u32,
+    /// the number of the current move, starting at 1 and going up after black moves.
+    pub fullmove_number: u32,
+    /// the Polyglot zobrist hash of the position.
+    pub hash: u64,
+    /// the position of each king, indexed by Color::index.
+    kings: [Pos; 2],
+    history: Vec<Undo>,
+    /// the hidden layers of the network evaluating the board, if there is one.
+    accumulator: Option<Accumulator>,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Board {
+    /// creates a board with all pieces in the correct starting position.
+    pub fn new() -> Self {
+        Self::from_fen(START_FEN).unwrap()
+    }
+
+    /// reads a position from Forsyth-Edwards Notation.
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(format!("not enough fields in FEN \"{fen}\""));
+        }
+        let mut board = Self {
+            squares: [[Piece::Empty; BOARD_SIZE]; BOARD_SIZE],
+            color: Color::White,
+            castling: [false; 4],
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            hash: 0,
+            kings: [[-1, -1]; 2],
+            history: vec![],
+            accumulator: None,
+        };
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != BOARD_SIZE {
+            return Err(format!("FEN \"{fen}\" does not have 8 ranks"));
+        }
+        for (i, rank) in ranks.iter().enumerate() {
+            let y = BOARD_SIZE - 1 - i;
+            let mut x = 0;
+            for c in rank.chars() {
+                if let Some(n) = c.to_digit(10) {
+                    x += n as usize;
+                } else {
+                    let piece = Piece::from_char(c)
+                        .ok_or_else(|| format!("unknown piece '{c}' in FEN \"{fen}\""))?;
+                    if x >= BOARD_SIZE {
+                        return Err(format!("rank too long in FEN \"{fen}\""));
+                    }
+                    if piece.kind() == Some(Type::Pawn) && (y == 0 || y == BOARD_SIZE - 1) {
+                        return Err(format!("pawn on rank {} in FEN \"{fen}\"", y + 1));
+                    }
+                    board.squares[y][x] = piece;
+                    x += 1;
+                }
+            }
+            if x != BOARD_SIZE {
+                return Err(format!(
+                    "rank {} has the wrong length in FEN \"{fen}\"",
+                    y + 1
+                ));
+            }
+        }
+        board.color = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(format!("unknown side to move in FEN \"{fen}\"")),
+        };
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                match CASTLING_CHARS.iter().position(|&k| k == c) {
+                    Some(i) => board.castling[i] = true,
+                    None => return Err(format!("unknown castling right '{c}' in FEN \"{fen}\"")),
+                }
+            }
+        }
+        if fields[3] != "-" {
+            board.en_passant = Some(
+                parse_pos(fields[3])
+                    .ok_or_else(|| format!("bad en passant square in FEN \"{fen}\""))?,
+            );
+        }
+        if let Some(n) = fields.get(4) {
+            board.halfmove_clock = n.parse().map_err(|_| format!("bad clock in \"{fen}\""))?;
+        }
+        if let Some(n) = fields.get(5) {
+            board.fullmove_number = n.parse().map_err(|_| format!("bad move in \"{fen}\""))?;
+        }
+        for color in [Color::Black, Color::White].iter() {
+            let king = board.find(Piece::new(*color, Type::King));
+            if king.len() != 1 {
+                return Err(format!("FEN \"{fen}\" needs exactly one king per side"));
+            }
+            board.kings[color.index()] = king[0];
+        }
+        // drop castling rights that the pieces on the board can not have
+        for (i, rook) in CASTLING_ROOKS.iter().enumerate() {
+            let color = if i < 2 { Color::White } else { Color::Black };
+            if board.piece_at([4, rook[1]]) != Piece::new(color, Type::King)
+                || board.piece_at(*rook) != Piece::new(color, Type::Rook)
+            {
+                board.castling[i] = false;
+            }
+        }
+        board.hash = board.compute_hash();
+        Ok(board)
+    }
+
+    /// writes the position in Forsyth-Edwards Notation.
+    pub fn to_fen(&self) -> String {
+        let mut ranks = vec![];
+        for row in self.squares.iter().rev() {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for &piece in row.iter() {
+                let kind = match piece.kind() {
+                    Some(kind) => kind,
+                    None => {
+                        empty += 1;
+                        continue;
+                    }
+                };
+                if empty > 0 {
+                    rank += &empty.to_string();
+                    empty = 0;
+                }
+                rank.push(match piece {
+                    Piece::Black(_) => kind.to_char().to_ascii_lowercase(),
+                    _ => kind.to_char(),
+                });
+            }
+            if empty > 0 {
+                rank += &empty.to_string();
+            }
+            ranks.push(rank);
+        }
+        let castling: String = CASTLING_CHARS
+            .iter()
+            .zip(self.castling.iter())
+            .filter(|(_, &allowed)| allowed)
+            .map(|(&c, _)| c)
+            .collect();
+        format!(
+            "{} {} {} {} {} {}",
+            ranks.join("/"),
+            if self.color == Color::White { "w" } else { "b" },
+            if castling.is_empty() { "-" } else { &castling },
+            self.en_passant.map_or("-".to_owned(), pos_to_string),
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+
+    /// gets the piece at the given position.
+    pub fn piece_at(&self, pos: Pos) -> Piece {
+        self.squares[pos[1] as usize][pos[0] as usize]
+    }
+
+    /// starts or stops keeping the hidden layers of a network up to date as moves are made.
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.accumulator = network.map(|network| Accumulator::new(network, &self.squares));
+    }
+
+    /// the hidden layers of the network set with set_network.
+    pub fn accumulator(&self) -> Option<&Accumulator> {
+        self.accumulator.as_ref()
+    }
+
+    /// the position of the king of the given color.
+    pub fn king_pos(&self, color: Color) -> Pos {
+        self.kings[color.index()]
+    }
+
+    /// lists the positions of every copy of a piece.
+    pub fn find(&self, piece: Piece) -> Vec<Pos> {
+        let mut v = vec![];
+        for y in 0..BOARD_SIZE {
+            for x in 0..BOARD_SIZE {
+                if self.squares[y][x] == piece {
+                    v.push([x as i8, y as i8]);
+                }
+            }
+        }
+        v
+    }
+
+    /// the key of a piece on a square.
+    fn piece_key(piece: Piece, pos: Pos) -> u64 {
+        let kind = match piece {
+            Piece::Empty => return 0,
+            Piece::Black(kind) => 2 * kind.polyglot_index(),
+            Piece::White(kind) => 2 * kind.polyglot_index() + 1,
+        };
+        RANDOM64[64 * kind + square_index(pos)]
+    }
+
+    /// the part of the hash that comes from the en passant square.
+    /// Like Polyglot, it only counts if a pawn could actually make the capture.
+    fn en_passant_key(&self) -> u64 {
+        let pos = match self.en_passant {
+            Some(pos) => pos,
+            None => return 0,
+        };
+        let pawn_y = if self.color == Color::White {
+            pos[1] - 1
+        } else {
+            pos[1] + 1
+        };
+        for dx in [-1, 1].iter() {
+            let from = [pos[0] + dx, pawn_y];
+            if !point_out_of_bounds(from)
+                && self.piece_at(from) == Piece::new(self.color, Type::Pawn)
+            {
+                return RANDOM64[772 + pos[0] as usize];
+            }
+        }
+        0
+    }
+
+    /// the key of all castling rights that are still available.
+    fn castling_key(&self) -> u64 {
+        let mut key = 0;
+        for i in 0..4 {
+            if self.castling[i] {
+                key ^= RANDOM64[768 + i];
+            }
+        }
+        key
+    }
+
+    /// computes the hash of the position from scratch.
+    fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for y in 0..BOARD_SIZE {
+            for x in 0..BOARD_SIZE {
+                hash ^= Self::piece_key(self.squares[y][x], [x as i8, y as i8]);
+            }
+        }
+        hash ^= self.castling_key();
+        hash ^= self.en_passant_key();
+        if self.color == Color::White {
+            hash ^= RANDOM64[780];
+        }
+        hash
+    }
+
+    /// puts a piece on a square, keeping the hash and king positions up to date.
+    fn set_piece(&mut self, pos: Pos, piece: Piece) {
+        if let Some(accumulator) = self.accumulator.as_mut() {
+            let old = self.squares[pos[1] as usize][pos[0] as usize];
+            accumulator.replace(pos, old, piece);
+        }
+        self.hash ^= Self::piece_key(self.piece_at(pos), pos);
+        self.hash ^= Self::piece_key(piece, pos);
+        self.squares[pos[1] as usize][pos[0] as usize] = piece;
+        if let Piece::Black(Type::King) | Piece::White(Type::King) = piece {
+            self.kings[piece.color().unwrap().index()] = pos;
+        }
+    }
+
+    /// true if any piece of the given color attacks the position.
+    pub fn is_attacked(&self, pos: Pos, by: Color) -> bool {
+        // pawns attack diagonally forward, so look one rank behind from their point of view
+        let pawn_y = if by == Color::White {
+            pos[1] - 1
+        } else {
+            pos[1] + 1
+        };
+        for dx in [-1, 1].iter() {
+            let from = [pos[0] + dx, pawn_y];
+            if !point_out_of_bounds(from) && self.piece_at(from) == Piece::new(by, Type::Pawn) {
+                return true;
+            }
+        }
+        for offset in KNIGHT_OFFSETS.iter() {
+            let from = [pos[0] + offset[0], pos[1] + offset[1]];
+            if !point_out_of_bounds(from) && self.piece_at(from) == Piece::new(by, Type::Knight) {
+                return true;
+            }
+        }
+        for offset in KING_OFFSETS.iter() {
+            let from = [pos[0] + offset[0], pos[1] + offset[1]];
+            if !point_out_of_bounds(from) && self.piece_at(from) == Piece::new(by, Type::King) {
+                return true;
+            }
+        }
+        for (directions, slider) in [
+            (ROOK_DIRECTIONS, Type::Rook),
+            (BISHOP_DIRECTIONS, Type::Bishop),
+        ]
+        .iter()
+        {
+            for direction in directions.iter() {
+                let mut from = [pos[0] + direction[0], pos[1] + direction[1]];
+                while !point_out_of_bounds(from) {
+                    let piece = self.piece_at(from);
+                    if piece != Piece::Empty {
+                        if piece == Piece::new(by, *slider) || piece == Piece::new(by, Type::Queen)
+                        {
+                            return true;
+                        }
+                        break;
+                    }
+                    from = [from[0] + direction[0], from[1] + direction[1]];
+                }
+            }
+        }
+        false
+    }
+
+    /// true if the king of the team whose turn it is is being attacked.
+    pub fn is_in_check(&self) -> bool {
+        self.is_attacked(self.king_pos(self.color), self.color.opponent())
+    }
+
+    /// true if the move takes a piece, including en passant.
+    pub fn is_capture(&self, m: Move) -> bool {
+        self.piece_at(m.to) != Piece::Empty || self.is_en_passant(m)
+    }
+
+    /// true if the move is a pawn taking another pawn en passant.
+    fn is_en_passant(&self, m: Move) -> bool {
+        self.piece_at(m.from).kind() == Some(Type::Pawn)
+            && m.from[0] != m.to[0]
+            && self.piece_at(m.to) == Piece::Empty
+    }
+
+    /// true if the given team has anything besides pawns and a king.
+    pub fn has_non_pawn_material(&self, color: Color) -> bool {
+        self.squares.iter().flatten().any(|piece| {
+            piece.color() == Some(color)
+                && !matches!(piece.kind(), Some(Type::Pawn) | Some(Type::King))
+        })
+    }
+
+    /// pushes a move to a vector if the target square is empty or holds an enemy.
+    fn push_if_open(&self, from: Pos, to: Pos, captures_only: bool, v: &mut Vec<Move>) -> bool {
+        if point_out_of_bounds(to) {
+            return false;
+        }
+        match self.piece_at(to).color() {
+            None => {
+                if !captures_only {
+                    v.push(Move::new(from, to));
+                }
+                true
+            }
+            Some(color) => {
+                if color != self.color {
+                    v.push(Move::new(from, to));
+                }
+                false
+            }
+        }
+    }
+
+    /// pushes a pawn move, expanding it to every promotion when it reaches the last rank.
+    fn push_pawn_move(&self, from: Pos, to: Pos, captures_only: bool, v: &mut Vec<Move>) {
+        if to[1] == 0 || to[1] == BOARD_SIZE as i8 - 1 {
+            for kind in PROMOTION_TYPES.iter() {
+                // under promotions are not tactical enough for the quiescence search
+                if captures_only && *kind != Type::Queen {
+                    continue;
+                }
+                v.push(Move {
+                    from,
+                    to,
+                    promotion: Some(*kind),
+                });
+            }
+        } else {
+            v.push(Move::new(from, to));
+        }
+    }
+
+    /// lists the moves of the pawn on the given square.
+    fn pawn_moves(&self, pos: Pos, captures_only: bool, v: &mut Vec<Move>) {
+        let (dir, start) = if self.color == Color::White {
+            (1, 1)
+        } else {
+            (-1, BOARD_SIZE as i8 - 2)
+        };
+        let last_rank = if self.color == Color::White {
+            BOARD_SIZE as i8 - 1
+        } else {
+            0
+        };
+        let one = [pos[0], pos[1] + dir];
+        if self.piece_at(one) == Piece::Empty && (!captures_only || one[1] == last_rank) {
+            self.push_pawn_move(pos, one, captures_only, v);
+            let two = [pos[0], pos[1] + 2 * dir];
+            if pos[1] == start && !captures_only && self.piece_at(two) == Piece::Empty {
+                v.push(Move::new(pos, two));
+            }
+        }
+        for dx in [-1, 1].iter() {
+            let to = [pos[0] + dx, pos[1] + dir];
+            if point_out_of_bounds(to) {
+                continue;
+            }
+            let enemy = self.piece_at(to).color() == Some(self.color.opponent());
+            if enemy || self.en_passant == Some(to) {
+                self.push_pawn_move(pos, to, captures_only, v);
+            }
+        }
+    }
+
+    /// lists the castling moves of the side to move.
+    fn castling_moves(&self, v: &mut Vec<Move>) {
+        let (y, rights) = if self.color == Color::White {
+            (0, [0, 1])
+        } else {
+            (BOARD_SIZE as i8 - 1, [2, 3])
+        };
+        let king = [4, y];
+        let enemy = self.color.opponent();
+        if !(self.castling[rights[0]] || self.castling[rights[1]]) || self.is_attacked(king, enemy)
+        {
+            return;
+        }
+        if self.castling[rights[0]]
+            && self.piece_at([5, y]) == Piece::Empty
+            && self.piece_at([6, y]) == Piece::Empty
+            && !self.is_attacked([5, y], enemy)
+            && !self.is_attacked([6, y], enemy)
+        {
+            v.push(Move::new(king, [6, y]));
+        }
+        if self.castling[rights[1]]
+            && self.piece_at([3, y]) == Piece::Empty
+            && self.piece_at([2, y]) == Piece::Empty
+            && self.piece_at([1, y]) == Piece::Empty
+            && !self.is_attacked([3, y], enemy)
+            && !self.is_attacked([2, y], enemy)
+        {
+            v.push(Move::new(king, [2, y]));
+        }
+    }
+
+    /// lists the moves of the side to move without checking if they leave the king in check.
+    /// With captures_only, only captures and queen promotions are listed.
+    pub fn pseudo_legal_moves(&self, captures_only: bool) -> Vec<Move> {
+        let mut v = Vec::with_capacity(48);
+        for y in 0..BOARD_SIZE {
+            for x in 0..BOARD_SIZE {
+                let piece = self.squares[y][x];
+                if piece.color() != Some(self.color) {
+                    continue;
+                }
+                let pos = [x as i8, y as i8];
+                match piece.kind().unwrap() {
+                    Type::Pawn => self.pawn_moves(pos, captures_only, &mut v),
+                    Type::Knight => {
+                        for offset in KNIGHT_OFFSETS.iter() {
+                            let to = [pos[0] + offset[0], pos[1] + offset[1]];
+                            self.push_if_open(pos, to, captures_only, &mut v);
+                        }
+                    }
+                    Type::King => {
+                        for offset in KING_OFFSETS.iter() {
+                            let to = [pos[0] + offset[0], pos[1] + offset[1]];
+                            self.push_if_open(pos, to, captures_only, &mut v);
+                        }
+                        if !captures_only {
+                            self.castling_moves(&mut v);
+                        }
+                    }
+                    kind => {
+                        let directions: &[Pos] = match kind {
+                            Type::Rook => &ROOK_DIRECTIONS,
+                            Type::Bishop => &BISHOP_DIRECTIONS,
+                            _ => &KING_OFFSETS,
+                        };
+                        for direction in directions.iter() {
+                            let mut to = [pos[0] + direction[0], pos[1] + direction[1]];
+                            while self.push_if_open(pos, to, captures_only, &mut v) {
+                                to = [to[0] + direction[0], to[1] + direction[1]];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        v
+    }
+
+    /// true if making the move would not leave the mover's king in check.
+    pub fn is_legal(&mut self, m: Move) -> bool {
+        let color = self.color;
+        self.make_move(m);
+        let legal = !self.is_attacked(self.king_pos(color), color.opponent());
+        self.unmake_move();
+        legal
+    }
+
+    /// lists every legal move of the side to move.
+    pub fn legal_moves(&mut self) -> Vec<Move> {
+        let moves = self.pseudo_legal_moves(false);
+        moves.into_iter().filter(|m| self.is_legal(*m)).collect()
+    }
+
+    /// lists the legal moves of the piece on the given square.
+    pub fn legal_moves_from(&mut self, pos: Pos) -> Vec<Move> {
+        let moves = self.legal_moves();
+        moves.into_iter().filter(|m| m.from == pos).collect()
+    }
+
+    /// moves a piece and updates the rest of the position.
+    /// The move must be at least pseudo legal.
+    pub fn make_move(&mut self, m: Move) {
+        let moved = self.piece_at(m.from);
+        let en_passant_capture = self.is_en_passant(m);
+        let captured = if en_passant_capture {
+            self.piece_at([m.to[0], m.from[1]])
+        } else {
+            self.piece_at(m.to)
+        };
+        self.history.push(Undo {
+            mv: Some(m),
+            moved,
+            captured,
+            en_passant_capture,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            hash: self.hash,
+        });
+        self.hash ^= self.en_passant_key() ^ self.castling_key();
+
+        if en_passant_capture {
+            self.set_piece([m.to[0], m.from[1]], Piece::Empty);
+        }
+        self.set_piece(m.from, Piece::Empty);
+        let placed = match m.promotion {
+            Some(kind) => Piece::new(self.color, kind),
+            None => moved,
+        };
+        self.set_piece(m.to, placed);
+        if moved.kind() == Some(Type::King) && (m.to[0] - m.from[0]).abs() == 2 {
+            let (rook_from, rook_to) = if m.to[0] > m.from[0] {
+                ([7, m.from[1]], [5, m.from[1]])
+            } else {
+                ([0, m.from[1]], [3, m.from[1]])
+            };
+            let rook = self.piece_at(rook_from);
+            self.set_piece(rook_from, Piece::Empty);
+            self.set_piece(rook_to, rook);
+        }
+
+        // moving the king or a rook, or taking a rook, loses the right to castle with it
+        for (allowed, rook) in self.castling.iter_mut().zip(CASTLING_ROOKS.iter()) {
+            if m.from == *rook || m.to == *rook {
+                *allowed = false;
+            }
+        }
+        if moved.kind() == Some(Type::King) {
+            let first = if self.color == Color::White { 0 } else { 2 };
+            self.castling[first] = false;
+            self.castling[first + 1] = false;
+        }
+
+        self.en_passant = None;
+        if moved.kind() == Some(Type::Pawn) && (m.to[1] - m.from[1]).abs() == 2 {
+            self.en_passant = Some([m.from[0], (m.from[1] + m.to[1]) / 2]);
+        }
+        if moved.kind() == Some(Type::Pawn) || captured != Piece::Empty {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if self.color == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.color = self.color.opponent();
+        self.hash ^= RANDOM64[780];
+        self.hash ^= self.en_passant_key() ^ self.castling_key();
+    }
+
+    /// takes back the last move made with make_move or make_null_move.
+    pub fn unmake_move(&mut self) {
+        let undo = self.history.pop().unwrap();
+        self.color = self.color.opponent();
+        if self.color == Color::Black {
+            self.fullmove_number -= 1;
+        }
+        self.castling = undo.castling;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        if let Some(m) = undo.mv {
+            self.set_piece(m.from, undo.moved);
+            if undo.en_passant_capture {
+                self.set_piece(m.to, Piece::Empty);
+                self.set_piece([m.to[0], m.from[1]], undo.captured);
+            } else {
+                self.set_piece(m.to, undo.captured);
+            }
+            if undo.moved.kind() == Some(Type::King) && (m.to[0] - m.from[0]).abs() == 2 {
+                let (rook_from, rook_to) = if m.to[0] > m.from[0] {
+                    ([7, m.from[1]], [5, m.from[1]])
+                } else {
+                    ([0, m.from[1]], [3, m.from[1]])
+                };
+                let rook = self.piece_at(rook_to);
+                self.set_piece(rook_to, Piece::Empty);
+                self.set_piece(rook_from, rook);
+            }
+        }
+        self.hash = undo.hash;
+    }
+
+    /// passes the turn to the other team without moving, used by null move pruning.
+    pub fn make_null_move(&mut self) {
+        self.history.push(Undo {
+            mv: None,
+            moved: Piece::Empty,
+            captured: Piece::Empty,
+            en_passant_capture: false,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            hash: self.hash,
+        });
+        self.hash ^= self.en_passant_key();
+        self.en_passant = None;
+        self.halfmove_clock += 1;
+        if self.color == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.color = self.color.opponent();
+        self.hash ^= RANDOM64[780];
+    }
+
+    /// the number of earlier times the current position appeared since the last irreversible move.
+    pub fn repetitions(&self) -> usize {
+        self.history
+            .iter()
+            .rev()
+            .take(self.halfmove_clock as usize)
+            .skip(1)
+            .step_by(2)
+            .filter(|undo| undo.hash == self.hash)
+            .count()
+    }
+
+    /// true if the side to move has been checkmated.
+    pub fn is_checkmate(&mut self) -> bool {
+        self.is_in_check() && self.legal_moves().is_empty()
+    }
+
+    /// true if the side to move can not move but is not in check.
+    pub fn is_stalemate(&mut self) -> bool {
+        !self.is_in_check() && self.legal_moves().is_empty()
+    }
+
+    /// true if neither side has enough pieces left to give checkmate.
+    pub fn is_insufficient_material(&self) -> bool {
+        let mut minors = 0;
+        for piece in self.squares.iter().flatten() {
+            match piece.kind() {
+                Some(Type::Knight) | Some(Type::Bishop) => minors += 1,
+                Some(Type::King) | None => (),
+                _ => return false,
+            }
+        }
+        minors <= 1
+    }
+
+    /// true if the game is drawn by the fifty move rule, threefold repetition or lack of material.
+    pub fn is_draw(&self) -> bool {
+        self.halfmove_clock >= 100 || self.repetitions() >= 2 || self.is_insufficient_material()
+    }
+
+    /// reads a move written in UCI's long algebraic notation, like "e2e4".
+    pub fn parse_uci_move(&mut self, s: &str) -> Option<Move> {
+        let moves = self.legal_moves();
+        moves.into_iter().find(|m| m.to_uci() == s)
+    }
+
+    /// writes a legal move in Standard Algebraic Notation, like "Nf3", "exd5" or "O-O".
+    pub fn san(&mut self, m: Move) -> String {
+        let piece = self.piece_at(m.from);
+        let kind = piece.kind().unwrap();
+        let mut s = String::new();
+        if kind == Type::King && (m.to[0] - m.from[0]).abs() == 2 {
+            s += if m.to[0] > m.from[0] { "O-O" } else { "O-O-O" };
+        } else {
+            let capture = self.is_capture(m);
+            if kind == Type::Pawn {
+                if capture {
+                    s.push((b'a' + m.from[0] as u8) as char);
+                }
+            } else {
+                s.push(kind.to_char());
+                let others: Vec<Move> = self
+                    .legal_moves()
+                    .into_iter()
+                    .filter(|o| o.to == m.to && o.from != m.from && self.piece_at(o.from) == piece)
+                    .collect();
+                if !others.is_empty() {
+                    let file = (b'a' + m.from[0] as u8) as char;
+                    let rank = (b'1' + m.from[1] as u8) as char;
+                    if others.iter().all(|o| o.from[0] != m.from[0]) {
+                        s.push(file);
+                    } else if others.iter().all(|o| o.from[1] != m.from[1]) {
+                        s.push(rank);
+                    } else {
+                        s.push(file);
+                        s.push(rank);
+                    }
+                }
+            }
+            if capture {
+                s.push('x');
+            }
+            s += &pos_to_string(m.to);
+            if let Some(promotion) = m.promotion {
+                s.push('=');
+                s.push(promotion.to_char());
+            }
+        }
+        self.make_move(m);
+        if self.is_in_check() {
+            s.push(if self.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        self.unmake_move();
+        s
+    }
+
+    /// reads a move written in Standard Algebraic Notation.
+    pub fn parse_san(&mut self, san: &str) -> Option<Move> {
+        let san = san.trim().trim_end_matches(|c| "+#!?".contains(c));
+        let moves = self.legal_moves();
+        if san == "O-O" || san == "0-0" || san == "O-O-O" || san == "0-0-0" {
+            let king = self.king_pos(self.color);
+            let to = if san.len() == 3 { 6 } else { 2 };
+            return moves.into_iter().find(|m| {
+                m.from == king
+                    && m.to == [to, king[1]]
+                    && self.piece_at(king).kind() == Some(Type::King)
+            });
+        }
+        let mut chars: Vec<char> = san.chars().filter(|&c| c != 'x' && c != '=').collect();
+        let promotion = match chars.last().and_then(|&c| Type::from_char(c)) {
+            Some(kind) => {
+                chars.pop();
+                Some(kind)
+            }
+            None => None,
+        };
+        let kind = match chars.first().and_then(|&c| Type::from_char(c)) {
+            Some(kind) => {
+                chars.remove(0);
+                kind
+            }
+            None => Type::Pawn,
+        };
+        if chars.len() < 2 {
+            return None;
+        }
+        let to: String = chars[chars.len() - 2..].iter().collect();
+        let to = parse_pos(&to)?;
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in chars[..chars.len() - 2].iter() {
+            match c {
+                'a'..='h' => from_file = Some(*c as i8 - 'a' as i8),
+                '1'..='8' => from_rank = Some(*c as i8 - '1' as i8),
+                _ => return None,
+            }
+        }
+        moves.into_iter().find(|m| {
+            m.to == to
+                && m.promotion == promotion
+                && self.piece_at(m.from).kind() == Some(kind)
+                && from_file.is_none_or(|x| m.from[0] == x)
+                && from_rank.is_none_or(|y| m.from[1] == y)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// counts the positions a number of plies deep.
+    fn perft(board: &mut Board, depth: u32) -> u64 {
+        let moves = board.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut count = 0;
+        for m in moves {
+            board.make_move(m);
+            count += perft(board, depth - 1);
+            board.unmake_move();
+        }
+        count
+    }
+
+    #[test]
+    fn perft_start_position() {
+        let mut board = Board::from_fen(START_FEN).unwrap();
+        assert_eq!(perft(&mut board, 4), 197_281);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        let hash = board.hash;
+        assert_eq!(perft(&mut board, 3), 97_862);
+        assert_eq!(board.hash, hash);
+        assert_eq!(board.to_fen(), fen);
+    }
+
+    #[test]
+    fn polyglot_keys() {
+        // the examples of the Polyglot book format
+        let keys = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                0x463b96181691fc9c,
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+                0x823c9b50fd114196,
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+                0x0756b94461c50fb0,
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+                0x662fafb965db29d4,
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                0x22a48b5a8e47ff78,
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3",
+                0x652a607ca3f242c1,
+            ),
+            (
+                "rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4",
+                0x00fdd303c946bdd9,
+            ),
+            (
+                "rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3",
+                0x3c8123ea7b067637,
+            ),
+            (
+                "rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4",
+                0x5c3f9b829b279560,
+            ),
+        ];
+        for (fen, key) in keys.iter() {
+            assert_eq!(Board::from_fen(fen).unwrap().hash, *key, "{}", fen);
+        }
+        // the key is kept up to date as moves are made
+        let mut board = Board::from_fen(START_FEN).unwrap();
+        for san in ["e4", "d5", "e5", "f5", "Ke2", "Kf7"] {
+            let m = board.parse_san(san).unwrap();
+            board.make_move(m);
+        }
+        assert_eq!(board.hash, 0x00fdd303c946bdd9);
+    }
+
+    #[test]
+    fn pawns_on_the_last_ranks_are_rejected() {
+        assert!(Board::from_fen("4k2P/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/p3K3 b - - 0 1").is_err());
+    }
+}
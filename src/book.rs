@@ -0,0 +1,301 @@
+//! Polyglot opening books.
+//! A book is a file of 16 byte entries sorted by the Zobrist key of the position,
+//! each giving a move and how much weight it has. The board's hash is already a Polyglot key.
+
+use crate::board::{Board, Move, Pos, Type};
+use crate::random::Rng;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// One move for one position.
+#[derive(Copy, Clone)]
+struct Entry {
+    key: u64,
+    /// the move packed the Polyglot way.
+    raw_move: u16,
+    weight: u16,
+}
+
+/// An opening book read from a Polyglot .bin file.
+pub struct Book {
+    /// sorted by key, as they are in the file.
+    entries: Vec<Entry>,
+}
+
+impl Book {
+    /// reads a book from a file.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("could not read book {path}: {e}"))?;
+        if bytes.len() % 16 != 0 {
+            return Err(format!("{path} is not a Polyglot book"));
+        }
+        let entries = bytes
+            .chunks(16)
+            .map(|chunk| Entry {
+                key: u64::from_be_bytes(chunk[0..8].try_into().unwrap()),
+                raw_move: u16::from_be_bytes(chunk[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(chunk[10..12].try_into().unwrap()),
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// lists the legal book moves for the position with their weights, heaviest first.
+    pub fn moves(&self, board: &mut Board) -> Vec<(Move, u16)> {
+        let key = board.hash;
+        let start = self.entries.partition_point(|e| e.key < key);
+        let mut moves: Vec<(Move, u16)> = self.entries[start..]
+            .iter()
+            .take_while(|e| e.key == key)
+            .filter_map(|e| decode_move(board, e.raw_move).map(|m| (m, e.weight)))
+            .collect();
+        moves.sort_by_key(|&(_, weight)| std::cmp::Reverse(weight));
+        moves
+    }
+
+    /// picks a book move for the position, or None once the game has left the book.
+    /// With random set, moves are picked with a chance in proportion to their weight,
+    /// otherwise the heaviest move is always played.
+    pub fn pick(&self, board: &mut Board, random: bool, rng: &mut Rng) -> Option<Move> {
+        let moves = self.moves(board);
+        let total: u64 = moves.iter().map(|&(_, weight)| weight as u64).sum();
+        if !random || total == 0 {
+            return moves.first().map(|&(m, _)| m);
+        }
+        let mut choice = rng.below(total);
+        for (m, weight) in moves {
+            if choice < weight as u64 {
+                return Some(m);
+            }
+            choice -= weight as u64;
+        }
+        None
+    }
+}
+
+/// Counts the moves played in many games to make a book from them.
+pub struct BookBuilder {
+    /// how many times each move was played in each position, and the points it scored,
+    /// with 2 for a win, 1 for a draw and 0 for a loss.
+    moves: HashMap<(u64, u16), (u32, u32)>,
+}
+
+impl BookBuilder {
+    pub fn new() -> Self {
+        Self {
+            moves: HashMap::new(),
+        }
+    }
+
+    /// counts a move played in the position before it is made,
+    /// with the points the side that played it went on to score.
+    pub fn add(&mut self, board: &Board, m: Move, points: u32) {
+        let entry = self
+            .moves
+            .entry((board.hash, encode_move(board, m)))
+            .or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += points;
+    }
+
+    /// writes the book, leaving out moves played fewer than min_count times.
+    /// Moves are weighted by the points they scored, or by how often they were played
+    /// if by_results is false. Moves that only ever lost are left out when weighting by results.
+    /// Returns the number of moves written.
+    pub fn write(&self, path: &str, min_count: u32, by_results: bool) -> Result<usize, String> {
+        let mut entries: Vec<(u64, u16, u32)> = self
+            .moves
+            .iter()
+            .filter(|(_, &(count, _))| count >= min_count)
+            .map(|(&(key, raw_move), &(count, points))| {
+                (key, raw_move, if by_results { points } else { count })
+            })
+            .filter(|&(_, _, weight)| weight > 0)
+            .collect();
+        entries.sort();
+        // weights only have 16 bits, so the moves of busy positions are scaled down together
+        let mut most = HashMap::new();
+        for &(key, _, weight) in entries.iter() {
+            let most = most.entry(key).or_insert(0);
+            *most = weight.max(*most);
+        }
+        let mut bytes = Vec::with_capacity(entries.len() * 16);
+        for &(key, raw_move, weight) in entries.iter() {
+            let weight = match most[&key] {
+                most if most > u16::MAX as u32 => {
+                    (weight as u64 * u16::MAX as u64 / most as u64).max(1) as u16
+                }
+                _ => weight as u16,
+            };
+            bytes.extend_from_slice(&key.to_be_bytes());
+            bytes.extend_from_slice(&raw_move.to_be_bytes());
+            bytes.extend_from_slice(&weight.to_be_bytes());
+            bytes.extend_from_slice(&0u32.to_be_bytes());
+        }
+        std::fs::write(path, bytes).map_err(|e| format!("could not write {path}: {e}"))?;
+        Ok(entries.len())
+    }
+}
+
+/// packs a legal move the Polyglot way.
+fn encode_move(board: &Board, m: Move) -> u16 {
+    let square = |pos: Pos| pos[0] as u16 | (pos[1] as u16) << 3;
+    let mut to = m.to;
+    if board.piece_at(m.from).kind() == Some(Type::King) && (m.to[0] - m.from[0]).abs() == 2 {
+        to[0] = if m.to[0] > m.from[0] { 7 } else { 0 };
+    }
+    let promotion = match m.promotion {
+        None | Some(Type::Pawn) | Some(Type::King) => 0,
+        Some(Type::Knight) => 1,
+        Some(Type::Bishop) => 2,
+        Some(Type::Rook) => 3,
+        Some(Type::Queen) => 4,
+    };
+    square(to) | square(m.from) << 6 | promotion << 12
+}
+
+/// unpacks a Polyglot move and finds the legal move it means.
+/// Polyglot writes castling as the king taking its own rook.
+fn decode_move(board: &mut Board, raw_move: u16) -> Option<Move> {
+    let square = |bits: u16| -> Pos { [(bits & 7) as i8, ((bits >> 3) & 7) as i8] };
+    let from = square(raw_move >> 6);
+    let mut to = square(raw_move);
+    let promotion = match (raw_move >> 12) & 7 {
+        0 => None,
+        1 => Some(Type::Knight),
+        2 => Some(Type::Bishop),
+        3 => Some(Type::Rook),
+        4 => Some(Type::Queen),
+        _ => return None,
+    };
+    let piece = board.piece_at(from);
+    if piece.kind() == Some(Type::King) && board.piece_at(to).color() == piece.color() {
+        to[0] = if to[0] > from[0] { 6 } else { 2 };
+    }
+    board
+        .legal_moves_from(from)
+        .into_iter()
+        .find(|m| m.to == to && m.promotion == promotion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a path in the temp directory for a test's book.
+    fn temp_path(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("book-test-{name}-{}.bin", std::process::id()));
+        path.to_str().unwrap().to_owned()
+    }
+
+    /// writes a book file by hand, with entries of key, move and weight.
+    fn write_book(name: &str, entries: &[(u64, u16, u16)]) -> String {
+        let mut bytes = vec![];
+        for &(key, raw_move, weight) in entries {
+            bytes.extend_from_slice(&key.to_be_bytes());
+            bytes.extend_from_slice(&raw_move.to_be_bytes());
+            bytes.extend_from_slice(&weight.to_be_bytes());
+            bytes.extend_from_slice(&0u32.to_be_bytes());
+        }
+        let path = temp_path(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// packs a move the Polyglot way from squares like "e2".
+    fn raw(from: &str, to: &str) -> u16 {
+        let square = |name: &str| {
+            let bytes = name.as_bytes();
+            (bytes[0] - b'a') as u16 | ((bytes[1] - b'1') as u16) << 3
+        };
+        square(to) | square(from) << 6
+    }
+
+    #[test]
+    fn reads_moves_heaviest_first() {
+        let mut board = Board::new();
+        let path = write_book(
+            "read",
+            &[
+                (board.hash - 1, raw("g1", "f3"), 9),
+                (board.hash, raw("d2", "d4"), 1),
+                (board.hash, raw("e2", "e4"), 3),
+                // not legal here, so it is skipped
+                (board.hash, raw("e2", "e5"), 5),
+            ],
+        );
+        let book = Book::open(&path).unwrap();
+        let moves: Vec<(String, u16)> = book
+            .moves(&mut board)
+            .into_iter()
+            .map(|(m, weight)| (m.to_uci(), weight))
+            .collect();
+        assert_eq!(moves, [("e2e4".to_owned(), 3), ("d2d4".to_owned(), 1)]);
+        let mut rng = Rng::new(1);
+        let best = book.pick(&mut board, false, &mut rng).unwrap();
+        assert_eq!(best.to_uci(), "e2e4");
+        for _ in 0..20 {
+            let m = book.pick(&mut board, true, &mut rng).unwrap().to_uci();
+            assert!(m == "e2e4" || m == "d2d4");
+        }
+        board.make_move(best);
+        assert!(book.pick(&mut board, false, &mut rng).is_none());
+    }
+
+    #[test]
+    fn castling_is_the_king_taking_its_rook() {
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        let path = write_book("castling", &[(board.hash, raw("e1", "h1"), 1)]);
+        let moves = Book::open(&path).unwrap().moves(&mut board);
+        assert_eq!(moves[0].0.to_uci(), "e1g1");
+    }
+
+    #[test]
+    fn needs_whole_entries() {
+        let path = temp_path("broken");
+        std::fs::write(&path, [0u8; 20]).unwrap();
+        assert!(Book::open(&path).is_err());
+        assert!(Book::open("/no/such/book.bin").is_err());
+    }
+
+    #[test]
+    fn builds_books_that_read_back() {
+        let mut builder = BookBuilder::new();
+        let board = Board::new();
+        let e4 = Move::new([4, 1], [4, 3]);
+        let d4 = Move::new([3, 1], [3, 3]);
+        let a4 = Move::new([0, 1], [0, 3]);
+        // e4 won twice and drew once, d4 drew twice, a4 lost its only game
+        for points in [2, 2, 1].iter() {
+            builder.add(&board, e4, *points);
+        }
+        builder.add(&board, d4, 1);
+        builder.add(&board, d4, 1);
+        builder.add(&board, a4, 0);
+        let castling = Board::from_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        builder.add(&castling, Move::new([4, 0], [6, 0]), 2);
+
+        let path = temp_path("built");
+        assert_eq!(builder.write(&path, 1, true).unwrap(), 3);
+        let book = Book::open(&path).unwrap();
+        let weights: Vec<(String, u16)> = book
+            .moves(&mut Board::new())
+            .into_iter()
+            .map(|(m, weight)| (m.to_uci(), weight))
+            .collect();
+        assert_eq!(weights, [("e2e4".to_owned(), 5), ("d2d4".to_owned(), 2)]);
+        let mut castling = castling;
+        assert_eq!(book.moves(&mut castling)[0].0.to_uci(), "e1g1");
+
+        // by how often they were played, and only moves played at least twice
+        assert_eq!(builder.write(&path, 2, false).unwrap(), 2);
+        let weights: Vec<u16> = Book::open(&path)
+            .unwrap()
+            .moves(&mut Board::new())
+            .into_iter()
+            .map(|(_, weight)| weight)
+            .collect();
+        assert_eq!(weights, [3, 2]);
+    }
+}
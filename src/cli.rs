@@ -0,0 +1,597 @@
+//! Command line tools that run without opening a window.
+
+use crate::annotate;
+use crate::board::{Board, Color};
+use crate::book::BookBuilder;
+use crate::dtm::{self, DtmTables, Outcome};
+use crate::eval::Weights;
+use crate::level::LEVELS;
+use crate::nnue::Network;
+use crate::pgn;
+use crate::problem::Solver;
+use crate::puzzle;
+use crate::random::Rng;
+use crate::search::{mate_in, Limits, Search, SearchOptions};
+use crate::sprt::{self, Match, Player, Sprt, Verdict};
+use crate::tune::{self, Tuner};
+use crate::uci;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Positions used by the bench command, picked to cover openings, middle games and endings.
+const BENCH_FENS: [&str; 6] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bq1rk1/pp2bppp/2n1pn2/2pp4/3P4/2PBPN2/PP1N1PPP/R1BQ1RK1 w - - 0 8",
+    "2r3k1/pp3ppp/2n1b3/3p4/3P4/2PB1N2/P4PPP/4R1K1 b - - 3 22",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+];
+
+/// runs the command named by the first argument.
+pub fn run(args: &[String]) -> Result<(), String> {
+    match args[0].as_str() {
+        "--bench" => bench(&args[1..]),
+        "--uci" => uci::run(),
+        "--make-book" => make_book(&args[1..]),
+        "--make-tables" => make_tables(&args[1..]),
+        "--check-tables" => check_tables(&args[1..]),
+        "--match" => play_match(&args[1..]),
+        "--tune" => tune(&args[1..]),
+        "--annotate" => annotate(&args[1..]),
+        "--make-puzzles" => make_puzzles(&args[1..]),
+        "--solve-mate" => solve_mate(&args[1..]),
+        command => Err(format!("unknown command {command}")),
+    }
+}
+
+/// The switches that turn off parts of the search, for comparing it with and without them.
+const SEARCH_SWITCHES: [&str; 6] = [
+    "--no-mvv-lva",
+    "--no-killers",
+    "--no-history",
+    "--no-null-move",
+    "--no-lmr",
+    "--no-check-extensions",
+];
+
+/// reads search switches like --no-null-move from the arguments.
+/// Arguments that are not switches are left for the caller.
+pub fn parse_search_options(args: &[String]) -> (SearchOptions, Vec<String>) {
+    let mut options = SearchOptions::default();
+    let mut rest = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--no-mvv-lva" => options.mvv_lva = false,
+            "--no-killers" => options.killers = false,
+            "--no-history" => options.history = false,
+            "--no-null-move" => options.null_move = false,
+            "--no-lmr" => options.late_move_reductions = false,
+            "--no-check-extensions" => options.check_extensions = false,
+            _ => rest.push(arg.clone()),
+        }
+    }
+    (options, rest)
+}
+
+/// The arguments of a command, split into the plain ones like file names, the flags it
+/// takes on their own and the options followed by a value.
+struct Arguments {
+    plain: Vec<String>,
+    flags: HashSet<String>,
+    values: HashMap<String, String>,
+}
+
+impl Arguments {
+    /// splits the arguments, given the command's flags and options. Any other argument
+    /// starting with -- is an error.
+    fn parse(args: &[String], flags: &[&str], options: &[&str]) -> Result<Self, String> {
+        let mut arguments = Self {
+            plain: vec![],
+            flags: HashSet::new(),
+            values: HashMap::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if flags.contains(&arg.as_str()) {
+                arguments.flags.insert(arg.clone());
+            } else if options.contains(&arg.as_str()) {
+                let value = args.next().ok_or_else(|| format!("{arg} needs a value"))?;
+                arguments.values.insert(arg.clone(), value.clone());
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option {arg}"));
+            } else {
+                arguments.plain.push(arg.clone());
+            }
+        }
+        Ok(arguments)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// the search with the SEARCH_SWITCHES that were given turned off.
+    fn search_options(&self) -> SearchOptions {
+        let switches: Vec<String> = self.flags.iter().cloned().collect();
+        parse_search_options(&switches).0
+    }
+
+    /// the value of an option, or None if it was not given.
+    fn value<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.values.get(name) {
+            Some(value) => match value.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(format!("bad value {value} for {name}")),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// searches a fixed set of positions to a fixed depth and prints how many nodes it took.
+/// Usage: --bench [--depth n] [--no-mvv-lva] [--no-killers] [--no-history] [--no-null-move]
+/// [--no-lmr] [--no-check-extensions]
+fn bench(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &SEARCH_SWITCHES, &["--depth"])?;
+    if !arguments.plain.is_empty() {
+        return Err(format!("--bench does not take {}", arguments.plain[0]));
+    }
+    let options = arguments.search_options();
+    let depth = arguments.value("--depth")?.unwrap_or(6);
+    println!("{options:?}");
+    let mut total_nodes = 0;
+    let start = Instant::now();
+    for fen in BENCH_FENS.iter() {
+        let mut board = Board::from_fen(fen)?;
+        let mut search = Search::new(options);
+        let limits = Limits {
+            depth: Some(depth),
+            ..Limits::default()
+        };
+        let info = search.search(&mut board, limits, &mut |_| ());
+        let best = info.pv.first().map_or("none".to_owned(), |m| m.to_uci());
+        println!(
+            "depth {:>2} {:>10} nodes {:>6} cp  {best:<6} {fen}",
+            info.depth, info.nodes, info.score
+        );
+        total_nodes += info.nodes;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "{total_nodes} nodes in {seconds:.2}s, {:.0} nodes per second",
+        total_nodes as f64 / seconds.max(0.001)
+    );
+    Ok(())
+}
+
+/// builds a Polyglot opening book from games in PGN files.
+/// Usage: --make-book <book.bin> <games.pgn>... [--plies n] [--min-count n] [--by-frequency]
+fn make_book(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &["--by-frequency"], &["--plies", "--min-count"])?;
+    let plies: usize = arguments.value("--plies")?.unwrap_or(20);
+    let min_count = arguments.value("--min-count")?.unwrap_or(1);
+    let by_results = !arguments.flag("--by-frequency");
+    let files = arguments.plain;
+    if files.len() < 2 {
+        return Err("--make-book needs a book file to write and at least one PGN file".to_owned());
+    }
+
+    let mut builder = BookBuilder::new();
+    let mut read = 0;
+    let mut skipped = 0;
+    for file in files[1..].iter() {
+        let text =
+            std::fs::read_to_string(file).map_err(|e| format!("could not read {file}: {e}"))?;
+        for game in pgn::read_games(&text) {
+            let game = match game {
+                Ok(game) => game,
+                Err(e) => {
+                    eprintln!("{file}: skipped {e}");
+                    skipped += 1;
+                    continue;
+                }
+            };
+            read += 1;
+            // points for white, from which black's follow
+            let white_points = match game.tag("Result") {
+                Some("1-0") => 2,
+                Some("0-1") => 0,
+                _ => 1,
+            };
+            let mut board = Board::from_fen(&game.start_fen)?;
+            for &m in game.moves.iter().take(plies) {
+                let points = match board.color {
+                    Color::White => white_points,
+                    Color::Black => 2 - white_points,
+                };
+                builder.add(&board, m, points);
+                board.make_move(m);
+            }
+        }
+    }
+    let written = builder.write(&files[0], min_count, by_results)?;
+    println!(
+        "read {read} games, skipped {skipped}, wrote {written} moves to {}",
+        files[0]
+    );
+    Ok(())
+}
+
+/// solves endings of up to four pieces by retrograde analysis and writes their
+/// depth to mate tables, along with the tables of the endings they turn into.
+/// Usage: --make-tables <directory> <ending>..., with endings named like KRK or KBNK
+fn make_tables(args: &[String]) -> Result<(), String> {
+    let args = Arguments::parse(args, &[], &[])?.plain;
+    if args.len() < 2 {
+        return Err("--make-tables needs a directory and at least one ending".to_owned());
+    }
+    std::fs::create_dir_all(&args[0]).map_err(|e| format!("could not create {}: {e}", args[0]))?;
+    for ending in args[1..].iter() {
+        for line in DtmTables::generate(&args[0], ending)? {
+            println!("{line}");
+        }
+    }
+    Ok(())
+}
+
+/// checks the move generator and the search against the depth to mate tables
+/// on random positions of an ending. The number of legal moves must match the tables'
+/// own move generator, and the search must find every mate within its depth.
+/// Usage: --check-tables <directory> <ending> [--positions n] [--depth n], with the search
+/// switches of --bench
+fn check_tables(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &SEARCH_SWITCHES, &["--positions", "--depth"])?;
+    let options = arguments.search_options();
+    let count: usize = arguments.value("--positions")?.unwrap_or(100);
+    let depth = arguments.value("--depth")?.unwrap_or(5);
+    let args = arguments.plain;
+    if args.len() != 2 {
+        return Err("--check-tables needs a directory and an ending".to_owned());
+    }
+    let tables = DtmTables::open(&args[0])?;
+    let positions = dtm::sample(&tables, &args[1], count, &mut Rng::new(1))?;
+    let mut failures = 0;
+    for (fen, outcome, moves) in positions {
+        let mut board = Board::from_fen(&fen)?;
+        let legal = board.legal_moves().len();
+        if legal != moves {
+            println!("{fen}: {legal} legal moves, the tables have {moves}");
+            failures += 1;
+        }
+        let plies = match outcome {
+            Outcome::Win(plies) | Outcome::Loss(plies) if plies < depth => plies,
+            _ => continue,
+        };
+        let limits = Limits {
+            depth: Some(depth),
+            ..Limits::default()
+        };
+        let info = Search::new(options).search(&mut board, limits, &mut |_| ());
+        let expected = match outcome {
+            Outcome::Win(_) => plies.div_ceil(2) as i32,
+            _ => -(plies as i32 / 2),
+        };
+        if mate_in(info.score) != Some(expected) {
+            println!(
+                "{fen}: {}, the search scored {}",
+                outcome.describe(),
+                info.score
+            );
+            failures += 1;
+        }
+    }
+    println!(
+        "checked {count} positions of {}, {failures} failures",
+        args[1]
+    );
+    Ok(())
+}
+
+/// plays games between two engines and runs the sequential probability ratio test on the results.
+/// An engine is "builtin", optionally followed by the search switches of --bench,
+/// --weights <file>, --nnue <network file> and --level <0-5> in the same argument like
+/// "builtin --no-lmr", or the path to a UCI engine. A level ignores the match limits.
+/// Usage: --match <games.pgn> <openings.epd|openings.pgn> <first> <second> [--games n]
+/// [--concurrency n] [--depth n] [--nodes n] [--movetime ms] [--elo0 x] [--elo1 x] [--alpha x]
+/// [--beta x]
+fn play_match(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(
+        args,
+        &[],
+        &[
+            "--games",
+            "--concurrency",
+            "--depth",
+            "--nodes",
+            "--movetime",
+            "--elo0",
+            "--elo1",
+            "--alpha",
+            "--beta",
+        ],
+    )?;
+    let args = &arguments.plain;
+    if args.len() != 4 {
+        return Err(
+            "--match needs a PGN file to write, an openings file and two engines".to_owned(),
+        );
+    }
+    let games = arguments.value("--games")?.unwrap_or(100);
+    let concurrency = match arguments.value::<usize>("--concurrency")? {
+        Some(concurrency) => concurrency.max(1),
+        None => std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+    };
+    let mut limits = Limits {
+        depth: arguments.value("--depth")?,
+        nodes: arguments.value("--nodes")?,
+        movetime: arguments.value("--movetime")?.map(Duration::from_millis),
+    };
+    let sprt = Sprt {
+        elo0: arguments.value("--elo0")?.unwrap_or(0.),
+        elo1: arguments.value("--elo1")?.unwrap_or(5.),
+        alpha: arguments.value("--alpha")?.unwrap_or(0.05),
+        beta: arguments.value("--beta")?.unwrap_or(0.05),
+    };
+    if limits.depth.is_none() && limits.nodes.is_none() && limits.movetime.is_none() {
+        limits.depth = Some(6);
+    }
+    let player = |spec: &String| {
+        let words: Vec<String> = spec.split_whitespace().map(str::to_owned).collect();
+        match words.first().map(String::as_str) {
+            Some("builtin") => {
+                let (options, rest) = parse_search_options(&words[1..]);
+                let mut player = Player {
+                    name: spec.clone(),
+                    path: None,
+                    options,
+                    weights: Weights::default(),
+                    network: None,
+                    level: None,
+                };
+                for pair in rest.chunks(2) {
+                    match (pair[0].as_str(), pair.get(1)) {
+                        ("--weights", Some(file)) => player.weights = Weights::read(file)?,
+                        ("--nnue", Some(file)) => {
+                            player.network = Some(Arc::new(Network::open(file)?))
+                        }
+                        ("--level", Some(level)) => match level.parse::<usize>() {
+                            Ok(level) if level < LEVELS.len() => player.level = Some(level),
+                            _ => return Err(format!("there is no level {level}")),
+                        },
+                        _ => return Err(format!("unknown search switches in {spec}")),
+                    }
+                }
+                Ok(player)
+            }
+            _ => Ok(Player {
+                name: spec.clone(),
+                path: Some(spec.clone()),
+                options: SearchOptions::default(),
+                weights: Weights::default(),
+                network: None,
+                level: None,
+            }),
+        }
+    };
+    let chess_match = Match {
+        players: [player(&args[2])?, player(&args[3])?],
+        openings: sprt::read_openings(&args[1])?,
+        games,
+        concurrency,
+        limits,
+        sprt,
+    };
+
+    let (lower, upper) = sprt.bounds();
+    println!(
+        "{} against {}, {} openings, H0 elo {} H1 elo {}, LLR bounds ({lower:.2}, {upper:.2})",
+        args[2],
+        args[3],
+        chess_match.openings.len(),
+        sprt.elo0,
+        sprt.elo1
+    );
+    let (tally, verdict) = chess_match.run(&args[0], &mut |tally| {
+        let (elo, margin) = tally.elo();
+        println!(
+            "game {:>4}: W {} D {} L {}, elo {elo:.1} +- {margin:.1}, LLR {:.2}",
+            tally.games(),
+            tally.wins,
+            tally.draws,
+            tally.losses,
+            sprt.llr(tally)
+        );
+    })?;
+    let (elo, margin) = tally.elo();
+    println!(
+        "{} games: W {} D {} L {}",
+        tally.games(),
+        tally.wins,
+        tally.draws,
+        tally.losses
+    );
+    println!("elo difference {elo:.1} +- {margin:.1}");
+    println!(
+        "{}",
+        match verdict {
+            Verdict::Pass => "SPRT: H1 accepted, the first engine is stronger",
+            Verdict::Fail => "SPRT: H0 accepted, the first engine is not stronger",
+            Verdict::Continue => "SPRT: no verdict yet, more games are needed",
+        }
+    );
+    Ok(())
+}
+
+/// tunes the evaluation weights on quiet positions labeled with their game results
+/// and writes them to a file that --weights and the UCI WeightsFile option read.
+/// Usage: --tune <positions.epd> <weights file to write> [--start <weights file>] [--seed n]
+/// [--epochs n] [--rate x] [--batch n]
+fn tune(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(
+        args,
+        &[],
+        &["--start", "--seed", "--epochs", "--rate", "--batch"],
+    )?;
+    let args = &arguments.plain;
+    if args.len() != 2 {
+        return Err("--tune needs a file of positions and a weights file to write".to_owned());
+    }
+    let start = match arguments.value::<String>("--start")? {
+        Some(file) => Weights::read(&file)?,
+        None => Weights::default(),
+    };
+    let tuner = Tuner {
+        epochs: arguments.value("--epochs")?.unwrap_or(50),
+        rate: arguments.value("--rate")?.unwrap_or(0.5),
+        batch: arguments.value("--batch")?.unwrap_or(16384),
+        seed: arguments.value("--seed")?.unwrap_or(1),
+    };
+    let positions = tune::read_positions(&args[0])?;
+    println!("read {} positions", positions.len());
+    let k = tune::fit_k(&positions, &start);
+    let before = tune::error(&positions, &start, k);
+    println!("sigmoid scale {k:.4}, error before {before:.6}");
+    let tuned = tuner.tune(&positions, &start, k, &mut |epoch, error| {
+        println!("epoch {epoch:>3}: error {error:.6}");
+    });
+    let after = tune::error(&positions, &tuned, k);
+    println!("error before {before:.6}, after {after:.6}");
+    tuned.write(&args[1])?;
+    println!("wrote {}", args[1]);
+    Ok(())
+}
+
+/// annotates every game in PGN files, marking inaccuracies, mistakes and blunders with the
+/// engine's better lines, and writes them to one PGN file.
+/// Usage: --annotate <annotated.pgn> <games.pgn>... [--depth n] [--movetime ms] [--threads n]
+fn annotate(args: &[String]) -> Result<(), String> {
+    let (files, mut search, mut limits) = search_arguments(args)?;
+    if files.len() < 2 {
+        return Err("--annotate needs a PGN file to write and at least one PGN file".to_owned());
+    }
+    if limits.depth.is_none() && limits.movetime.is_none() {
+        limits.depth = Some(8);
+    }
+
+    let mut out = String::new();
+    let mut annotated = 0;
+    for file in files[1..].iter() {
+        let text =
+            std::fs::read_to_string(file).map_err(|e| format!("could not read {file}: {e}"))?;
+        for game in pgn::read_games(&text) {
+            let mut game = match game {
+                Ok(game) => game,
+                Err(e) => {
+                    eprintln!("{file}: skipped {e}");
+                    continue;
+                }
+            };
+            let annotation = annotate::annotate(&mut game, &mut search, limits, &mut |_| ())?;
+            annotated += 1;
+            println!(
+                "{} - {}: {}",
+                game.tag("White").unwrap_or("?"),
+                game.tag("Black").unwrap_or("?"),
+                annotation.summary()
+            );
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out += &game.write();
+        }
+    }
+    std::fs::write(&files[0], out).map_err(|e| format!("could not write {}: {e}", files[0]))?;
+    println!("annotated {annotated} games in {}", files[0]);
+    Ok(())
+}
+
+/// reads the files and the --depth, --movetime and --threads options of a command that
+/// searches positions from PGN files.
+fn search_arguments(args: &[String]) -> Result<(Vec<String>, Search, Limits), String> {
+    let arguments = Arguments::parse(args, &[], &["--depth", "--movetime", "--threads"])?;
+    let mut search = Search::default();
+    if let Some(threads) = arguments.value::<usize>("--threads")? {
+        search.threads = threads.max(1);
+    }
+    let limits = Limits {
+        depth: arguments.value("--depth")?,
+        movetime: arguments.value("--movetime")?.map(Duration::from_millis),
+        ..Limits::default()
+    };
+    Ok((arguments.plain, search, limits))
+}
+
+/// writes puzzles found in the blunders and mistakes of annotated PGN games
+/// to a file the puzzle trainer reads.
+/// Usage: --make-puzzles <puzzles.csv> <annotated.pgn>... [--depth n] [--movetime ms]
+/// [--threads n]
+fn make_puzzles(args: &[String]) -> Result<(), String> {
+    let (files, mut search, mut limits) = search_arguments(args)?;
+    if files.len() < 2 {
+        return Err(
+            "--make-puzzles needs a puzzle file to write and at least one PGN file".to_owned(),
+        );
+    }
+    if limits.depth.is_none() && limits.movetime.is_none() {
+        limits.depth = Some(8);
+    }
+
+    let mut lines = vec![puzzle::CSV_HEADER.to_owned()];
+    let mut ids = HashSet::new();
+    for file in files[1..].iter() {
+        let text =
+            std::fs::read_to_string(file).map_err(|e| format!("could not read {file}: {e}"))?;
+        for (i, game) in pgn::read_games(&text).into_iter().enumerate() {
+            let game = match game {
+                Ok(game) => game,
+                Err(e) => {
+                    eprintln!("{file}: skipped {e}");
+                    continue;
+                }
+            };
+            let puzzles = puzzle::find_puzzles(&game, &mut search, limits)?;
+            println!("{file} game {}: {} puzzles", i + 1, puzzles.len());
+            for puzzle in puzzles {
+                if ids.insert(puzzle.id.clone()) {
+                    lines.push(puzzle.to_csv());
+                }
+            }
+        }
+    }
+    lines.push(String::new());
+    std::fs::write(&files[0], lines.join("\n"))
+        .map_err(|e| format!("could not write {}: {e}", files[0]))?;
+    println!("wrote {} puzzles to {}", ids.len(), files[0]);
+    Ok(())
+}
+
+/// proves or refutes a mate in a number of moves, printing every key move and the solution
+/// tree. The FEN can be one argument or the rest of them.
+/// Usage: --solve-mate <fen> --moves n
+fn solve_mate(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[], &["--moves"])?;
+    let moves = arguments
+        .value("--moves")?
+        .ok_or_else(|| "--solve-mate needs --moves n".to_owned())?;
+    if arguments.plain.is_empty() {
+        return Err("--solve-mate needs a FEN".to_owned());
+    }
+    // the fields of the FEN come as separate arguments unless it was quoted
+    let mut board = Board::from_fen(&arguments.plain.join(" "))?;
+    let start = Instant::now();
+    let mut solver = Solver::default();
+    let solution = solver.solve(&mut board, moves);
+    println!("{}", solution.summary(&mut board));
+    for line in solution.tree(&mut board) {
+        println!("{line}");
+    }
+    println!(
+        "{} moves tried in {:.2}s",
+        solver.nodes,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
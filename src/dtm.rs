@@ -0,0 +1,1000 @@
+//! Depth to mate tables for endings of up to four pieces, made by retrograde analysis.
+//! Every position of an ending is solved by working backwards from the checkmates,
+//! so a table gives perfect play and the exact number of moves to mate.
+//!
+//! Each table is a file named like "KRK.dtm" holding one byte per position,
+//! packed by replacing runs of the same byte with a count. The pieces of the stronger side are always white in the file,
+//! and the white king is kept on the a-d files, and on ranks 1-4 without pawns,
+//! by mirroring the board. Castling and the fifty move rule are left out, and so are
+//! en passant captures, which only matter with pawns on both sides.
+
+use crate::board::{Board, Color, Move, Piece, Type};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The most pieces, kings included, a table can have.
+pub const MAX_PIECES: usize = 4;
+/// The first bytes of every table file.
+const MAGIC: &[u8; 4] = b"DTM1";
+/// Marks an empty slot in a list of piece squares.
+const NONE: u8 = 64;
+/// The counter of a position that can not happen, like one with the kings touching.
+const ILLEGAL: u8 = 255;
+/// The stored exit of a position with a capture or promotion that draws.
+const DRAW_EXIT: u8 = 255;
+/// The order pieces are named in.
+const PIECE_ORDER: [Type; 6] = [
+    Type::King,
+    Type::Queen,
+    Type::Rook,
+    Type::Bishop,
+    Type::Knight,
+    Type::Pawn,
+];
+
+/// The result of a position for the side to move, with the plies to mate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win(u32),
+    Draw,
+    Loss(u32),
+}
+
+impl Outcome {
+    /// reads a stored byte: 0 for a draw, otherwise one more than the plies to mate,
+    /// which are odd for a win and even for a loss.
+    fn from_value(value: u8) -> Self {
+        match value {
+            0 => Outcome::Draw,
+            value if value % 2 == 0 => Outcome::Win(value as u32 - 1),
+            value => Outcome::Loss(value as u32 - 1),
+        }
+    }
+
+    /// the byte the outcome is stored as.
+    fn value(self) -> u8 {
+        match self {
+            Outcome::Win(plies) | Outcome::Loss(plies) => plies.min(254) as u8 + 1,
+            Outcome::Draw => 0,
+        }
+    }
+
+    /// the outcome for the side that moved into a position with this outcome.
+    fn before_move(self) -> Self {
+        match self {
+            Outcome::Win(plies) => Outcome::Loss(plies + 1),
+            Outcome::Draw => Outcome::Draw,
+            Outcome::Loss(plies) => Outcome::Win(plies + 1),
+        }
+    }
+
+    /// a number that is larger for better outcomes: quicker wins and slower losses.
+    fn rank(self) -> i64 {
+        match self {
+            Outcome::Win(plies) => 1000 - plies as i64,
+            Outcome::Draw => 0,
+            Outcome::Loss(plies) => plies as i64 - 1000,
+        }
+    }
+
+    /// says the outcome the way players do, like "mate in 12" or "mated in 3".
+    pub fn describe(self) -> String {
+        match self {
+            Outcome::Win(plies) => format!("mate in {}", plies.div_ceil(2)),
+            Outcome::Draw => "draw".to_owned(),
+            Outcome::Loss(plies) => format!("mated in {}", plies / 2),
+        }
+    }
+}
+
+fn file(square: u8) -> i8 {
+    (square % 8) as i8
+}
+
+fn rank(square: u8) -> i8 {
+    (square / 8) as i8
+}
+
+/// the square a step away, or None off the board.
+fn offset(square: u8, files: i8, ranks: i8) -> Option<u8> {
+    let (f, r) = (file(square) + files, rank(square) + ranks);
+    if (0..8).contains(&f) && (0..8).contains(&r) {
+        Some((r * 8 + f) as u8)
+    } else {
+        None
+    }
+}
+
+/// the direction a color's pawns move in.
+fn forward(color: Color) -> i8 {
+    match color {
+        Color::White => 1,
+        Color::Black => -1,
+    }
+}
+
+/// the steps a piece can take, and true if it keeps going in each direction.
+fn steps(kind: Type) -> (&'static [(i8, i8)], bool) {
+    const KNIGHT: [(i8, i8); 8] = [
+        (1, 2),
+        (2, 1),
+        (2, -1),
+        (1, -2),
+        (-1, -2),
+        (-2, -1),
+        (-2, 1),
+        (-1, 2),
+    ];
+    const ROOK: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+    const BISHOP: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+    const KING: [(i8, i8); 8] = [
+        (1, 0),
+        (-1, 0),
+        (0, 1),
+        (0, -1),
+        (1, 1),
+        (1, -1),
+        (-1, 1),
+        (-1, -1),
+    ];
+    match kind {
+        Type::Knight => (&KNIGHT, false),
+        Type::Rook => (&ROOK, true),
+        Type::Bishop => (&BISHOP, true),
+        Type::Queen => (&KING, true),
+        Type::King | Type::Pawn => (&KING, false),
+    }
+}
+
+/// the piece standing on a square.
+fn occupant(squares: &[u8], square: u8) -> Option<usize> {
+    squares.iter().position(|&s| s == square)
+}
+
+/// The pieces of an ending, white's first, with each side's king before its other pieces.
+#[derive(Clone, Debug, PartialEq)]
+struct Material {
+    pieces: Vec<(Color, Type)>,
+    has_pawns: bool,
+}
+
+impl Material {
+    /// reads an ending named like "KQKR", with white's pieces and then black's.
+    fn parse(name: &str) -> Result<Self, String> {
+        let bad = || format!("bad ending {name}, write it like KRK or KBNK");
+        let black_king = name
+            .get(1..)
+            .and_then(|rest| rest.find('K'))
+            .ok_or_else(bad)?
+            + 1;
+        let mut pieces = vec![];
+        for (color, side) in [
+            (Color::White, &name[..black_king]),
+            (Color::Black, &name[black_king..]),
+        ] {
+            if !side.starts_with('K') || side[1..].contains('K') {
+                return Err(bad());
+            }
+            for c in side.chars() {
+                pieces.push((color, Type::from_char(c).ok_or_else(bad)?));
+            }
+        }
+        if pieces.len() > MAX_PIECES {
+            return Err(format!("{name} has more than {MAX_PIECES} pieces"));
+        }
+        Ok(Self::new(pieces))
+    }
+
+    /// puts the pieces in order, white's first and each side's in naming order.
+    fn new(mut pieces: Vec<(Color, Type)>) -> Self {
+        pieces.sort_by_key(|&(color, kind)| {
+            (
+                color == Color::Black,
+                PIECE_ORDER.iter().position(|&k| k == kind),
+            )
+        });
+        let has_pawns = pieces.iter().any(|&(_, kind)| kind == Type::Pawn);
+        Self { pieces, has_pawns }
+    }
+
+    fn name(&self) -> String {
+        self.pieces
+            .iter()
+            .map(|&(_, kind)| kind.to_char())
+            .collect()
+    }
+
+    /// the same ending with the stronger side white, and true if the colors were swapped.
+    fn canonical(&self) -> (Self, bool) {
+        let strength = |color: Color| -> (u32, Vec<usize>) {
+            let kinds: Vec<usize> = self
+                .pieces
+                .iter()
+                .filter(|&&(c, _)| c == color)
+                .map(|&(_, kind)| 5 - PIECE_ORDER.iter().position(|&k| k == kind).unwrap())
+                .collect();
+            (kinds.iter().map(|&k| [1, 3, 3, 5, 9, 0][k]).sum(), kinds)
+        };
+        if strength(Color::White) >= strength(Color::Black) {
+            return (self.clone(), false);
+        }
+        let pieces = self
+            .pieces
+            .iter()
+            .map(|&(color, kind)| (color.opponent(), kind))
+            .collect();
+        (Self::new(pieces), true)
+    }
+
+    /// the number of king squares times the squares of the other pieces,
+    /// for each side to move. All the positions with white to move come first.
+    fn size(&self) -> usize {
+        let kings = if self.has_pawns { 32 } else { 16 };
+        kings * 64usize.pow(self.pieces.len() as u32 - 1) * 2
+    }
+
+    /// where a position is stored, after mirroring the board to put the white king
+    /// on the a-d files and, without pawns, ranks 1-4.
+    fn index(&self, squares: &[u8], white_to_move: bool) -> usize {
+        let king = squares[0];
+        let mut flip = 0;
+        if file(king) > 3 {
+            flip ^= 7;
+        }
+        if !self.has_pawns && rank(king) > 3 {
+            flip ^= 56;
+        }
+        let king = king ^ flip;
+        let mut index = (rank(king) * 4 + file(king)) as usize;
+        for &square in squares[1..self.pieces.len()].iter() {
+            index = index * 64 + (square ^ flip) as usize;
+        }
+        match white_to_move {
+            true => index,
+            false => index + self.size() / 2,
+        }
+    }
+
+    /// the position stored at an index, with true if white is to move.
+    fn position(&self, index: usize) -> ([u8; MAX_PIECES], bool) {
+        let mut squares = [NONE; MAX_PIECES];
+        let white_to_move = index < self.size() / 2;
+        let mut rest = index % (self.size() / 2);
+        for i in (1..self.pieces.len()).rev() {
+            squares[i] = (rest % 64) as u8;
+            rest /= 64;
+        }
+        squares[0] = (rest / 4 * 8 + rest % 4) as u8;
+        (squares, white_to_move)
+    }
+
+    /// the square of a color's king.
+    fn king(&self, squares: &[u8], color: Color) -> u8 {
+        let i = self
+            .pieces
+            .iter()
+            .position(|&p| p == (color, Type::King))
+            .unwrap();
+        squares[i]
+    }
+
+    /// true if a piece on from attacks the square to.
+    fn attacks(&self, piece: usize, to: u8, squares: &[u8]) -> bool {
+        let (color, kind) = self.pieces[piece];
+        let from = squares[piece];
+        let (df, dr) = (file(to) - file(from), rank(to) - rank(from));
+        let line = match kind {
+            Type::Pawn => return dr == forward(color) && df.abs() == 1,
+            Type::Knight => return df.abs() * dr.abs() == 2,
+            Type::King => return df.abs().max(dr.abs()) == 1,
+            Type::Rook => (df == 0) != (dr == 0),
+            Type::Bishop => df != 0 && df.abs() == dr.abs(),
+            Type::Queen => (df == 0) != (dr == 0) || (df != 0 && df.abs() == dr.abs()),
+        };
+        if !line {
+            return false;
+        }
+        // nothing may stand between the squares
+        let mut square = from;
+        loop {
+            square = offset(square, df.signum(), dr.signum()).unwrap();
+            if square == to {
+                return true;
+            }
+            if occupant(squares, square).is_some() {
+                return false;
+            }
+        }
+    }
+
+    /// true if the king of a color is attacked.
+    fn in_check(&self, squares: &[u8], color: Color) -> bool {
+        let king = self.king(squares, color);
+        (0..self.pieces.len()).any(|i| {
+            self.pieces[i].0 != color && squares[i] != NONE && self.attacks(i, king, squares)
+        })
+    }
+
+    /// true if the position could come up in a game.
+    fn is_legal(&self, squares: &[u8], to_move: Color) -> bool {
+        let n = self.pieces.len();
+        for i in 0..n {
+            if squares[..i].contains(&squares[i]) {
+                return false;
+            }
+            if self.pieces[i].1 == Type::Pawn && (rank(squares[i]) == 0 || rank(squares[i]) == 7) {
+                return false;
+            }
+        }
+        !self.in_check(squares, to_move.opponent())
+    }
+
+    /// the squares a piece could move to if the board were otherwise empty of
+    /// friendly pieces, with captures of anything but the king for pawns only diagonally.
+    fn targets(&self, piece: usize, squares: &[u8]) -> Vec<u8> {
+        let (color, kind) = self.pieces[piece];
+        let from = squares[piece];
+        let mut targets = vec![];
+        if kind == Type::Pawn {
+            let dir = forward(color);
+            if let Some(one) = offset(from, 0, dir).filter(|&s| occupant(squares, s).is_none()) {
+                targets.push(one);
+                let start = if color == Color::White { 1 } else { 6 };
+                if let Some(two) = offset(one, 0, dir).filter(|&s| occupant(squares, s).is_none()) {
+                    if rank(from) == start {
+                        targets.push(two);
+                    }
+                }
+            }
+            for side in [-1, 1] {
+                if let Some(to) = offset(from, side, dir) {
+                    if occupant(squares, to).is_some_and(|i| self.pieces[i].0 != color) {
+                        targets.push(to);
+                    }
+                }
+            }
+            return targets;
+        }
+        let (steps, slides) = steps(kind);
+        for &(df, dr) in steps {
+            let mut square = from;
+            while let Some(to) = offset(square, df, dr) {
+                match occupant(squares, to) {
+                    Some(i) if self.pieces[i].0 == color => break,
+                    Some(_) => {
+                        targets.push(to);
+                        break;
+                    }
+                    None => targets.push(to),
+                }
+                if !slides {
+                    break;
+                }
+                square = to;
+            }
+        }
+        targets
+    }
+
+    /// the legal moves of the side to move.
+    fn moves(&self, squares: &[u8], to_move: Color) -> Vec<TableMove> {
+        let mut moves = vec![];
+        for piece in 0..self.pieces.len() {
+            let (color, kind) = self.pieces[piece];
+            if color != to_move || squares[piece] == NONE {
+                continue;
+            }
+            for to in self.targets(piece, squares) {
+                let capture = occupant(squares, to);
+                if capture.is_some_and(|i| self.pieces[i].1 == Type::King) {
+                    continue;
+                }
+                let mut after = [NONE; MAX_PIECES];
+                after[..squares.len()].copy_from_slice(squares);
+                after[piece] = to;
+                if let Some(captured) = capture {
+                    after[captured] = NONE;
+                }
+                if self.in_check(&after, color) {
+                    continue;
+                }
+                if kind == Type::Pawn && (rank(to) == 0 || rank(to) == 7) {
+                    for promotion in [Type::Queen, Type::Rook, Type::Bishop, Type::Knight] {
+                        moves.push(TableMove {
+                            piece,
+                            to,
+                            capture,
+                            promotion: Some(promotion),
+                        });
+                    }
+                } else {
+                    moves.push(TableMove {
+                        piece,
+                        to,
+                        capture,
+                        promotion: None,
+                    });
+                }
+            }
+        }
+        moves
+    }
+
+    /// the legal positions the side that just moved could have come from
+    /// with a move that was not a capture or promotion.
+    fn unmoves(&self, squares: &[u8], mover: Color) -> Vec<[u8; MAX_PIECES]> {
+        let mut positions = vec![];
+        for piece in 0..self.pieces.len() {
+            let (color, kind) = self.pieces[piece];
+            if color != mover {
+                continue;
+            }
+            let to = squares[piece];
+            let mut sources = vec![];
+            if kind == Type::Pawn {
+                let back = -forward(color);
+                let first_rank = if color == Color::White { 0 } else { 7 };
+                if let Some(one) = offset(to, 0, back).filter(|&s| occupant(squares, s).is_none()) {
+                    if rank(one) != first_rank {
+                        sources.push(one);
+                    }
+                    let double_rank = if color == Color::White { 3 } else { 4 };
+                    if rank(to) == double_rank {
+                        let two = offset(one, 0, back).unwrap();
+                        if occupant(squares, two).is_none() {
+                            sources.push(two);
+                        }
+                    }
+                }
+            } else {
+                // other pieces move the same way backwards, but never from an occupied square
+                sources = self
+                    .targets(piece, squares)
+                    .into_iter()
+                    .filter(|&s| occupant(squares, s).is_none())
+                    .collect();
+            }
+            for from in sources {
+                let mut before = [NONE; MAX_PIECES];
+                before[..squares.len()].copy_from_slice(squares);
+                before[piece] = from;
+                if !self.in_check(&before, mover.opponent()) {
+                    positions.push(before);
+                }
+            }
+        }
+        positions
+    }
+}
+
+/// A move in a table's position, by the index of the piece that moves.
+struct TableMove {
+    piece: usize,
+    to: u8,
+    capture: Option<usize>,
+    promotion: Option<Type>,
+}
+
+/// The solved positions of one ending.
+struct Table {
+    material: Material,
+    values: Vec<u8>,
+}
+
+impl Table {
+    /// writes the table. The values are split into pieces that each start with a byte n:
+    /// below 128, n + 1 values follow as they are, otherwise the next value is repeated n - 125 times.
+    fn write(&self, path: &Path) -> Result<(), String> {
+        let name = self.material.name();
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&(self.values.len() as u32).to_le_bytes());
+        let values = &self.values;
+        let run_at = |i: usize| {
+            values[i..]
+                .iter()
+                .take(130)
+                .take_while(|&&v| v == values[i])
+                .count()
+        };
+        let mut i = 0;
+        while i < values.len() {
+            let run = run_at(i);
+            if run >= 3 {
+                bytes.push(run as u8 + 125);
+                bytes.push(values[i]);
+                i += run;
+                continue;
+            }
+            // copy values as they are up to the next run worth packing
+            let start = i;
+            while i < values.len() && i - start < 128 && run_at(i) < 3 {
+                i += 1;
+            }
+            bytes.push((i - start - 1) as u8);
+            bytes.extend_from_slice(&values[start..i]);
+        }
+        std::fs::write(path, bytes).map_err(|e| format!("could not write {}: {e}", path.display()))
+    }
+
+    /// reads a table written by write.
+    fn read(path: &Path) -> Result<Self, String> {
+        let bad = || format!("{} is not a depth to mate table", path.display());
+        let bytes =
+            std::fs::read(path).map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        if bytes.get(..4) != Some(&MAGIC[..]) {
+            return Err(bad());
+        }
+        let name_end = 5 + *bytes.get(4).ok_or_else(bad)? as usize;
+        let name = bytes
+            .get(5..name_end)
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .ok_or_else(bad)?;
+        let material = Material::parse(name)?;
+        let mut count = [0; 4];
+        count.copy_from_slice(bytes.get(name_end..name_end + 4).ok_or_else(bad)?);
+        let count = u32::from_le_bytes(count) as usize;
+        if count != material.size() {
+            return Err(bad());
+        }
+        let mut values = Vec::with_capacity(count);
+        let mut i = name_end + 4;
+        while i < bytes.len() {
+            let n = bytes[i] as usize;
+            if n < 128 {
+                values.extend_from_slice(bytes.get(i + 1..i + 2 + n).ok_or_else(bad)?);
+                i += n + 2;
+            } else {
+                let value = *bytes.get(i + 1).ok_or_else(bad)?;
+                values.extend(std::iter::repeat_n(value, n - 125));
+                i += 2;
+            }
+        }
+        if values.len() != count {
+            return Err(bad());
+        }
+        Ok(Self { material, values })
+    }
+}
+
+/// A set of depth to mate tables.
+pub struct DtmTables {
+    /// by the name of the ending with the stronger side white, like "KRK".
+    tables: HashMap<String, Table>,
+}
+
+impl DtmTables {
+    /// reads every table in a directory. Fails if there are none.
+    pub fn open(directory: &str) -> Result<Self, String> {
+        let entries = std::fs::read_dir(directory)
+            .map_err(|e| format!("could not read table directory {directory}: {e}"))?;
+        let mut tables = HashMap::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == "dtm") {
+                let table = Table::read(&path)?;
+                tables.insert(table.material.name(), table);
+            }
+        }
+        if tables.is_empty() {
+            return Err(format!("no depth to mate tables in {directory}"));
+        }
+        Ok(Self { tables })
+    }
+
+    /// the outcome of a position given as colored pieces on squares numbered from a1 along ranks,
+    /// or None if there is no table for it.
+    fn outcome(&self, pieces: &[(Color, Type, u8)], to_move: Color) -> Option<Outcome> {
+        if pieces.len() == 2 {
+            return Some(Outcome::Draw);
+        }
+        let material = pieces
+            .iter()
+            .map(|&(color, kind, _)| (color, kind))
+            .collect();
+        let (material, swapped) = Material::new(material).canonical();
+        let table = self.tables.get(&material.name())?;
+        // put the pieces in the table's order, swapping the colors if the table has them
+        // the other way around
+        let mut squares = [NONE; MAX_PIECES];
+        let mut used = [false; MAX_PIECES];
+        for &(color, kind, square) in pieces {
+            let (color, square) = match swapped {
+                true => (color.opponent(), square ^ 56),
+                false => (color, square),
+            };
+            let slot =
+                (0..pieces.len()).find(|&i| !used[i] && material.pieces[i] == (color, kind))?;
+            used[slot] = true;
+            squares[slot] = square;
+        }
+        let white_to_move = (to_move == Color::White) != swapped;
+        let index = material.index(&squares, white_to_move);
+        Some(Outcome::from_value(table.values[index]))
+    }
+
+    /// the outcome of the position on a board, or None if there is no table for it.
+    pub fn probe(&self, board: &Board) -> Option<Outcome> {
+        if board.castling.iter().any(|&c| c) {
+            return None;
+        }
+        let mut pieces = vec![];
+        for (y, row) in board.squares.iter().enumerate() {
+            for (x, &piece) in row.iter().enumerate() {
+                if let (Some(color), Some(kind)) = (piece.color(), piece.kind()) {
+                    if pieces.len() == MAX_PIECES {
+                        return None;
+                    }
+                    pieces.push((color, kind, (y * 8 + x) as u8));
+                }
+            }
+        }
+        // the tables do not know about en passant captures
+        if let Some(target) = board.en_passant {
+            let behind = target[1] - forward(board.color);
+            let pawn = Piece::new(board.color, Type::Pawn);
+            if [-1, 1].iter().any(|dx| {
+                let from = [target[0] + dx, behind];
+                (0..8).contains(&from[0]) && board.piece_at(from) == pawn
+            }) {
+                return None;
+            }
+        }
+        self.outcome(&pieces, board.color)
+    }
+
+    /// the move that mates soonest when winning, or holds out longest when losing,
+    /// with the outcome of the position.
+    pub fn best_move(&self, board: &mut Board) -> Option<(Move, Outcome)> {
+        self.probe(board)?;
+        let mut best: Option<(Move, Outcome)> = None;
+        for m in board.legal_moves() {
+            board.make_move(m);
+            let outcome = self.probe(board);
+            board.unmake_move();
+            let outcome = outcome?.before_move();
+            if best.is_none_or(|(_, best)| outcome.rank() > best.rank()) {
+                best = Some((m, outcome));
+            }
+        }
+        best
+    }
+
+    /// solves an ending and writes its table to a directory, along with the tables of every
+    /// ending it can turn into that are not there yet. Returns a line about each table made.
+    pub fn generate(directory: &str, name: &str) -> Result<Vec<String>, String> {
+        let mut tables = Self {
+            tables: HashMap::new(),
+        };
+        let mut report = vec![];
+        let (material, _) = Material::parse(name)?.canonical();
+        tables.make(Path::new(directory), &material, &mut report)?;
+        Ok(report)
+    }
+
+    /// makes the table for an ending once the tables it depends on are loaded or made.
+    fn make(
+        &mut self,
+        directory: &Path,
+        material: &Material,
+        report: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let name = material.name();
+        if self.tables.contains_key(&name) {
+            return Ok(());
+        }
+        let path = directory.join(format!("{name}.dtm"));
+        if path.exists() {
+            let table = Table::read(&path)?;
+            self.tables.insert(name, table);
+            return Ok(());
+        }
+        for child in children(material) {
+            self.make(directory, &child, report)?;
+        }
+        let start = std::time::Instant::now();
+        let table = self.solve(material);
+        table.write(&path)?;
+        let mut longest = 0;
+        let (mut wins, mut draws, mut losses) = (0, 0, 0);
+        for (index, &value) in table.values.iter().enumerate() {
+            let (squares, white_to_move) = material.position(index);
+            let to_move = if white_to_move {
+                Color::White
+            } else {
+                Color::Black
+            };
+            if !material.is_legal(&squares, to_move) {
+                continue;
+            }
+            match Outcome::from_value(value) {
+                Outcome::Win(plies) => {
+                    wins += 1;
+                    longest = longest.max(plies);
+                }
+                Outcome::Draw => draws += 1,
+                Outcome::Loss(_) => losses += 1,
+            }
+        }
+        report.push(format!(
+            "{name}: {wins} wins, {draws} draws, {losses} losses for the side to move, \
+             longest mate in {} moves, {:.1}s",
+            longest.div_ceil(2),
+            start.elapsed().as_secs_f64()
+        ));
+        self.tables.insert(name, table);
+        Ok(())
+    }
+
+    /// works out every position of an ending by retrograde analysis.
+    /// Positions lost in n plies make the positions before them won in n + 1,
+    /// and once every move from a position is known to lose, it is lost too.
+    /// Captures and promotions leave the table, so they are looked up in smaller tables.
+    fn solve(&self, material: &Material) -> Table {
+        let size = material.size();
+        let mut values = vec![0u8; size];
+        // the moves that stay in the table and are not yet known to lose for the mover
+        let mut counters = vec![0u8; size];
+        // the best capture or promotion, stored like values, with DRAW_EXIT for a draw
+        let mut exits = vec![0u8; size];
+        // the positions that may get their value at each ply
+        let mut pending: Vec<Vec<usize>> = vec![vec![]; 255];
+        let last_ply = |plies: u32| (plies as usize).min(254);
+
+        for index in 0..size {
+            let (squares, white_to_move) = material.position(index);
+            let to_move = if white_to_move {
+                Color::White
+            } else {
+                Color::Black
+            };
+            if !material.is_legal(&squares, to_move) {
+                counters[index] = ILLEGAL;
+                continue;
+            }
+            let mut internal = 0;
+            let mut exit: Option<Outcome> = None;
+            for m in material.moves(&squares, to_move) {
+                if m.capture.is_none() && m.promotion.is_none() {
+                    internal += 1;
+                    continue;
+                }
+                let pieces: Vec<(Color, Type, u8)> = (0..material.pieces.len())
+                    .filter(|&i| Some(i) != m.capture)
+                    .map(|i| {
+                        let (color, kind) = material.pieces[i];
+                        match i == m.piece {
+                            true => (color, m.promotion.unwrap_or(kind), m.to),
+                            false => (color, kind, squares[i]),
+                        }
+                    })
+                    .collect();
+                // the smaller tables were all made first
+                let outcome = self
+                    .outcome(&pieces, to_move.opponent())
+                    .unwrap_or(Outcome::Draw)
+                    .before_move();
+                if exit.is_none_or(|exit| outcome.rank() > exit.rank()) {
+                    exit = Some(outcome);
+                }
+            }
+            counters[index] = internal;
+            exits[index] = match exit {
+                Some(Outcome::Draw) => DRAW_EXIT,
+                Some(outcome) => outcome.value(),
+                None => 0,
+            };
+            match exit {
+                None if internal == 0 && material.in_check(&squares, to_move) => {
+                    pending[0].push(index)
+                }
+                Some(Outcome::Win(plies)) => pending[last_ply(plies)].push(index),
+                Some(Outcome::Loss(plies)) if internal == 0 => pending[last_ply(plies)].push(index),
+                _ => (),
+            }
+        }
+
+        for ply in 0..pending.len() {
+            let positions = std::mem::take(&mut pending[ply]);
+            for index in positions {
+                if values[index] != 0 {
+                    continue;
+                }
+                values[index] = ply as u8 + 1;
+                if ply + 1 >= pending.len() {
+                    continue;
+                }
+                let (squares, white_to_move) = material.position(index);
+                let mover = if white_to_move {
+                    Color::Black
+                } else {
+                    Color::White
+                };
+                for before in material.unmoves(&squares[..material.pieces.len()], mover) {
+                    let previous = material.index(&before, !white_to_move);
+                    if values[previous] != 0 || counters[previous] == ILLEGAL {
+                        continue;
+                    }
+                    if ply % 2 == 0 {
+                        // this position is lost, so moving into it wins
+                        pending[ply + 1].push(previous);
+                        continue;
+                    }
+                    counters[previous] -= 1;
+                    if counters[previous] > 0 {
+                        continue;
+                    }
+                    // every move that stays in the table loses, so the best exit decides
+                    match exits[previous] {
+                        0 => pending[ply + 1].push(previous),
+                        DRAW_EXIT => (),
+                        exit => {
+                            if let Outcome::Loss(plies) = Outcome::from_value(exit) {
+                                pending[last_ply(plies).max(ply + 1)].push(previous);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // positions that can not happen are never looked up, so they take the value
+        // before them to make longer runs in the file
+        for index in 1..size {
+            if counters[index] == ILLEGAL {
+                values[index] = values[index - 1];
+            }
+        }
+        Table {
+            material: material.clone(),
+            values,
+        }
+    }
+}
+
+/// the endings a capture or promotion can turn an ending into, with at least three pieces.
+fn children(material: &Material) -> Vec<Material> {
+    let mut children = vec![];
+    let pieces = &material.pieces;
+    for (i, &(color, kind)) in pieces.iter().enumerate() {
+        if kind != Type::King {
+            let mut child = pieces.clone();
+            child.remove(i);
+            children.push(child);
+        }
+        if kind == Type::Pawn {
+            for promotion in [Type::Queen, Type::Rook, Type::Bishop, Type::Knight] {
+                let mut child = pieces.clone();
+                child[i] = (color, promotion);
+                // a promotion can also capture
+                for (j, &(_, captured)) in pieces.iter().enumerate() {
+                    if j != i && captured != Type::King {
+                        let mut child = child.clone();
+                        child.remove(j);
+                        children.push(child);
+                    }
+                }
+                children.push(child);
+            }
+        }
+    }
+    children
+        .into_iter()
+        .filter(|child| child.len() > 2)
+        .map(|child| Material::new(child).canonical().0)
+        .collect()
+}
+
+/// writes a table position as FEN, for checking the tables against the rest of the engine.
+fn fen(material: &Material, squares: &[u8], white_to_move: bool) -> String {
+    let mut board = [[Piece::Empty; 8]; 8];
+    for (&(color, kind), &square) in material.pieces.iter().zip(squares) {
+        board[rank(square) as usize][file(square) as usize] = Piece::new(color, kind);
+    }
+    let mut rows = vec![];
+    for row in board.iter().rev() {
+        let mut text = String::new();
+        let mut empty = 0;
+        for &piece in row.iter() {
+            match (piece.color(), piece.kind()) {
+                (Some(color), Some(kind)) => {
+                    if empty > 0 {
+                        text += &empty.to_string();
+                        empty = 0;
+                    }
+                    let c = kind.to_char();
+                    text.push(if color == Color::White {
+                        c
+                    } else {
+                        c.to_ascii_lowercase()
+                    });
+                }
+                _ => empty += 1,
+            }
+        }
+        if empty > 0 {
+            text += &empty.to_string();
+        }
+        rows.push(text);
+    }
+    let color = if white_to_move { "w" } else { "b" };
+    format!("{} {color} - - 0 1", rows.join("/"))
+}
+
+/// picks legal positions of an ending at random, using the index of the table they are in.
+/// Returns the FEN of each with its outcome and the number of legal moves the table's
+/// own move generator finds.
+pub fn sample(
+    tables: &DtmTables,
+    name: &str,
+    count: usize,
+    rng: &mut crate::random::Rng,
+) -> Result<Vec<(String, Outcome, usize)>, String> {
+    let (material, _) = Material::parse(name)?.canonical();
+    let table = tables
+        .tables
+        .get(&material.name())
+        .ok_or_else(|| format!("no table for {name}"))?;
+    let mut positions = vec![];
+    while positions.len() < count {
+        let index = rng.below(table.values.len() as u64) as usize;
+        let (squares, white_to_move) = material.position(index);
+        let to_move = if white_to_move {
+            Color::White
+        } else {
+            Color::Black
+        };
+        if !material.is_legal(&squares, to_move) {
+            continue;
+        }
+        let moves = material.moves(&squares, to_move).len();
+        positions.push((
+            fen(&material, &squares, white_to_move),
+            Outcome::from_value(table.values[index]),
+            moves,
+        ));
+    }
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_mates() {
+        let directory = std::env::temp_dir().join(format!("dtm-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let directory = directory.to_str().unwrap();
+        // the longest mates of the endings, known from other tablebases
+        let longest = [("KQK", 10), ("KRK", 16), ("KPK", 28), ("KBNK", 33)];
+        let mut report = vec![];
+        for (name, _) in longest.iter() {
+            report.extend(DtmTables::generate(directory, name).unwrap());
+        }
+        for (name, moves) in longest.iter() {
+            let line = report
+                .iter()
+                .find(|line| line.starts_with(&format!("{name}:")))
+                .unwrap();
+            assert!(
+                line.contains(&format!("longest mate in {} moves", moves)),
+                "{}",
+                line
+            );
+        }
+
+        let tables = DtmTables::open(directory).unwrap();
+        let mut board = Board::from_fen("8/8/8/4k3/8/8/8/3QK3 w - - 0 1").unwrap();
+        let plies = match tables.probe(&board) {
+            Some(Outcome::Win(plies)) => plies,
+            outcome => panic!("{:?}", outcome.map(Outcome::describe)),
+        };
+        // the best move keeps the win and brings the mate closer
+        let (m, outcome) = tables.best_move(&mut board).unwrap();
+        assert!(matches!(outcome, Outcome::Win(after) if after == plies));
+        board.make_move(m);
+        assert!(matches!(tables.probe(&board), Some(Outcome::Loss(after)) if after == plies - 1));
+        let _ = std::fs::remove_dir_all(directory);
+    }
+}
@@ -0,0 +1,112 @@
+//! Opening names and their codes in the Encyclopaedia of Chess Openings.
+//! The table in eco.tsv gives the moves of each opening, and openings are looked up by the
+//! position those moves reach, so a game that gets there in another order is still named.
+//! It has the columns of the public domain tables of the Lichess chess-openings project
+//! (https://github.com/lichess-org/chess-openings). The bundled one is a selection of the
+//! main lines; the full table of about 3,400 openings is its files a.tsv to e.tsv joined
+//! together, which can replace it as they are.
+
+use crate::board::{Board, Move, START_FEN};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// The bundled table, a line for each opening with its code, name and moves in PGN.
+const TABLE: &str = include_str!("eco.tsv");
+
+/// One opening in the table.
+pub struct Opening {
+    pub eco: String,
+    pub name: String,
+}
+
+/// the openings keyed by the hash of the position they reach.
+fn openings() -> &'static HashMap<u64, Opening> {
+    static OPENINGS: OnceLock<HashMap<u64, Opening>> = OnceLock::new();
+    OPENINGS.get_or_init(|| {
+        let mut openings = HashMap::new();
+        // each joined file starts with a line naming the columns
+        for line in TABLE.lines().filter(|line| !line.starts_with("eco\t")) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let mut board = Board::from_fen(START_FEN).unwrap();
+            // move numbers end with a dot
+            for san in fields[2].split_whitespace().filter(|s| !s.ends_with('.')) {
+                let m = board
+                    .parse_san(san)
+                    .unwrap_or_else(|| panic!("illegal move {} in the opening table", san));
+                board.make_move(m);
+            }
+            // an opening reached by an earlier line keeps that line's name
+            openings.entry(board.hash).or_insert(Opening {
+                eco: fields[0].to_owned(),
+                name: fields[1].to_owned(),
+            });
+        }
+        openings
+    })
+}
+
+/// names the opening of a game: the last position in it that is in the table.
+pub fn classify(start_fen: &str, moves: &[Move]) -> Option<&'static Opening> {
+    let openings = openings();
+    let mut board = Board::from_fen(start_fen).ok()?;
+    let mut opening = None;
+    for &m in moves {
+        board.make_move(m);
+        opening = openings.get(&board.hash).or(opening);
+    }
+    opening
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the code and name of the opening of the moves, given in SAN.
+    fn name(moves: &str) -> Option<(String, String)> {
+        let mut board = Board::new();
+        let moves: Vec<Move> = moves
+            .split_whitespace()
+            .map(|san| {
+                let m = board.parse_san(san).unwrap();
+                board.make_move(m);
+                m
+            })
+            .collect();
+        classify(START_FEN, &moves).map(|opening| (opening.eco.clone(), opening.name.clone()))
+    }
+
+    #[test]
+    fn reads_the_whole_table() {
+        assert!(openings().len() > 200);
+    }
+
+    #[test]
+    fn names_openings() {
+        assert_eq!(
+            name("e4 e5 Nf3 Nc6 Bc4 Bc5"),
+            Some(("C50".to_owned(), "Italian Game: Giuoco Piano".to_owned()))
+        );
+        // the last named position is kept once the game leaves the table
+        assert_eq!(
+            name("e4 e5 Nf3 Nc6 Bc4 Bc5 d3 Nf6 h3 h6").unwrap().1,
+            "Italian Game: Giuoco Pianissimo"
+        );
+        assert_eq!(name(""), None);
+    }
+
+    #[test]
+    fn names_transpositions() {
+        assert_eq!(
+            name("c4 e6 d4 d5"),
+            Some(("D30".to_owned(), "Queen's Gambit Declined".to_owned()))
+        );
+    }
+
+    #[test]
+    fn other_starts_are_not_openings() {
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        let m = board.parse_san("e4").unwrap();
+        assert!(classify("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", &[m]).is_none());
+        assert!(classify("not a position", &[]).is_none());
+    }
+}
@@ -0,0 +1,259 @@
+//! Endgame practice: well known endings set up on the board to be played out against the
+//! engine, each with a goal that is checked as the moves are played.
+
+use crate::board::{Board, Color, Piece, Type};
+
+/// What has to be done in a practice position.
+#[derive(Copy, Clone)]
+pub enum Goal {
+    /// mate within this many moves.
+    Mate(usize),
+    /// keep the draw for this many moves, or until the game is drawn.
+    Draw(usize),
+    /// make a new queen from a pawn.
+    Promote,
+}
+
+impl Goal {
+    /// says what has to be done.
+    pub fn describe(self) -> String {
+        match self {
+            Goal::Mate(moves) => format!("Mate within {moves} moves"),
+            Goal::Draw(moves) => format!("Hold the draw for {moves} moves"),
+            Goal::Promote => "Promote the pawn".to_owned(),
+        }
+    }
+}
+
+/// A practice position. The side to move in it is the one practicing.
+pub struct Drill {
+    pub name: &'static str,
+    pub fen: &'static str,
+    pub goal: Goal,
+}
+
+/// The practice positions, from the easiest.
+pub const DRILLS: [Drill; 7] = [
+    Drill {
+        name: "Queen mate",
+        fen: "8/8/8/4k3/8/8/8/3QK3 w - - 0 1",
+        goal: Goal::Mate(10),
+    },
+    Drill {
+        name: "Rook mate",
+        fen: "8/8/8/4k3/8/8/8/R3K3 w - - 0 1",
+        goal: Goal::Mate(16),
+    },
+    Drill {
+        name: "King and pawn: the opposition",
+        fen: "8/8/4k3/8/4K3/8/4P3/8 w - - 0 1",
+        goal: Goal::Promote,
+    },
+    Drill {
+        name: "King and pawn: holding the draw",
+        fen: "4k3/8/8/4K3/4P3/8/8/8 b - - 0 1",
+        goal: Goal::Draw(20),
+    },
+    Drill {
+        name: "Bishop and knight mate",
+        fen: "8/8/8/4k3/8/8/8/4KBN1 w - - 0 1",
+        goal: Goal::Mate(40),
+    },
+    Drill {
+        name: "Lucena position",
+        fen: "1K6/1P2k3/8/8/8/8/2r5/3R4 w - - 0 1",
+        goal: Goal::Promote,
+    },
+    Drill {
+        name: "Philidor position",
+        fen: "4k3/1R6/7r/3KP3/8/8/8/8 b - - 0 1",
+        goal: Goal::Draw(25),
+    },
+];
+
+/// Goes through the practice positions, checking each goal as its moves are played.
+pub struct Practice {
+    /// the index in DRILLS of the position being played.
+    index: usize,
+    /// the side practicing.
+    pub color: Color,
+    /// how many queens each side had at the start, by Color::index.
+    queens: [usize; 2],
+    /// true once the goal has been reached or missed.
+    pub finished: bool,
+    pub passed: u32,
+    pub failed: u32,
+}
+
+impl Practice {
+    /// starts with the first position.
+    pub fn new() -> Self {
+        Self {
+            index: 0,
+            color: Color::White,
+            queens: [0; 2],
+            finished: false,
+            passed: 0,
+            failed: 0,
+        }
+    }
+
+    /// the position being played.
+    pub fn drill(&self) -> &'static Drill {
+        &DRILLS[self.index]
+    }
+
+    /// moves on to the next position, going back to the first after the last.
+    pub fn next(&mut self) {
+        self.index = (self.index + 1) % DRILLS.len();
+    }
+
+    /// sets the position being played up again, played by the side to move on the board.
+    pub fn start(&mut self, board: &Board) {
+        self.color = board.color;
+        for color in [Color::White, Color::Black] {
+            self.queens[color.index()] = count(board, Piece::new(color, Type::Queen));
+        }
+        self.finished = false;
+    }
+
+    /// checks the goal after a move, with the number of plies played since the start.
+    /// Returns what became of it once it has been reached or missed.
+    pub fn check(&mut self, board: &mut Board, plies: usize) -> Option<Result<String, String>> {
+        if self.finished {
+            return None;
+        }
+        // the practicing side moved first, and a ply later when it is their move again
+        let moves = plies.div_ceil(2);
+        let mated = board.is_checkmate();
+        let drawn = board.is_stalemate() || board.is_draw();
+        let result = match self.drill().goal {
+            Goal::Mate(limit) => {
+                if mated && board.color != self.color {
+                    Some(Ok(format!("Mate in {moves} moves")))
+                } else if drawn {
+                    Some(Err("The game was drawn".to_owned()))
+                } else if moves >= limit && board.color != self.color {
+                    Some(Err(format!("No mate within {limit} moves")))
+                } else {
+                    None
+                }
+            }
+            Goal::Draw(limit) => {
+                if mated {
+                    Some(Err("You were mated".to_owned()))
+                } else if self.queened(board, self.color.opponent()) {
+                    Some(Err("The pawn queened".to_owned()))
+                } else if drawn {
+                    Some(Ok("The game was drawn".to_owned()))
+                } else if moves >= limit && board.color != self.color {
+                    Some(Ok(format!("Held for {limit} moves")))
+                } else {
+                    None
+                }
+            }
+            Goal::Promote => {
+                if self.queened(board, self.color) {
+                    Some(Ok(format!("Promoted in {moves} moves")))
+                } else if drawn || count(board, Piece::new(self.color, Type::Pawn)) == 0 {
+                    Some(Err("The win slipped away".to_owned()))
+                } else {
+                    None
+                }
+            }
+        };
+        match result {
+            Some(Ok(_)) => self.passed += 1,
+            Some(Err(_)) => self.failed += 1,
+            None => return None,
+        }
+        self.finished = true;
+        result
+    }
+
+    /// true if a side has more queens than it started with.
+    fn queened(&self, board: &Board, color: Color) -> bool {
+        count(board, Piece::new(color, Type::Queen)) > self.queens[color.index()]
+    }
+}
+
+/// how many of a piece are on the board.
+fn count(board: &Board, piece: Piece) -> usize {
+    board
+        .squares
+        .iter()
+        .flatten()
+        .filter(|&&square| square == piece)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a practice of the drill at the index, started on the position.
+    fn start(index: usize, fen: &str) -> (Practice, Board) {
+        let mut practice = Practice::new();
+        for _ in 0..index {
+            practice.next();
+        }
+        let board = Board::from_fen(fen).unwrap();
+        practice.start(&board);
+        (practice, board)
+    }
+
+    /// plays moves given in UCI notation.
+    fn play(board: &mut Board, moves: &[&str]) {
+        for m in moves {
+            let m = board.parse_uci_move(m).unwrap();
+            board.make_move(m);
+        }
+    }
+
+    #[test]
+    fn drills_can_be_played() {
+        for drill in DRILLS.iter() {
+            let mut board = Board::from_fen(drill.fen).unwrap();
+            assert!(!board.legal_moves().is_empty(), "{}", drill.name);
+        }
+    }
+
+    #[test]
+    fn mates_in_time() {
+        let (mut practice, mut board) = start(0, "7k/8/6K1/8/8/8/8/3Q4 w - - 0 1");
+        play(&mut board, &["d1d8"]);
+        assert_eq!(
+            practice.check(&mut board, 1),
+            Some(Ok("Mate in 1 moves".to_owned()))
+        );
+        assert_eq!(practice.check(&mut board, 1), None);
+        assert_eq!(practice.passed, 1);
+
+        let (mut practice, mut board) = start(0, DRILLS[0].fen);
+        play(&mut board, &["d1d2"]);
+        assert_eq!(practice.check(&mut board, 1), None);
+        assert_eq!(
+            practice.check(&mut board, 19),
+            Some(Err("No mate within 10 moves".to_owned()))
+        );
+    }
+
+    #[test]
+    fn promotes_and_holds() {
+        let (mut practice, mut board) = start(2, "8/4P3/8/8/k7/8/8/4K3 w - - 0 1");
+        play(&mut board, &["e7e8q"]);
+        assert_eq!(
+            practice.check(&mut board, 1),
+            Some(Ok("Promoted in 1 moves".to_owned()))
+        );
+
+        let (mut practice, mut board) = start(3, "8/4P2k/4K3/8/8/8/8/8 b - - 0 1");
+        assert_eq!(practice.color, Color::Black);
+        play(&mut board, &["h7g7", "e7e8q"]);
+        assert_eq!(
+            practice.check(&mut board, 2),
+            Some(Err("The pawn queened".to_owned()))
+        );
+        assert_eq!(practice.failed, 1);
+    }
+}
@@ -0,0 +1,425 @@
+//! The hand written evaluation of a chess position.
+//! Every score is in centipawns, where 100 is worth about one pawn.
+
+use crate::board::{point_out_of_bounds, Board, Color, Piece, Type};
+use crate::BOARD_SIZE;
+
+/// Middle game and end game values of each type of piece, indexed by Type::index.
+const PIECE_VALUES: [[i32; 6]; 2] = [[82, 477, 337, 365, 1025, 0], [94, 512, 281, 297, 936, 0]];
+
+/// How much each type of piece counts towards the game being in the middle game.
+const PHASE_WEIGHTS: [i32; 6] = [0, 2, 1, 1, 4, 0];
+/// The phase of the starting position.
+const MAX_PHASE: i32 = 24;
+
+/// Bonuses for each square, written from white's point of view with rank 8 at the top.
+/// Indexed by Type::index.
+#[rustfmt::skip]
+const PIECE_SQUARES: [[i32; 64]; 6] = [
+    // pawn
+    [
+         0,  0,  0,  0,  0,  0,  0,  0,
+        50, 50, 50, 50, 50, 50, 50, 50,
+        10, 10, 20, 30, 30, 20, 10, 10,
+         5,  5, 10, 25, 25, 10,  5,  5,
+         0,  0,  0, 20, 20,  0,  0,  0,
+         5, -5,-10,  0,  0,-10, -5,  5,
+         5, 10, 10,-20,-20, 10, 10,  5,
+         0,  0,  0,  0,  0,  0,  0,  0,
+    ],
+    // rook
+    [
+         0,  0,  0,  0,  0,  0,  0,  0,
+         5, 10, 10, 10, 10, 10, 10,  5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+         0,  0,  0,  5,  5,  0,  0,  0,
+    ],
+    // knight
+    [
+       -50,-40,-30,-30,-30,-30,-40,-50,
+       -40,-20,  0,  0,  0,  0,-20,-40,
+       -30,  0, 10, 15, 15, 10,  0,-30,
+       -30,  5, 15, 20, 20, 15,  5,-30,
+       -30,  0, 15, 20, 20, 15,  0,-30,
+       -30,  5, 10, 15, 15, 10,  5,-30,
+       -40,-20,  0,  5,  5,  0,-20,-40,
+       -50,-40,-30,-30,-30,-30,-40,-50,
+    ],
+    // bishop
+    [
+       -20,-10,-10,-10,-10,-10,-10,-20,
+       -10,  0,  0,  0,  0,  0,  0,-10,
+       -10,  0,  5, 10, 10,  5,  0,-10,
+       -10,  5,  5, 10, 10,  5,  5,-10,
+       -10,  0, 10, 10, 10, 10,  0,-10,
+       -10, 10, 10, 10, 10, 10, 10,-10,
+       -10,  5,  0,  0,  0,  0,  5,-10,
+       -20,-10,-10,-10,-10,-10,-10,-20,
+    ],
+    // queen
+    [
+       -20,-10,-10, -5, -5,-10,-10,-20,
+       -10,  0,  0,  0,  0,  0,  0,-10,
+       -10,  0,  5,  5,  5,  5,  0,-10,
+        -5,  0,  5,  5,  5,  5,  0, -5,
+         0,  0,  5,  5,  5,  5,  0, -5,
+       -10,  5,  5,  5,  5,  5,  0,-10,
+       -10,  0,  5,  0,  0,  0,  0,-10,
+       -20,-10,-10, -5, -5,-10,-10,-20,
+    ],
+    // king
+    [
+       -30,-40,-40,-50,-50,-40,-40,-30,
+       -30,-40,-40,-50,-50,-40,-40,-30,
+       -30,-40,-40,-50,-50,-40,-40,-30,
+       -30,-40,-40,-50,-50,-40,-40,-30,
+       -20,-30,-30,-40,-40,-30,-30,-20,
+       -10,-20,-20,-20,-20,-20,-20,-10,
+        20, 20,  0,  0,  0,  0, 20, 20,
+        20, 30, 10,  0,  0, 10, 30, 20,
+    ],
+];
+
+/// The king wants to walk to the middle once the queens are gone.
+#[rustfmt::skip]
+const KING_END_GAME_SQUARES: [i32; 64] = [
+   -50,-40,-30,-20,-20,-30,-40,-50,
+   -30,-20,-10,  0,  0,-10,-20,-30,
+   -30,-10, 20, 30, 30, 20,-10,-30,
+   -30,-10, 30, 40, 40, 30,-10,-30,
+   -30,-10, 30, 40, 40, 30,-10,-30,
+   -30,-10, 20, 30, 30, 20,-10,-30,
+   -30,-30,  0,  0,  0,  0,-30,-30,
+   -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+/// The numbers the evaluation is made of.
+/// Every pair holds a middle game value followed by an end game value.
+#[derive(Clone)]
+pub struct Weights {
+    pub piece_values: [[i32; 6]; 2],
+    pub piece_squares: [[[i32; 64]; 6]; 2],
+    pub bishop_pair: [i32; 2],
+    pub doubled_pawn: [i32; 2],
+    pub isolated_pawn: [i32; 2],
+    /// indexed by how many ranks the pawn has moved forward.
+    pub passed_pawn: [[i32; BOARD_SIZE]; 2],
+    pub rook_open_file: [i32; 2],
+    pub rook_half_open_file: [i32; 2],
+    /// per square a piece can move to, indexed by Type::index.
+    pub mobility: [[i32; 6]; 2],
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        let mut end_game_squares = PIECE_SQUARES;
+        end_game_squares[Type::King.index()] = KING_END_GAME_SQUARES;
+        Self {
+            piece_values: PIECE_VALUES,
+            piece_squares: [PIECE_SQUARES, end_game_squares],
+            bishop_pair: [30, 50],
+            doubled_pawn: [-10, -20],
+            isolated_pawn: [-10, -15],
+            passed_pawn: [
+                [0, 5, 10, 15, 25, 40, 60, 0],
+                [0, 10, 20, 35, 60, 100, 150, 0],
+            ],
+            rook_open_file: [25, 10],
+            rook_half_open_file: [10, 5],
+            mobility: [[0, 2, 4, 3, 1, 0], [0, 4, 4, 3, 2, 0]],
+        }
+    }
+}
+
+/// The groups of weights in the order they are listed, with how many numbers each holds.
+const GROUPS: [(&str, usize); 9] = [
+    ("piece_values", 2 * 6),
+    ("piece_squares", 2 * 6 * 64),
+    ("bishop_pair", 2),
+    ("doubled_pawn", 2),
+    ("isolated_pawn", 2),
+    ("passed_pawn", 2 * BOARD_SIZE),
+    ("rook_open_file", 2),
+    ("rook_half_open_file", 2),
+    ("mobility", 2 * 6),
+];
+
+impl Weights {
+    /// every weight, group by group, with the middle game values of a group first.
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut i32> {
+        self.piece_values
+            .iter_mut()
+            .flatten()
+            .chain(self.piece_squares.iter_mut().flatten().flatten())
+            .chain(self.bishop_pair.iter_mut())
+            .chain(self.doubled_pawn.iter_mut())
+            .chain(self.isolated_pawn.iter_mut())
+            .chain(self.passed_pawn.iter_mut().flatten())
+            .chain(self.rook_open_file.iter_mut())
+            .chain(self.rook_half_open_file.iter_mut())
+            .chain(self.mobility.iter_mut().flatten())
+    }
+
+    /// all the weights in one list, in the order of the weights file.
+    pub fn values(&self) -> Vec<i32> {
+        self.clone().iter_mut().map(|value| *value).collect()
+    }
+
+    /// sets all the weights from a list made by values.
+    pub fn set_values(&mut self, values: &[i32]) {
+        for (weight, value) in self.iter_mut().zip(values.iter()) {
+            *weight = *value;
+        }
+    }
+
+    /// adds to one weight, counted the same way as in values.
+    pub fn nudge(&mut self, index: usize, amount: i32) {
+        if let Some(weight) = self.iter_mut().nth(index) {
+            *weight += amount;
+        }
+    }
+
+    /// reads weights written by write. Every group has to be there.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+        let mut groups: Vec<(&str, Vec<i32>)> = vec![];
+        for word in text.split_whitespace() {
+            match word.parse() {
+                Ok(value) => match groups.last_mut() {
+                    Some((_, values)) => values.push(value),
+                    None => return Err(format!("{path}: {value} is not in a group")),
+                },
+                Err(_) => groups.push((word, vec![])),
+            }
+        }
+        let mut values = vec![];
+        for (name, size) in GROUPS.iter() {
+            match groups.iter().find(|(n, _)| n == name) {
+                Some((_, group)) if group.len() == *size => values.extend(group),
+                Some((_, group)) => {
+                    return Err(format!(
+                        "{path}: {name} has {} numbers instead of {size}",
+                        group.len()
+                    ))
+                }
+                None => return Err(format!("{path}: {name} is missing")),
+            }
+        }
+        if let Some((name, _)) = groups
+            .iter()
+            .find(|(n, _)| !GROUPS.iter().any(|g| g.0 == *n))
+        {
+            return Err(format!("{path}: unknown group {name}"));
+        }
+        let mut weights = Self::default();
+        weights.set_values(&values);
+        Ok(weights)
+    }
+
+    /// writes the weights as text, each group's name followed by its numbers
+    /// in rows of eight.
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let values = self.values();
+        let mut out = String::new();
+        let mut start = 0;
+        for (name, size) in GROUPS.iter() {
+            out += name;
+            out.push('\n');
+            for row in values[start..start + size].chunks(BOARD_SIZE) {
+                let row: Vec<String> = row.iter().map(|value| format!("{value:>5}")).collect();
+                out += &row.join(" ");
+                out.push('\n');
+            }
+            start += size;
+        }
+        std::fs::write(path, out).map_err(|e| format!("could not write {path}: {e}"))
+    }
+}
+
+/// the value of a piece type when counting material, used to order captures.
+pub fn piece_value(kind: Type) -> i32 {
+    match kind {
+        Type::Pawn => 100,
+        Type::Knight => 320,
+        Type::Bishop => 330,
+        Type::Rook => 500,
+        Type::Queen => 900,
+        Type::King => 20000,
+    }
+}
+
+/// scores the position from the point of view of the team whose turn it is.
+pub fn evaluate(board: &Board, weights: &Weights) -> i32 {
+    let score = evaluate_white(board, weights);
+    if board.color == Color::White {
+        score
+    } else {
+        -score
+    }
+}
+
+/// scores the position from white's point of view.
+pub fn evaluate_white(board: &Board, weights: &Weights) -> i32 {
+    // [middle game, end game] scores for white minus black
+    let mut score = [0; 2];
+    let mut phase = 0;
+    let mut bishops = [0; 2];
+    let mut pawn_files = [[0; BOARD_SIZE]; 2];
+    for y in 0..BOARD_SIZE {
+        for x in 0..BOARD_SIZE {
+            if let Piece::Black(Type::Pawn) | Piece::White(Type::Pawn) = board.squares[y][x] {
+                pawn_files[board.squares[y][x].color().unwrap().index()][x] += 1;
+            }
+        }
+    }
+    for y in 0..BOARD_SIZE {
+        for x in 0..BOARD_SIZE {
+            let piece = board.squares[y][x];
+            let (color, kind) = match (piece.color(), piece.kind()) {
+                (Some(color), Some(kind)) => (color, kind),
+                _ => continue,
+            };
+            let sign = if color == Color::White { 1 } else { -1 };
+            // the tables are written with rank 8 first from white's point of view
+            let square = if color == Color::White {
+                (BOARD_SIZE - 1 - y) * BOARD_SIZE + x
+            } else {
+                y * BOARD_SIZE + x
+            };
+            let forward = if color == Color::White {
+                y
+            } else {
+                BOARD_SIZE - 1 - y
+            };
+            let own = color.index();
+            let enemy = color.opponent().index();
+            phase += PHASE_WEIGHTS[kind.index()];
+            for (stage, value) in score.iter_mut().enumerate() {
+                *value += sign
+                    * (weights.piece_values[stage][kind.index()]
+                        + weights.piece_squares[stage][kind.index()][square]);
+            }
+            match kind {
+                Type::Pawn => {
+                    let mut bonus = [0; 2];
+                    if pawn_files[own][x] > 1 {
+                        bonus = add(bonus, weights.doubled_pawn);
+                    }
+                    let left = x > 0 && pawn_files[own][x - 1] > 0;
+                    let right = x + 1 < BOARD_SIZE && pawn_files[own][x + 1] > 0;
+                    if !left && !right {
+                        bonus = add(bonus, weights.isolated_pawn);
+                    }
+                    if is_passed(board, [x as i8, y as i8], color) {
+                        bonus[0] += weights.passed_pawn[0][forward];
+                        bonus[1] += weights.passed_pawn[1][forward];
+                    }
+                    score = add(score, [sign * bonus[0], sign * bonus[1]]);
+                }
+                Type::Bishop => bishops[own] += 1,
+                Type::Rook => {
+                    if pawn_files[own][x] == 0 && pawn_files[enemy][x] == 0 {
+                        score = add(score, scale(weights.rook_open_file, sign));
+                    } else if pawn_files[own][x] == 0 {
+                        score = add(score, scale(weights.rook_half_open_file, sign));
+                    }
+                }
+                _ => (),
+            }
+            if kind != Type::Pawn && kind != Type::King {
+                let squares = mobility(board, [x as i8, y as i8], kind) * sign;
+                score[0] += weights.mobility[0][kind.index()] * squares;
+                score[1] += weights.mobility[1][kind.index()] * squares;
+            }
+        }
+    }
+    if bishops[Color::White.index()] >= 2 {
+        score = add(score, weights.bishop_pair);
+    }
+    if bishops[Color::Black.index()] >= 2 {
+        score = add(score, scale(weights.bishop_pair, -1));
+    }
+    let phase = phase.min(MAX_PHASE);
+    (score[0] * phase + score[1] * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+/// adds two [middle game, end game] pairs.
+fn add(a: [i32; 2], b: [i32; 2]) -> [i32; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+/// multiplies both halves of a [middle game, end game] pair.
+fn scale(a: [i32; 2], n: i32) -> [i32; 2] {
+    [a[0] * n, a[1] * n]
+}
+
+/// true if no enemy pawn can stop the pawn on the way to promotion.
+fn is_passed(board: &Board, pos: [i8; 2], color: Color) -> bool {
+    let dir = if color == Color::White { 1 } else { -1 };
+    let enemy = Piece::new(color.opponent(), Type::Pawn);
+    let mut y = pos[1] + dir;
+    while y >= 0 && y < BOARD_SIZE as i8 {
+        for x in pos[0] - 1..=pos[0] + 1 {
+            if !point_out_of_bounds([x, y]) && board.piece_at([x, y]) == enemy {
+                return false;
+            }
+        }
+        y += dir;
+    }
+    true
+}
+
+/// counts the squares a piece attacks that are not taken by its own team.
+fn mobility(board: &Board, pos: [i8; 2], kind: Type) -> i32 {
+    let color = board.piece_at(pos).color();
+    let (directions, slides): (&[[i8; 2]], bool) = match kind {
+        Type::Knight => (
+            &[
+                [1, 2],
+                [2, 1],
+                [2, -1],
+                [1, -2],
+                [-1, -2],
+                [-2, -1],
+                [-2, 1],
+                [-1, 2],
+            ],
+            false,
+        ),
+        Type::Bishop => (&[[1, 1], [-1, 1], [1, -1], [-1, -1]], true),
+        Type::Rook => (&[[1, 0], [-1, 0], [0, 1], [0, -1]], true),
+        _ => (
+            &[
+                [1, 0],
+                [1, 1],
+                [0, 1],
+                [-1, 1],
+                [-1, 0],
+                [-1, -1],
+                [0, -1],
+                [1, -1],
+            ],
+            true,
+        ),
+    };
+    let mut count = 0;
+    for direction in directions.iter() {
+        let mut to = [pos[0] + direction[0], pos[1] + direction[1]];
+        while !point_out_of_bounds(to) {
+            let piece = board.piece_at(to);
+            if piece.color() != color {
+                count += 1;
+            }
+            if piece != Piece::Empty || !slides {
+                break;
+            }
+            to = [to[0] + direction[0], to[1] + direction[1]];
+        }
+    }
+    count
+}
@@ -0,0 +1,245 @@
+//! Interactive lessons. A course is a text file of steps, each a position with text
+//! explaining it, arrows and highlighted squares, and maybe a move the student has to find
+//! before going on. A line starts with a keyword:
+//!
+//! ```text
+//! title The Lucena position
+//! step
+//! fen 1K6/1P2k3/8/8/8/8/2r5/3R4 w - - 0 1
+//! text White's king is stuck in front of the pawn.
+//! text The rook builds a bridge on the fourth rank.
+//! arrow d1d4
+//! highlight b8 b7
+//! move Rd4
+//! ```
+//!
+//! A step without a fen goes on from the position the step before it ends in, after its move.
+//! Lines starting with # are left out.
+
+use crate::board::{self, Board, Move, Pos, START_FEN};
+use std::collections::HashMap;
+
+/// One step of a lesson.
+pub struct Step {
+    pub fen: String,
+    pub text: String,
+    pub arrows: Vec<Move>,
+    pub highlights: Vec<Pos>,
+    /// the move to play before going on.
+    pub move_to_find: Option<Move>,
+}
+
+/// A course of lessons, as a list of steps.
+pub struct Course {
+    pub title: String,
+    pub steps: Vec<Step>,
+}
+
+impl Course {
+    /// reads a course from a file, checking the positions and moves in it.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+        let mut title = path.to_owned();
+        let mut steps: Vec<Step> = vec![];
+        let mut board = Board::from_fen(START_FEN)?;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e: &str| format!("{path} line {}: {e}", i + 1);
+            let (keyword, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            if keyword == "title" {
+                title = value.to_owned();
+                continue;
+            }
+            if keyword == "step" {
+                // the next step starts where this one ends
+                if let Some(m) = steps.last().and_then(|step| step.move_to_find) {
+                    board.make_move(m);
+                }
+                steps.push(Step {
+                    fen: board.to_fen(),
+                    text: String::new(),
+                    arrows: vec![],
+                    highlights: vec![],
+                    move_to_find: None,
+                });
+                continue;
+            }
+            let step = steps
+                .last_mut()
+                .ok_or_else(|| error("a step has to start before this"))?;
+            match keyword {
+                "fen" => {
+                    board = Board::from_fen(value).map_err(|e| error(&e))?;
+                    step.fen = value.to_owned();
+                }
+                "text" => {
+                    if !step.text.is_empty() {
+                        step.text.push('\n');
+                    }
+                    step.text += value;
+                }
+                "arrow" => {
+                    for arrow in value.split_whitespace() {
+                        let squares = match arrow.split_at_checked(2) {
+                            Some((from, to)) => board::parse_pos(from).zip(board::parse_pos(to)),
+                            None => None,
+                        };
+                        let (from, to) =
+                            squares.ok_or_else(|| error(&format!("bad arrow {arrow}")))?;
+                        step.arrows.push(Move::new(from, to));
+                    }
+                }
+                "highlight" => {
+                    for square in value.split_whitespace() {
+                        let pos = board::parse_pos(square)
+                            .ok_or_else(|| error(&format!("bad square {square}")))?;
+                        step.highlights.push(pos);
+                    }
+                }
+                "move" => {
+                    let m = board
+                        .parse_san(value)
+                        .ok_or_else(|| error(&format!("illegal move {value}")))?;
+                    step.move_to_find = Some(m);
+                }
+                keyword => return Err(error(&format!("unknown keyword {keyword}"))),
+            }
+        }
+        if steps.is_empty() {
+            return Err(format!("there are no steps in {path}"));
+        }
+        Ok(Self { title, steps })
+    }
+}
+
+/// How far the student got in each course, kept in a text file with a line for each course:
+/// the number of steps done, then the course's title.
+#[derive(Default)]
+pub struct Progress {
+    steps: HashMap<String, usize>,
+}
+
+impl Progress {
+    /// reads the progress from a file, starting with none if there is no file yet.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("could not read {path}: {e}")),
+        };
+        let mut steps = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (done, title) = line
+                .split_once(' ')
+                .and_then(|(done, title)| Some((done.parse().ok()?, title)))
+                .ok_or_else(|| format!("{path} line {}: bad course progress", i + 1))?;
+            steps.insert(title.to_owned(), done);
+        }
+        Ok(Self { steps })
+    }
+
+    /// writes the progress to a file, which is replaced.
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let mut titles: Vec<&String> = self.steps.keys().collect();
+        titles.sort();
+        let mut out = String::new();
+        for title in titles {
+            out += &format!("{} {title}\n", self.steps[title]);
+        }
+        std::fs::write(path, out).map_err(|e| format!("could not write {path}: {e}"))
+    }
+
+    /// the number of steps of a course done so far.
+    pub fn done(&self, title: &str) -> usize {
+        self.steps.get(title).copied().unwrap_or(0)
+    }
+
+    pub fn set_done(&mut self, title: &str, done: usize) {
+        self.steps.insert(title.to_owned(), done);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a path in the temp directory for a test's file.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("lesson-test-{}-{name}", std::process::id()));
+        path.to_str().unwrap().to_owned()
+    }
+
+    /// writes a course file and reads it.
+    fn read(name: &str, text: &str) -> Result<Course, String> {
+        let path = temp_path(name);
+        std::fs::write(&path, text).unwrap();
+        Course::read(&path)
+    }
+
+    const COURSE: &str = "\
+# the Lucena position
+title The Lucena position
+step
+fen 1K6/1P2k3/8/8/8/8/2r5/3R4 w - - 0 1
+text White's king is stuck in front of the pawn.
+text The rook builds a bridge on the fourth rank.
+arrow d1d4
+highlight b8 b7
+move Rd4
+
+step
+text Now the king can come out.
+";
+
+    #[test]
+    fn reads_steps() {
+        let course = read("course.txt", COURSE).unwrap();
+        assert_eq!(course.title, "The Lucena position");
+        assert_eq!(course.steps.len(), 2);
+        let first = &course.steps[0];
+        assert_eq!(first.text.lines().count(), 2);
+        assert_eq!(first.arrows[0].to_uci(), "d1d4");
+        assert_eq!(first.highlights, [[1, 7], [1, 6]]);
+        assert_eq!(first.move_to_find.unwrap().to_uci(), "d1d4");
+        // the second step goes on after the move of the first
+        assert_eq!(course.steps[1].fen, "1K6/1P2k3/8/8/3R4/8/2r5/8 b - - 1 1");
+        assert!(course.steps[1].move_to_find.is_none());
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let error = |text: &str| read("bad.txt", text).err().unwrap();
+        assert!(
+            error("text before any step\n").ends_with("line 1: a step has to start before this")
+        );
+        assert!(error("step\nmove Ke5\n").ends_with("line 2: illegal move Ke5"));
+        assert!(error("step\narrow e2\n").ends_with("line 2: bad arrow e2"));
+        assert!(error("step\nhighlight z9\n").ends_with("line 2: bad square z9"));
+        assert!(error("step\nsound ding\n").ends_with("line 2: unknown keyword sound"));
+        assert!(error("# nothing\n").starts_with("there are no steps"));
+    }
+
+    #[test]
+    fn progress_round_trip() {
+        let path = temp_path("progress.txt");
+        let _ = std::fs::remove_file(&path);
+        let mut progress = Progress::read(&path).unwrap();
+        assert_eq!(progress.done("The Lucena position"), 0);
+        progress.set_done("The Lucena position", 2);
+        progress.set_done("Basic mates", 5);
+        progress.write(&path).unwrap();
+        let progress = Progress::read(&path).unwrap();
+        assert_eq!(progress.done("The Lucena position"), 2);
+        assert_eq!(progress.done("Basic mates"), 5);
+        std::fs::write(&path, "two The Lucena position\n").unwrap();
+        assert!(Progress::read(&path).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Difficulty levels for the built in computer opponent.
+//! Weaker levels search less and make deliberate mistakes,
+//! so the engine can be a fair opponent for beginners as well as strong players.
+
+use crate::board::{Board, Move};
+use crate::random::Rng;
+use crate::search::{Limits, Search};
+
+/// How strongly the computer plays.
+pub struct Level {
+    pub name: &'static str,
+    /// the rating measured in games between the levels, see LEVELS.
+    pub elo: u32,
+    /// how deep each move is searched, or None for no limit.
+    pub depth: Option<u32>,
+    /// how many positions may be searched for each move, or None for no limit.
+    pub nodes: Option<u64>,
+    /// the most centipawns of random noise added to the score of every move.
+    pub noise: i32,
+    /// the percent chance of playing a random legal move instead of thinking.
+    pub blunder_percent: u64,
+    /// whether moves are taken from the opening book, which would play weaker levels
+    /// as well as the book does.
+    pub book: bool,
+}
+
+/// The levels from weakest to strongest.
+/// The Elo ratings were measured by playing each level against the next one with --match
+/// and "builtin --level n" players from 20 openings, counting Beginner as 800. The three
+/// weakest pairs played 100 games, the others up to 40. Games between engines spread
+/// ratings further apart than human ones, and the three strongest levels won nearly every
+/// game against the level below, so those gaps are at least as big as given.
+pub const LEVELS: [Level; 6] = [
+    Level {
+        name: "Beginner",
+        elo: 800,
+        depth: Some(1),
+        nodes: Some(2_000),
+        noise: 250,
+        blunder_percent: 20,
+        book: false,
+    },
+    Level {
+        name: "Novice",
+        elo: 1200,
+        depth: Some(1),
+        nodes: Some(5_000),
+        noise: 150,
+        blunder_percent: 10,
+        book: false,
+    },
+    Level {
+        name: "Casual",
+        elo: 1550,
+        depth: Some(2),
+        nodes: Some(20_000),
+        noise: 80,
+        blunder_percent: 5,
+        book: false,
+    },
+    Level {
+        name: "Club",
+        elo: 2300,
+        depth: Some(3),
+        nodes: Some(100_000),
+        noise: 40,
+        blunder_percent: 0,
+        book: true,
+    },
+    Level {
+        name: "Strong club",
+        elo: 3000,
+        depth: Some(5),
+        nodes: Some(400_000),
+        noise: 15,
+        blunder_percent: 0,
+        book: true,
+    },
+    Level {
+        name: "Expert",
+        elo: 3550,
+        depth: None,
+        nodes: Some(1_000_000),
+        noise: 0,
+        blunder_percent: 0,
+        book: true,
+    },
+];
+
+impl Level {
+    /// picks a move, playing only as well as the level allows.
+    /// Returns None if there are no legal moves.
+    pub fn choose_move(&self, board: &mut Board, rng: &mut Rng) -> Option<Move> {
+        let moves = board.legal_moves();
+        if moves.is_empty() {
+            return None;
+        }
+        if rng.below(100) < self.blunder_percent {
+            return Some(moves[rng.below(moves.len() as u64) as usize]);
+        }
+        let mut search = Search::default();
+        if self.noise == 0 {
+            let limits = Limits {
+                depth: self.depth,
+                nodes: self.nodes,
+                ..Limits::default()
+            };
+            return search
+                .search(board, limits, &mut |_| ())
+                .pv
+                .first()
+                .copied();
+        }
+        // score every move on its own, so noise can make a worse one look best
+        let limits = Limits {
+            depth: self.depth,
+            nodes: self.nodes.map(|nodes| nodes / moves.len() as u64),
+            ..Limits::default()
+        };
+        let mut best = None;
+        let mut best_score = i32::MIN;
+        for m in moves {
+            board.make_move(m);
+            let score = -search.search(board, limits, &mut |_| ()).score;
+            board.unmake_move();
+            let noise = rng.below(self.noise as u64 * 2 + 1) as i32 - self.noise;
+            if score + noise > best_score {
+                best_score = score + noise;
+                best = Some(m);
+            }
+        }
+        best
+    }
+}
@@ -3,103 +3,410 @@
 //! it uses the ggez graphics and game library to.
 //! create a graphics user interface and get mouse input.
 //!
+//! Run it with ```--uci``` to use the engine from a chess GUI instead of opening a window,
+//! or with ```--engine <path>``` to play against an outside UCI engine
+//! (```--engine-color white``` makes the engine move first, ```--movetime 500``` makes it faster).
+//! The side panel names the opening being played and its ECO code, which are saved with the game.
+//! ```--book <file>``` opens a Polyglot opening book for the computer to play from and to show
+//! in the side panel. Book moves are picked at random by weight unless ```--book-best``` is given.
+//! ```--syzygy <directory>``` looks endgames up in the Syzygy tablebases in a directory,
+//! showing the result in the side panel and making the computer play them perfectly.
+//! ```--puzzles <file.csv>``` trains on tactics puzzles from a file in the Lichess puzzle
+//! format instead of playing games, only those with a theme if ```--theme fork``` is given
+//! and within ```--min-rating``` and ```--max-rating```. Press P for the next puzzle.
+//! ```--repertoire <file.pgn>``` quizzes the prepared moves of an opening repertoire,
+//! with the other side's replies taken from its games and variations. The team chosen in
+//! the setup is the one quizzed, and P goes on to the next line.
+//! ```--endgames``` practices well known endings like the Lucena and Philidor positions and
+//! the bishop and knight mate against the engine at full strength, checking the goal of each,
+//! like mating within a number of moves. ```--dtm <directory>``` makes the engine play them
+//! perfectly from depth to mate tables. N and Return try the ending again, P goes on to the next.
+//! ```--course <file>``` steps through a course of lessons, showing each step's text in the
+//! side panel and its arrows and highlighted squares on the board. P goes on to the next
+//! step, or the move a step asks for has to be played first. How far each course got is
+//! saved to lessons.txt, and it carries on from there the next time.
+//! Puzzles and repertoire lines are scheduled for review with spaced repetition, which is
+//! saved to reviews.txt, and the ones due come first.
+//! The new game setup is shown first, where the computer's level, the teams and the number
+//! of threads the analysis and hints search with are chosen.
+//! Press A while playing to have the engine analyze the position in the side panel,
+//! M to change how many of the best moves it shows lines for, H for a hint (press it again
+//! to see where the piece goes), S to save the game as PGN,
+//! G to have the engine go over every move, saving the game with its comments to
+//! annotated.pgn and drawing a graph of the scores that can be clicked to see each position,
+//! K to prove or refute a mate in up to three moves in the position, showing every key move
+//! (more than one means a problem is cooked) and the solution tree, or 1 to 5 to check a
+//! stated mate in that many moves (K again gives up),
+//! and N to set up a new game. ```--fen <fen>``` starts the games from another position,
+//! and so does typing one after pressing F in the new game setup.
+//!
 //! IMPORTANT: alsa-sys v0.1.2, a package used by ggez causes a panic in some linux distros.
 //! This can be solved by typing ```sudo apt install libsdl2-dev``` into the command line.
 //!
 //! This project is a collaboration between Patrick and Shane McDonough.
 
+mod analysis;
+mod annotate;
+mod board;
+mod book;
+mod cli;
+mod dtm;
+mod eco;
+mod endgame;
+mod eval;
+mod lesson;
+mod level;
+mod nnue;
+mod pgn;
+mod problem;
+mod puzzle;
+mod random;
+mod repertoire;
+mod review;
+mod search;
+mod sprt;
+mod syzygy;
+mod tune;
+mod uci;
+mod uci_client;
+mod zobrist;
+
+use analysis::Analysis;
+use annotate::Annotation;
+use board::{Board, Color, Move, Piece, Type, START_FEN};
+use book::Book;
+use dtm::DtmTables;
+use endgame::Practice;
 use ggez::event;
 use ggez::graphics;
 use ggez::input;
 use ggez::nalgebra as na;
 use ggez::{Context, GameResult};
+use lesson::{Course, Progress};
+use level::LEVELS;
+use problem::Solver;
+use puzzle::{Answer, Filter, Trainer};
+use random::Rng;
+use repertoire::{Quiz, Repertoire};
+use review::Schedule;
+use search::{Limits, Search};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use syzygy::{Probe, Tablebase};
+use uci_client::UciEngine;
 
-/// The size of the main window in pixels.
+/// The command line arguments that open the window instead of running a command line tool.
+const WINDOW_ARGS: [&str; 15] = [
+    "--engine",
+    "--engine-color",
+    "--movetime",
+    "--book",
+    "--book-best",
+    "--syzygy",
+    "--puzzles",
+    "--theme",
+    "--min-rating",
+    "--max-rating",
+    "--repertoire",
+    "--endgames",
+    "--dtm",
+    "--course",
+    "--fen",
+];
+/// The size of the chess board in pixels.
 /// The first number is the x coordinate and the second is the y.
-const WINDOW_SIZE: [f32; 2] = [700., 700.];
+const BOARD_PIXELS: [f32; 2] = [700., 700.];
+/// The width of the evaluation bar to the right of the board.
+const EVAL_BAR_WIDTH: f32 = 30.;
+/// The width of the side panel to the right of the evaluation bar.
+const PANEL_WIDTH: f32 = 300.;
+/// The size of the main window in pixels.
+const WINDOW_SIZE: [f32; 2] = [
+    BOARD_PIXELS[0] + EVAL_BAR_WIDTH + PANEL_WIDTH,
+    BOARD_PIXELS[1],
+];
 /// The number of tiles across the board.
 const BOARD_SIZE: usize = 8;
+/// How long the engine thinks about a hint.
+const HINT_TIME: Duration = Duration::from_millis(300);
+/// The file the game is saved to.
+const PGN_FILE: &str = "game.pgn";
+/// The file the analyzed game is saved to.
+const ANNOTATED_FILE: &str = "annotated.pgn";
+/// The file the history of puzzle and repertoire reviews is kept in.
+const REVIEW_FILE: &str = "reviews.txt";
+/// The file the progress through each course of lessons is kept in.
+const PROGRESS_FILE: &str = "lessons.txt";
+/// How long the engine searches each position of the game when analyzing it.
+const ANNOTATE_TIME: Duration = Duration::from_millis(250);
+/// The most moves the mate solver looks for a mate in.
+const SOLVE_MOVES: usize = 3;
+/// How many lines of the mate solver's solution fit in the side panel.
+const SOLUTION_LINES: usize = 12;
+/// The colors of the analysis arrows, best line first. There is one line for each.
+const ARROW_COLORS: [[f32; 4]; 4] = [
+    [0., 0.4, 1., 0.6],
+    [0.1, 0.8, 0.2, 0.6],
+    [1., 0.6, 0., 0.6],
+    [0.8, 0.2, 0.8, 0.6],
+];
+/// The height of the evaluation graph at the bottom of the side panel.
+const GRAPH_HEIGHT: f32 = 120.;
 /// The first number is the x coordinate and the second is the y.
 /// the size of a single tile in pixels.
 const SQUARE_SIZE: [f32; 2] = [
-    WINDOW_SIZE[0] / BOARD_SIZE as f32,
-    WINDOW_SIZE[1] / BOARD_SIZE as f32,
+    BOARD_PIXELS[0] / BOARD_SIZE as f32,
+    BOARD_PIXELS[1] / BOARD_SIZE as f32,
 ];
 
-/// The two different colors a chess piece can be.
-#[derive(PartialEq)]
-enum Color {
-    Black,
-    White,
-}
-
-/// An enum that represents a spot on a chess board.
-/// Holds the team information and which type of piece it is.
-/// Alternatively, It could represent and empty space on the chessboard.
-#[derive(Copy, Clone)]
-enum Piece {
-    Empty,
-    Black(Type),
-    White(Type),
-}
-
-/// An enum that represents each type of chess piece there is.
-/// Does not identify team at all.
-#[derive(Copy, Clone)]
-enum Type {
-    Pawn,
-    Rook,
-    Knight,
-    Bishop,
-    Queen,
-    King,
+/// What the thread analyzing the game sends back.
+enum Annotating {
+    /// the number of positions searched so far.
+    Progress(usize),
+    Done(Box<Result<(pgn::Game, Annotation), String>>),
 }
 
 /// This is the current game state.
 struct State {
-    /// board represents the pieces are and their location in the chess board.
-    board: [[Piece; BOARD_SIZE]; BOARD_SIZE],
-    /// color represents which team currently has a turn.
-    color: Color,
+    /// board holds the pieces, which team has a turn, and the rest of the rules.
+    board: Board,
+    /// the FEN of the position the game started from.
+    start_fen: String,
+    /// every move played so far.
+    moves: Vec<Move>,
+    /// a comment about each move played so far, saved with the game.
+    comments: Vec<String>,
     /// the position of the currently selected piece
     selected_pos: Option<[f32; 2]>,
+    /// an outside UCI engine playing one of the teams.
+    engine: Option<UciEngine>,
+    /// the index in LEVELS of the built in computer opponent, or None for two players.
+    /// Ignored while an outside engine is playing.
+    level: Option<usize>,
+    /// the move the built in computer is working out on another thread.
+    computer_move: Option<Receiver<Option<Move>>>,
+    /// the team the computer plays, whether it is the outside engine or the built in one.
+    engine_color: Color,
+    /// the opening book the built in computer plays from.
+    book: Option<Book>,
+    /// true to pick book moves at random by weight instead of always the heaviest.
+    book_random: bool,
+    /// the endgame tablebases used by the built in engine.
+    tablebase: Option<Arc<Tablebase>>,
+    /// the depth to mate tables the computer plays the practice endings from.
+    dtm: Option<Arc<DtmTables>>,
+    /// the tablebase result of the position with the given hash, kept so it is not
+    /// looked up again every frame.
+    tablebase_probe: Option<(u64, Option<Probe>)>,
+    /// the tablebase lookup working on another thread, so reading the files does not
+    /// hold up drawing.
+    tablebase_probing: Option<Receiver<(u64, Option<Probe>)>>,
+    /// how long the engine may think about each move.
+    engine_movetime: Duration,
+    /// a message shown over the board, like the result of the game.
+    message: Option<String>,
+    /// the built in engine analyzing the position on the board.
+    analysis: Analysis,
+    /// true while the analysis is shown.
+    show_analysis: bool,
+    /// the move suggested by the last hint, and true once its destination is shown.
+    hint: Option<(Move, bool)>,
+    /// the hint search working on another thread, with the hash of the position it is for.
+    hinting: Option<Receiver<(u64, Option<Move>)>>,
+    /// true once a hint was given for the move about to be played, which is noted in the game.
+    /// Puzzles and repertoire quizzes show their answers as a hint without it being one.
+    hint_taken: bool,
+    /// how many hints have been asked for this game.
+    hints_used: usize,
+    /// how many threads the analysis and hints search with.
+    threads: usize,
+    /// the analysis of the whole game running on another thread.
+    annotating: Option<Receiver<Annotating>>,
+    /// the score for white of each position of the analyzed game, drawn as a graph.
+    eval_graph: Option<Vec<i32>>,
+    /// how many moves into the game the board is, while an earlier position is looked at.
+    viewing: Option<usize>,
+    /// the mate solver working on another thread.
+    solving: Option<Receiver<(u64, Vec<String>)>>,
+    /// set to true to give up on the mate being solved.
+    solving_stop: Arc<AtomicBool>,
+    /// the mate solver's summary and solution tree for the position with the given hash.
+    mate_solution: Option<(u64, Vec<String>)>,
+    /// the puzzles being solved instead of playing a game.
+    trainer: Option<Trainer>,
+    /// the repertoire being trained instead of playing a game.
+    quiz: Option<Quiz>,
+    /// the endings being practiced against the computer instead of playing a game.
+    practice: Option<Practice>,
+    /// the course of lessons being studied instead of playing a game.
+    course: Option<Course>,
+    /// the index of the course's step on the board.
+    course_step: usize,
+    /// how far each course has been studied.
+    progress: Progress,
+    /// the repertoire lines that had a move missed this time, in SAN.
+    missed_lines: Vec<String>,
+    /// when each puzzle and repertoire line is due to be tried again.
+    schedule: Schedule,
+    /// true while the new game setup is shown instead of a game being played.
+    setting_up: bool,
+    /// the starting position being typed in the new game setup, as FEN.
+    fen_entry: Option<String>,
 }
 
 impl State {
     /// creates a new State with all pieces in the correct starting position.
     fn new() -> Self {
         Self {
-            board: [
-                [
-                    Piece::White(Type::Rook),
-                    Piece::White(Type::Knight),
-                    Piece::White(Type::Bishop),
-                    Piece::White(Type::Queen),
-                    Piece::White(Type::King),
-                    Piece::White(Type::Bishop),
-                    Piece::White(Type::Knight),
-                    Piece::White(Type::Rook),
-                ],
-                [Piece::White(Type::Pawn); BOARD_SIZE],
-                [Piece::Empty; BOARD_SIZE],
-                [Piece::Empty; BOARD_SIZE],
-                [Piece::Empty; BOARD_SIZE],
-                [Piece::Empty; BOARD_SIZE],
-                [Piece::Black(Type::Pawn); BOARD_SIZE],
-                [
-                    Piece::Black(Type::Rook),
-                    Piece::Black(Type::Knight),
-                    Piece::Black(Type::Bishop),
-                    Piece::Black(Type::Queen),
-                    Piece::Black(Type::King),
-                    Piece::Black(Type::Bishop),
-                    Piece::Black(Type::Knight),
-                    Piece::Black(Type::Rook),
-                ],
-            ],
-            color: Color::Black,
+            board: Board::new(),
+            start_fen: START_FEN.to_owned(),
+            moves: vec![],
+            comments: vec![],
             selected_pos: None,
+            engine: None,
+            level: Some(2),
+            computer_move: None,
+            engine_color: Color::Black,
+            book: None,
+            book_random: true,
+            tablebase: None,
+            dtm: None,
+            tablebase_probe: None,
+            tablebase_probing: None,
+            engine_movetime: Duration::from_secs(1),
+            message: None,
+            analysis: Analysis::new(),
+            show_analysis: false,
+            hint: None,
+            hinting: None,
+            hint_taken: false,
+            hints_used: 0,
+            threads: 1,
+            annotating: None,
+            eval_graph: None,
+            viewing: None,
+            solving: None,
+            solving_stop: Arc::new(AtomicBool::new(false)),
+            mate_solution: None,
+            trainer: None,
+            quiz: None,
+            practice: None,
+            course: None,
+            course_step: 0,
+            progress: Progress::default(),
+            missed_lines: vec![],
+            schedule: Schedule::default(),
+            setting_up: true,
+            fen_entry: None,
+        }
+    }
+
+    /// sets up the game from the command line arguments "[--engine <path>]
+    /// [--engine-color white|black] [--movetime <milliseconds>] [--book <file>] [--book-best]
+    /// [--syzygy <directory>] [--puzzles <file.csv>] [--theme <theme>] [--min-rating <rating>]
+    /// [--max-rating <rating>] [--repertoire <file.pgn>] [--endgames] [--dtm <directory>]
+    /// [--course <file>] [--fen <fen>]".
+    fn configure(&mut self, args: &[String]) -> Result<(), String> {
+        let mut puzzles = None;
+        let mut filter = Filter::default();
+        let mut i = 0;
+        while i < args.len() {
+            if args[i] == "--book-best" || args[i] == "--endgames" {
+                if args[i] == "--book-best" {
+                    self.book_random = false;
+                } else {
+                    self.practice = Some(Practice::new());
+                }
+                i += 1;
+                continue;
+            }
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| format!("{} needs a value", args[i]))?;
+            match args[i].as_str() {
+                "--engine" => self.engine = Some(UciEngine::spawn(value)?),
+                "--book" => self.book = Some(Book::open(value)?),
+                // the game can still be played without the tables, so this is not an error
+                "--syzygy" => match Tablebase::open(value) {
+                    Ok(tablebase) => {
+                        let tablebase = Arc::new(tablebase);
+                        self.analysis.tablebase = Some(tablebase.clone());
+                        self.tablebase = Some(tablebase);
+                    }
+                    Err(e) => self.message = Some(format!("{e}, tablebases are off")),
+                },
+                "--dtm" => match DtmTables::open(value) {
+                    Ok(tables) => self.dtm = Some(Arc::new(tables)),
+                    Err(e) => self.message = Some(format!("{e}, depth to mate tables are off")),
+                },
+                "--engine-color" => {
+                    self.engine_color = match value.as_str() {
+                        "white" => Color::White,
+                        "black" => Color::Black,
+                        color => return Err(format!("unknown color {color}")),
+                    }
+                }
+                "--movetime" => {
+                    let millis = value
+                        .parse()
+                        .map_err(|_| format!("bad move time {value}"))?;
+                    self.engine_movetime = Duration::from_millis(millis);
+                }
+                "--puzzles" => puzzles = Some(value.clone()),
+                "--repertoire" => {
+                    self.schedule = Schedule::read(REVIEW_FILE)?;
+                    self.quiz = Some(Quiz::new(Repertoire::read(value)?));
+                }
+                "--fen" => {
+                    Board::from_fen(value)?;
+                    self.start_fen = value.clone();
+                }
+                "--course" => {
+                    let course = Course::read(value)?;
+                    self.progress = Progress::read(PROGRESS_FILE)?;
+                    // a course that was finished starts again
+                    let done = self.progress.done(&course.title);
+                    self.course_step = if done < course.steps.len() { done } else { 0 };
+                    self.course = Some(course);
+                }
+                "--theme" => filter.theme = Some(value.clone()),
+                "--min-rating" | "--max-rating" => {
+                    let rating = value.parse().map_err(|_| format!("bad rating {value}"))?;
+                    if args[i] == "--min-rating" {
+                        filter.min_rating = rating;
+                    } else {
+                        filter.max_rating = rating;
+                    }
+                }
+                arg => return Err(format!("unknown argument {arg}")),
+            }
+            i += 2;
+        }
+        let modes = [
+            puzzles.is_some(),
+            self.quiz.is_some(),
+            self.practice.is_some(),
+            self.course.is_some(),
+        ];
+        if modes.iter().filter(|&&mode| mode).count() > 1 {
+            return Err(
+                "only one of puzzles, a repertoire, endgames and a course can be trained at once"
+                    .to_owned(),
+            );
+        }
+        if self.practice.is_some() || self.course.is_some() {
+            self.start_game();
         }
+        if let Some(path) = puzzles {
+            self.schedule = Schedule::read(REVIEW_FILE)?;
+            let puzzles = puzzle::read_puzzles(&path, &filter)?;
+            self.trainer = Some(Trainer::new(puzzles, &self.schedule, review::today()));
+            self.start_game();
+        }
+        Ok(())
     }
 
     /// Draws the white tiles of the chess board against the black background.
@@ -144,20 +451,11 @@ impl State {
 
     /// Draws the chess piece that occupies the given position.
     fn draw_piece(&mut self, ctx: &mut Context, pos: [f32; 2]) {
-        let piece = self.board[pos[1] as usize][pos[0] as usize];
-        let color: graphics::Color;
-        let text_color: graphics::Color;
-        match piece {
+        let piece = self.board.piece_at([pos[0] as i8, pos[1] as i8]);
+        let (color, text_color): (graphics::Color, graphics::Color) = match piece {
             Piece::Empty => return,
-            Piece::Black(_) => {
-                color = [0.2, 0.2, 0.2, 1.0].into();
-                text_color = [0.8, 0.8, 0.8, 1.0].into()
-            }
-            Piece::White(_) => {
-                color = [0.8, 0.8, 0.8, 1.0].into();
-                text_color = [0.2, 0.2, 0.2, 1.0].into()
-            }
-            _ => unreachable!(),
+            Piece::Black(_) => ([0.2, 0.2, 0.2, 1.0].into(), [0.8, 0.8, 0.8, 1.0].into()),
+            Piece::White(_) => ([0.8, 0.8, 0.8, 1.0].into(), [0.2, 0.2, 0.2, 1.0].into()),
         };
         let circle = graphics::Mesh::new_circle(
             ctx,
@@ -195,26 +493,21 @@ impl State {
             ),),
         )
         .unwrap();
-        let text_fragment: graphics::TextFragment;
-        match piece {
+        let text_fragment = match piece {
             Piece::Black(Type::Pawn) | Piece::White(Type::Pawn) => return,
-            Piece::Black(Type::Rook) | Piece::White(Type::Rook) => {
-                text_fragment = graphics::TextFragment::new("R")
-            }
+            Piece::Black(Type::Rook) | Piece::White(Type::Rook) => graphics::TextFragment::new("R"),
             Piece::Black(Type::Knight) | Piece::White(Type::Knight) => {
-                text_fragment = graphics::TextFragment::new("N")
+                graphics::TextFragment::new("N")
             }
             Piece::Black(Type::Bishop) | Piece::White(Type::Bishop) => {
-                text_fragment = graphics::TextFragment::new("B")
+                graphics::TextFragment::new("B")
             }
             Piece::Black(Type::Queen) | Piece::White(Type::Queen) => {
-                text_fragment = graphics::TextFragment::new("Q")
-            }
-            Piece::Black(Type::King) | Piece::White(Type::King) => {
-                text_fragment = graphics::TextFragment::new("K")
+                graphics::TextFragment::new("Q")
             }
+            Piece::Black(Type::King) | Piece::White(Type::King) => graphics::TextFragment::new("K"),
             _ => return,
-        }
+        };
         graphics::draw(
             ctx,
             graphics::Text::new(
@@ -241,12 +534,16 @@ impl State {
     }
 
     /// gets the index of the current square that the mouse is hovering over.
-    fn get_current_square(&mut self, ctx: &mut Context) -> [f32; 2] {
+    /// Returns None when the mouse is off the board.
+    fn get_current_square(&mut self, ctx: &mut Context) -> Option<[f32; 2]> {
         let pos = input::mouse::position(ctx);
-        [
+        if pos.x < 0. || pos.y < 0. || pos.x >= BOARD_PIXELS[0] || pos.y >= BOARD_PIXELS[1] {
+            return None;
+        }
+        Some([
             (pos.x / SQUARE_SIZE[0]) as usize as f32,
             (pos.y / SQUARE_SIZE[1]) as usize as f32,
-        ]
+        ])
     }
 
     /// highlights the square at the given position.
@@ -276,280 +573,1199 @@ impl State {
 
     /// lists the coordinates of valid moves
     fn get_valid_moves(&mut self, pos: [f32; 2]) -> Vec<[f32; 2]> {
-        // TODO: Make piece logic more efficient and not stupid <20-12-20, Shane McDonough>
-        // TODO: Make pawns able to kill by sliding past <20-12-20, Shane McDonough>
-        // TODO: Create king logic <20-12-20, Shane McDonough>
-        let mut v: Vec<[f32; 2]> = vec![];
-        let piece = self.board[pos[1] as usize][pos[0] as usize];
-        match piece {
-            Piece::Black(Type::Pawn) => {
-                self.push_move([pos[0], pos[1] - 1.], false, &mut v);
-                // starting line
-                if pos[1] == 6. {
-                    self.push_move([pos[0], pos[1] - 2.], false, &mut v);
-                }
-                let mut new_pos = [pos[0] + 1., pos[1] - 1.];
-                if !self.point_out_of_bounds(new_pos) {
-                    match self.board[new_pos[1] as usize][new_pos[0] as usize] {
-                        Piece::White(_) => v.push(new_pos),
-                        _ => (),
-                    }
-                    match self.board[new_pos[1] as usize][new_pos[0] as usize + 1] {
-                        Piece::White(_) => v.push(new_pos),
-                        _ => (),
-                    }
-                }
-                new_pos = [pos[0] - 1., pos[1] - 1.];
-                if !self.point_out_of_bounds(new_pos) {
-                    match self.board[new_pos[1] as usize][new_pos[0] as usize] {
-                        Piece::White(_) => v.push(new_pos),
-                        _ => (),
-                    }
-                    match self.board[new_pos[1] as usize][new_pos[0] as usize + 1] {
-                        Piece::White(_) => v.push(new_pos),
-                        _ => (),
-                    }
-                }
+        let mut v: Vec<[f32; 2]> = self
+            .board
+            .legal_moves_from([pos[0] as i8, pos[1] as i8])
+            .iter()
+            .map(|m| [m.to[0] as f32, m.to[1] as f32])
+            .collect();
+        // the four promotions of a pawn all go to the same square
+        v.dedup();
+        v
+    }
+
+    /// move the piece in the position self.selected_pos to the argument pos
+    fn move_selected_piece(&mut self, pos: [f32; 2]) -> bool {
+        let s_pos = self.selected_pos.unwrap();
+        let to = [pos[0] as i8, pos[1] as i8];
+        // pawns that reach the last rank always become queens
+        let m = self
+            .board
+            .legal_moves_from([s_pos[0] as i8, s_pos[1] as i8])
+            .into_iter()
+            .find(|m| m.to == to && matches!(m.promotion, None | Some(Type::Queen)));
+        match m {
+            Some(m) if self.trainer.is_some() => {
+                self.answer_puzzle(m);
+                true
             }
-            Piece::White(Type::Pawn) => {
-                self.push_move([pos[0], pos[1] + 1.], false, &mut v);
-                // starting line
-                if pos[1] == 1. {
-                    self.push_move([pos[0], pos[1] + 2.], false, &mut v);
-                }
-                let mut new_pos = [pos[0] + 1., pos[1] + 1.];
-                if !self.point_out_of_bounds(new_pos) {
-                    match self.board[new_pos[1] as usize][new_pos[0] as usize] {
-                        Piece::Black(_) => v.push(new_pos),
-                        _ => (),
-                    }
-                }
-                new_pos = [pos[0] - 1., pos[1] + 1.];
-                if !self.point_out_of_bounds(new_pos) {
-                    match self.board[new_pos[1] as usize][new_pos[0] as usize] {
-                        Piece::Black(_) => v.push(new_pos),
-                        _ => (),
-                    }
-                }
+            Some(m) if self.quiz.is_some() => {
+                self.answer_repertoire(m);
+                true
             }
-            Piece::Black(Type::Knight) | Piece::White(Type::Knight) => {
-                self.push_move([pos[0] + 2., pos[1] + 1.], true, &mut v);
-                self.push_move([pos[0] - 2., pos[1] + 1.], true, &mut v);
-                self.push_move([pos[0] + 2., pos[1] - 1.], true, &mut v);
-                self.push_move([pos[0] - 2., pos[1] - 1.], true, &mut v);
-                self.push_move([pos[0] + 1., pos[1] + 2.], true, &mut v);
-                self.push_move([pos[0] - 1., pos[1] + 2.], true, &mut v);
-                self.push_move([pos[0] + 1., pos[1] - 2.], true, &mut v);
-                self.push_move([pos[0] - 1., pos[1] - 2.], true, &mut v);
-            }
-            Piece::Black(Type::Rook) | Piece::White(Type::Rook) => {
-                let mut offset = 1.;
-                while self.push_move([pos[0] + offset, pos[1]], true, &mut v) {
-                    if !self.can_move_to([pos[0] + offset, pos[1]], false) {
-                        break;
-                    }
-                    offset += 1.;
-                }
-                offset = -1.;
-                while self.push_move([pos[0] + offset, pos[1]], true, &mut v) {
-                    if !self.can_move_to([pos[0] + offset, pos[1]], false) {
-                        break;
-                    }
-                    offset -= 1.;
-                }
-                offset = 1.;
-                while self.push_move([pos[0], pos[1] + offset], true, &mut v) {
-                    if !self.can_move_to([pos[0], pos[1] + offset], false) {
-                        break;
-                    }
-                    offset += 1.;
-                }
-                offset = -1.;
-                while self.push_move([pos[0], pos[1] + offset], true, &mut v) {
-                    if !self.can_move_to([pos[0], pos[1] + offset], false) {
-                        break;
-                    }
-                    offset -= 1.;
-                }
+            Some(m) if self.course.is_some() => {
+                self.answer_lesson(m);
+                true
             }
-            Piece::Black(Type::Bishop) | Piece::White(Type::Bishop) => {
-                let mut offset = 1.;
-                while self.push_move([pos[0] + offset, pos[1] + offset], true, &mut v) {
-                    if !self.can_move_to([pos[0] + offset, pos[1] + offset], false) {
-                        break;
-                    }
-                    offset += 1.;
-                }
-                offset = 1.;
-                while self.push_move([pos[0] - offset, pos[1] + offset], true, &mut v) {
-                    if !self.can_move_to([pos[0] - offset, pos[1] + offset], false) {
-                        break;
-                    }
-                    offset += 1.;
-                }
-                offset = 1.;
-                while self.push_move([pos[0] + offset, pos[1] - offset], true, &mut v) {
-                    if !self.can_move_to([pos[0] + offset, pos[1] - offset], false) {
-                        break;
-                    }
-                    offset += 1.;
+            Some(m) => {
+                self.play_move(m);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// checks a move played in a puzzle, answering with the opponent's next move
+    /// or saying how the puzzle went once it is over.
+    fn answer_puzzle(&mut self, m: Move) {
+        let trainer = self.trainer.as_mut().unwrap();
+        match trainer.answer(&mut self.board, m) {
+            Answer::Correct(m, reply) => {
+                self.play_move(m);
+                self.play_move(reply);
+            }
+            Answer::Solved(m) => {
+                self.play_move(m);
+                self.message = Some("Solved, P for the next puzzle".to_owned());
+                self.review_puzzle(review::REMEMBERED);
+            }
+            Answer::Wrong(answer) => {
+                self.message = Some(format!(
+                    "Wrong, the answer was {}. P for the next puzzle",
+                    self.board.san(answer)
+                ));
+                self.hint = Some((answer, true));
+                self.review_puzzle(review::FORGOTTEN);
+            }
+        }
+    }
+
+    /// schedules the puzzle just finished for review.
+    fn review_puzzle(&mut self, quality: u32) {
+        let id = self.trainer.as_ref().unwrap().puzzle().id.clone();
+        self.review(&id, quality);
+    }
+
+    /// records a review of a puzzle or repertoire line and saves the history to REVIEW_FILE.
+    fn review(&mut self, id: &str, quality: u32) {
+        self.schedule.review(id, quality, review::today());
+        if let Err(e) = self.schedule.write(REVIEW_FILE) {
+            self.message = Some(e);
+        }
+    }
+
+    /// checks a move played in the repertoire quiz, showing the prepared move if it was
+    /// another one.
+    fn answer_repertoire(&mut self, m: Move) {
+        let quiz = self.quiz.as_mut().unwrap();
+        match quiz.check(m, &self.schedule, review::today()) {
+            Ok(()) => {
+                self.play_move(m);
+                self.play_repertoire_replies();
+            }
+            Err(expected) => {
+                self.message = Some(format!(
+                    "Expected {}, play it to go on",
+                    self.board.san(expected)
+                ));
+                self.hint = Some((expected, true));
+            }
+        }
+    }
+
+    /// plays the repertoire's replies until it is the quizzed team's turn,
+    /// and schedules the line for review once it is over.
+    fn play_repertoire_replies(&mut self) {
+        loop {
+            let quiz = self.quiz.as_mut().unwrap();
+            if quiz.finished {
+                break;
+            }
+            if self.board.color == quiz.color {
+                return;
+            }
+            let m = quiz.reply().unwrap();
+            self.play_move(m);
+        }
+        let quiz = self.quiz.as_ref().unwrap();
+        let id = quiz.line_id();
+        let quality = if quiz.missed {
+            let start = Board::from_fen(&self.start_fen).unwrap();
+            self.missed_lines
+                .push(analysis::line_to_san(&start, quiz.line()));
+            self.message = Some("Line done with a miss, P for the next line".to_owned());
+            review::FORGOTTEN
+        } else {
+            self.message = Some("Line done, P for the next line".to_owned());
+            review::REMEMBERED
+        };
+        self.review(&id, quality);
+    }
+
+    /// checks a move played in a lesson against the one the step asks for,
+    /// going on to the next step if it is right.
+    fn answer_lesson(&mut self, m: Move) {
+        let course = self.course.as_ref().unwrap();
+        let expected = match course.steps[self.course_step].move_to_find {
+            Some(expected) => expected,
+            None => return,
+        };
+        // pawns clicked to the last rank always become queens, so the lesson's piece is used
+        if m.from == expected.from && m.to == expected.to {
+            self.play_move(expected);
+            self.next_step();
+        } else {
+            self.message = Some("Not that move, try again".to_owned());
+        }
+    }
+
+    /// goes on to the next step of the course, or back to the first after the last,
+    /// and saves how far the course has got to PROGRESS_FILE.
+    fn next_step(&mut self) {
+        let course = self.course.as_ref().unwrap();
+        let steps = course.steps.len();
+        self.course_step += 1;
+        self.progress.set_done(&course.title, self.course_step);
+        let saved = self.progress.write(PROGRESS_FILE);
+        let finished = self.course_step == steps;
+        if finished {
+            self.course_step = 0;
+        }
+        self.start_game();
+        if let Err(e) = saved {
+            self.message = Some(e);
+        } else if finished {
+            self.message = Some("Course finished, back to the first step".to_owned());
+        }
+    }
+
+    /// moves on to the next puzzle, repertoire line, practice ending or lesson step.
+    fn next_training(&mut self) {
+        if let Some(course) = self.course.as_ref() {
+            if course.steps[self.course_step].move_to_find.is_some() {
+                self.message = Some("Find the move to go on".to_owned());
+            } else {
+                self.next_step();
+            }
+            return;
+        }
+        if let Some(trainer) = self.trainer.as_mut() {
+            trainer.next();
+        }
+        if let Some(practice) = self.practice.as_mut() {
+            practice.next();
+        }
+        if self.trainer.is_some() || self.quiz.is_some() || self.practice.is_some() {
+            self.start_game();
+        }
+    }
+
+    /// plays a move for the team whose turn it is.
+    /// Every move goes through here, whether it was clicked or came from an engine.
+    fn play_move(&mut self, m: Move) {
+        self.board.make_move(m);
+        self.moves.push(m);
+        self.hint = None;
+        let comment = if std::mem::take(&mut self.hint_taken) {
+            "hint"
+        } else {
+            ""
+        };
+        self.comments.push(comment.to_owned());
+        self.selected_pos = None;
+        if self.show_analysis {
+            self.analysis.start(&self.board);
+        }
+        if self.board.is_checkmate() {
+            self.message = Some(format!("Checkmate, {:?} wins", self.board.color.opponent()));
+        } else if self.board.is_stalemate() {
+            self.message = Some("Stalemate".to_owned());
+        } else if self.board.is_draw() {
+            self.message = Some("Draw".to_owned());
+        }
+        if let Some(practice) = self.practice.as_mut() {
+            match practice.check(&mut self.board, self.moves.len()) {
+                Some(Ok(text)) => {
+                    self.message = Some(format!("Passed: {text}. P for the next ending"))
                 }
-                offset = 1.;
-                while self.push_move([pos[0] - offset, pos[1] - offset], true, &mut v) {
-                    if !self.can_move_to([pos[0] - offset, pos[1] - offset], false) {
-                        break;
-                    }
-                    offset += 1.;
+                Some(Err(text)) => {
+                    self.message = Some(format!("Failed: {text}. N to try again, P to go on"))
                 }
+                None => (),
             }
-            Piece::Black(Type::Queen) | Piece::White(Type::Queen) => {
-                let mut offset = 1.;
-                while self.push_move([pos[0] + offset, pos[1] + offset], true, &mut v) {
-                    if !self.can_move_to([pos[0] + offset, pos[1] + offset], false) {
-                        break;
-                    }
-                    offset += 1.;
-                }
-                offset = 1.;
-                while self.push_move([pos[0] - offset, pos[1] + offset], true, &mut v) {
-                    if !self.can_move_to([pos[0] - offset, pos[1] + offset], false) {
-                        break;
+        }
+    }
+
+    /// true once no more moves can be played.
+    fn is_game_over(&mut self) -> bool {
+        self.board.legal_moves().is_empty() || self.board.is_draw()
+    }
+
+    /// true if the computer should be choosing the next move.
+    fn is_engine_turn(&self) -> bool {
+        !self.setting_up
+            && self.viewing.is_none()
+            && self.trainer.is_none()
+            && self.quiz.is_none()
+            && self.course.is_none()
+            && (self.engine.is_some() || self.level.is_some() || self.practice.is_some())
+            && !self
+                .practice
+                .as_ref()
+                .is_some_and(|practice| practice.finished)
+            && self.board.color == self.engine_color
+    }
+
+    /// starts working out the built in computer's move, or plays it once it is ready.
+    fn update_computer(&mut self) {
+        match self.computer_move.as_ref() {
+            None => {
+                let book_move = match self.book.as_ref() {
+                    Some(book) if self.level.is_none_or(|level| LEVELS[level].book) => {
+                        book.pick(&mut self.board, self.book_random, &mut Rng::from_time())
                     }
-                    offset += 1.;
+                    _ => None,
+                };
+                if let Some(m) = book_move {
+                    self.play_move(m);
+                    return;
                 }
-                offset = 1.;
-                while self.push_move([pos[0] + offset, pos[1] - offset], true, &mut v) {
-                    if !self.can_move_to([pos[0] + offset, pos[1] - offset], false) {
-                        break;
-                    }
-                    offset += 1.;
+                let mut board = self.board.clone();
+                let (sender, receiver) = mpsc::channel();
+                if self.practice.is_some() {
+                    // the practice endings are played at full strength, from the tables if
+                    // there are any
+                    let mut search = Search::default();
+                    search.tablebase = self.tablebase.clone();
+                    search.dtm = self.dtm.clone();
+                    search.threads = self.threads;
+                    let limits = Limits {
+                        movetime: Some(self.engine_movetime),
+                        ..Limits::default()
+                    };
+                    thread::spawn(move || {
+                        let info = search.search(&mut board, limits, &mut |_| ());
+                        let _ = sender.send(info.pv.first().copied());
+                    });
+                } else {
+                    let level = self.level.unwrap();
+                    // only the strongest level plays endgames perfectly from the tablebases
+                    let tablebase = match level == LEVELS.len() - 1 {
+                        true => self.tablebase.clone(),
+                        false => None,
+                    };
+                    thread::spawn(move || {
+                        let m = match tablebase
+                            .and_then(|tablebase| tablebase.best_move(&mut board))
+                        {
+                            Some((m, _)) => Some(m),
+                            None => LEVELS[level].choose_move(&mut board, &mut Rng::from_time()),
+                        };
+                        let _ = sender.send(m);
+                    });
                 }
-                offset = 1.;
-                while self.push_move([pos[0] - offset, pos[1] - offset], true, &mut v) {
-                    if !self.can_move_to([pos[0] - offset, pos[1] - offset], false) {
-                        break;
+                self.computer_move = Some(receiver);
+            }
+            Some(receiver) => {
+                if let Ok(m) = receiver.try_recv() {
+                    self.computer_move = None;
+                    if let Some(m) = m {
+                        self.play_move(m);
                     }
-                    offset += 1.;
                 }
-                offset = 1.;
-                while self.push_move([pos[0] + offset, pos[1]], true, &mut v) {
-                    if !self.can_move_to([pos[0] + offset, pos[1]], false) {
-                        break;
-                    }
-                    offset += 1.;
+            }
+        }
+    }
+
+    /// steps through the built in levels, with two players before the weakest.
+    fn change_level(&mut self, step: i32) {
+        let count = LEVELS.len() as i32 + 1;
+        let index = self.level.map_or(0, |level| level as i32 + 1);
+        let index = (index + step).rem_euclid(count);
+        self.level = if index == 0 {
+            None
+        } else {
+            Some(index as usize - 1)
+        };
+    }
+
+    /// changes the number of search threads, up to one for each core.
+    fn change_threads(&mut self, step: i32) {
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        self.threads = (self.threads as i32 + step).clamp(1, cores as i32) as usize;
+        self.analysis.threads = self.threads;
+    }
+
+    /// makes the typed FEN the position games start from, or the usual start if nothing
+    /// was typed. A FEN that can not be read is shown as a message and can be fixed.
+    fn enter_fen(&mut self) {
+        let fen = match self.fen_entry.as_ref() {
+            Some(fen) if fen.trim().is_empty() => START_FEN.to_owned(),
+            Some(fen) => fen.trim().to_owned(),
+            None => return,
+        };
+        match Board::from_fen(&fen) {
+            Ok(_) => {
+                self.start_fen = fen;
+                self.fen_entry = None;
+                self.message = None;
+            }
+            Err(e) => self.message = Some(e),
+        }
+    }
+
+    /// clears the board and starts the game chosen in the setup.
+    /// In puzzle mode the game is the puzzle being solved.
+    fn start_game(&mut self) {
+        if let Some(trainer) = self.trainer.as_ref() {
+            self.start_fen = trainer.puzzle().fen.clone();
+        }
+        if let Some(quiz) = self.quiz.as_ref() {
+            self.start_fen = quiz.start_fen().to_owned();
+        }
+        if let Some(practice) = self.practice.as_ref() {
+            self.start_fen = practice.drill().fen.to_owned();
+        }
+        if let Some(course) = self.course.as_ref() {
+            self.start_fen = course.steps[self.course_step].fen.clone();
+        }
+        self.board = Board::from_fen(&self.start_fen).unwrap();
+        self.moves.clear();
+        self.comments.clear();
+        self.selected_pos = None;
+        self.message = None;
+        self.hint = None;
+        self.hinting = None;
+        self.hint_taken = false;
+        self.hints_used = 0;
+        self.computer_move = None;
+        self.annotating = None;
+        self.eval_graph = None;
+        self.viewing = None;
+        self.setting_up = false;
+        if let Some(engine) = self.engine.as_mut() {
+            if let Err(e) = engine.new_game() {
+                self.engine = None;
+                self.level = None;
+                self.message = Some(format!("{e}, both teams are now played by hand"));
+            }
+        }
+        if self.show_analysis {
+            self.analysis.start(&self.board);
+        }
+        if let Some(practice) = self.practice.as_mut() {
+            // the computer plays against the side to move
+            self.engine_color = self.board.color.opponent();
+            practice.start(&self.board);
+        }
+        if let Some(trainer) = self.trainer.as_mut() {
+            // the opponent sets the puzzle up
+            self.engine_color = self.board.color;
+            let m = trainer.setup_move();
+            self.play_move(m);
+        }
+        if let Some(quiz) = self.quiz.as_mut() {
+            let color = self.engine_color.opponent();
+            match quiz.start(color, &self.schedule, review::today()) {
+                Ok(()) => self.play_repertoire_replies(),
+                Err(e) => self.message = Some(e),
+            }
+        }
+    }
+
+    /// asks the engine for a move, or plays its move once it has picked one.
+    fn update_engine(&mut self) -> Result<(), String> {
+        let engine = self.engine.as_mut().unwrap();
+        if !engine.is_ready() {
+            // it has not answered the handshake of a new game yet
+            engine.poll(&mut self.board)?;
+            return Ok(());
+        }
+        if !engine.is_thinking() {
+            return engine.go(&self.start_fen, &self.moves, self.engine_movetime);
+        }
+        if let Some(m) = engine.poll(&mut self.board)? {
+            self.play_move(m);
+        }
+        Ok(())
+    }
+
+    /// checks if the piece being clicked on is of the right team
+    fn is_piece_selectable(&mut self, pos: [f32; 2]) -> bool {
+        if self.setting_up
+            || self.viewing.is_some()
+            || self
+                .trainer
+                .as_ref()
+                .is_some_and(|trainer| trainer.finished)
+            || self.quiz.as_ref().is_some_and(|quiz| quiz.finished)
+            || self
+                .course
+                .as_ref()
+                .is_some_and(|course| course.steps[self.course_step].move_to_find.is_none())
+            || self
+                .practice
+                .as_ref()
+                .is_some_and(|practice| practice.finished)
+            || self.is_engine_turn()
+            || self.is_game_over()
+        {
+            return false;
+        }
+        self.board.piece_at([pos[0] as i8, pos[1] as i8]).color() == Some(self.board.color)
+    }
+
+    /// the first press highlights the piece the engine would move,
+    /// and the second press highlights where it would go.
+    fn show_hint(&mut self) {
+        if self.is_engine_turn() || self.is_game_over() {
+            return;
+        }
+        match self.hint {
+            Some((m, false)) => self.hint = Some((m, true)),
+            Some((_, true)) => (),
+            None if self.hinting.is_some() => (),
+            None => {
+                let limits = Limits {
+                    movetime: Some(HINT_TIME),
+                    ..Limits::default()
+                };
+                let mut search = Search::default();
+                search.tablebase = self.tablebase.clone();
+                search.threads = self.threads;
+                let mut board = self.board.clone();
+                let (sender, receiver) = mpsc::channel();
+                thread::spawn(move || {
+                    let info = search.search(&mut board, limits, &mut |_| ());
+                    let _ = sender.send((board.hash, info.pv.first().copied()));
+                });
+                self.hinting = Some(receiver);
+            }
+        }
+    }
+
+    /// shows the hint once it has been found, if the position is still the same.
+    fn update_hint(&mut self) {
+        let (hash, m) = match self.hinting.as_ref().map(|receiver| receiver.try_recv()) {
+            Some(Ok(hint)) => hint,
+            _ => return,
+        };
+        self.hinting = None;
+        if let Some(m) = m.filter(|_| hash == self.board.hash) {
+            self.hint = Some((m, false));
+            self.hint_taken = true;
+            self.hints_used += 1;
+        }
+    }
+
+    /// keeps the tablebase result of the position on the board up to date,
+    /// looking it up on another thread.
+    fn update_tablebase(&mut self) {
+        if let Some(Ok(probe)) = self
+            .tablebase_probing
+            .as_ref()
+            .map(|receiver| receiver.try_recv())
+        {
+            self.tablebase_probing = None;
+            self.tablebase_probe = Some(probe);
+        }
+        let tablebase = match self.tablebase.as_ref() {
+            Some(tablebase) => tablebase.clone(),
+            None => return,
+        };
+        let known = matches!(self.tablebase_probe, Some((hash, _)) if hash == self.board.hash);
+        if known || self.tablebase_probing.is_some() {
+            return;
+        }
+        let mut board = self.board.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let probe = tablebase.probe(&mut board);
+            let _ = sender.send((board.hash, probe));
+        });
+        self.tablebase_probing = Some(receiver);
+    }
+
+    /// writes the game so far as PGN.
+    fn to_pgn(&self) -> pgn::Game {
+        let mut game = pgn::Game::new(&self.start_fen);
+        if let Some(engine) = self.engine.as_ref() {
+            let tag = match self.engine_color {
+                Color::White => "White",
+                Color::Black => "Black",
+            };
+            game.set_tag(tag, &engine.name);
+        } else if let Some(level) = self.level.map(|level| &LEVELS[level]) {
+            let tag = match self.engine_color {
+                Color::White => "White",
+                Color::Black => "Black",
+            };
+            game.set_tag(tag, &format!("Chess ({})", level.name));
+            game.set_tag(&format!("{tag}Elo"), &level.elo.to_string());
+            game.set_tag("Level", level.name);
+        }
+        if self.hints_used > 0 {
+            game.comment = format!("Hints used: {}", self.hints_used);
+        }
+        if let Some(opening) = eco::classify(&self.start_fen, &self.moves) {
+            game.set_tag("ECO", &opening.eco);
+            game.set_tag("Opening", &opening.name);
+        }
+        game.moves = self.moves.clone();
+        game.comments = self.comments.clone();
+        game
+    }
+
+    /// saves the game so far to PGN_FILE.
+    fn save_game(&mut self) {
+        self.message = Some(match std::fs::write(PGN_FILE, self.to_pgn().write()) {
+            Ok(()) => format!("Saved the game to {PGN_FILE}"),
+            Err(e) => format!("Could not save the game: {e}"),
+        });
+    }
+
+    /// starts the engine analyzing every move of the game on another thread.
+    fn analyze_game(&mut self) {
+        if self.annotating.is_some() || self.moves.is_empty() {
+            return;
+        }
+        let mut game = self.to_pgn();
+        let mut search = Search::default();
+        search.tablebase = self.tablebase.clone();
+        search.threads = self.threads;
+        let limits = Limits {
+            movetime: Some(ANNOTATE_TIME),
+            ..Limits::default()
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let progress = sender.clone();
+            let annotation = annotate::annotate(&mut game, &mut search, limits, &mut |done| {
+                let _ = progress.send(Annotating::Progress(done));
+            });
+            let _ = sender.send(Annotating::Done(Box::new(annotation.map(|a| (game, a)))));
+        });
+        self.annotating = Some(receiver);
+        self.message = Some("Analyzing the game".to_owned());
+    }
+
+    /// shows how far the analysis of the game has got, and saves it once it is done.
+    fn update_annotating(&mut self) {
+        let receiver = match self.annotating.as_ref() {
+            Some(receiver) => receiver,
+            None => return,
+        };
+        for update in receiver.try_iter() {
+            match update {
+                Annotating::Progress(done) => {
+                    self.message = Some(format!(
+                        "Analyzing the game: {done} of {} positions",
+                        self.moves.len() + 1
+                    ));
                 }
-                offset = -1.;
-                while self.push_move([pos[0] + offset, pos[1]], true, &mut v) {
-                    if !self.can_move_to([pos[0] + offset, pos[1]], false) {
-                        break;
-                    }
-                    offset -= 1.;
+                Annotating::Done(result) => {
+                    self.message = Some(match *result {
+                        Ok((game, annotation)) => {
+                            self.eval_graph = Some(annotation.scores.clone());
+                            let accuracy = |color: Color| annotation.sides[color.index()].accuracy;
+                            match std::fs::write(ANNOTATED_FILE, game.write()) {
+                                Ok(()) => format!(
+                                    "Accuracy: white {:.1}%, black {:.1}%. Saved the analysis to {ANNOTATED_FILE}",
+                                    accuracy(Color::White),
+                                    accuracy(Color::Black)
+                                ),
+                                Err(e) => format!("Could not save the analysis: {e}"),
+                            }
+                        }
+                        Err(e) => format!("Could not analyze the game: {e}"),
+                    });
+                    self.annotating = None;
+                    return;
                 }
-                offset = 1.;
-                while self.push_move([pos[0], pos[1] + offset], true, &mut v) {
-                    if !self.can_move_to([pos[0], pos[1] + offset], false) {
-                        break;
-                    }
-                    offset += 1.;
+            }
+        }
+    }
+
+    /// starts the mate solver on the position on the board, checking a mate in the given
+    /// number of moves for cooks, or looking for the quickest mate within SOLVE_MOVES moves.
+    fn solve_mate(&mut self, moves: Option<usize>) {
+        // asking again while it works gives up, as a long problem can take a while
+        if self.solving.is_some() {
+            self.solving_stop.store(true, Ordering::Relaxed);
+            self.solving = None;
+            return;
+        }
+        let mut board = self.board.clone();
+        // the board may have moved on by the time the solution is ready
+        let hash = board.hash;
+        let (sender, receiver) = mpsc::channel();
+        let mut solver = Solver::default();
+        self.solving_stop = solver.stop.clone();
+        thread::spawn(move || {
+            let (first, last) = moves.map_or((1, SOLVE_MOVES), |moves| (moves, moves));
+            for moves in first..=last {
+                let solution = solver.solve(&mut board, moves);
+                if solver.stop.load(Ordering::Relaxed) {
+                    return;
                 }
-                offset = -1.;
-                while self.push_move([pos[0], pos[1] + offset], true, &mut v) {
-                    if !self.can_move_to([pos[0], pos[1] + offset], false) {
-                        break;
-                    }
-                    offset -= 1.;
+                if !solution.keys.is_empty() || moves == last {
+                    let mut lines = vec![solution.summary(&mut board)];
+                    lines.extend(solution.tree(&mut board));
+                    let _ = sender.send((hash, lines));
+                    return;
                 }
             }
-            Piece::Black(Type::King) | Piece::White(Type::King) => {
-                // TODO: Add logic stoping from king from making illegal moves <20-12-20, Shane McDonough>
-                let mut new_pos = [pos[0] + 1., pos[1]];
-                self.push_move(new_pos, true, &mut v);
-                new_pos = [pos[0] + 1., pos[1] + 1.];
-                self.push_move(new_pos, true, &mut v);
-                new_pos = [pos[0], pos[1] + 1.];
-                self.push_move(new_pos, true, &mut v);
-                new_pos = [pos[0] - 1., pos[1] + 1.];
-                self.push_move(new_pos, true, &mut v);
-                new_pos = [pos[0] - 1., pos[1]];
-                self.push_move(new_pos, true, &mut v);
-                new_pos = [pos[0] - 1., pos[1] - 1.];
-                self.push_move(new_pos, true, &mut v);
-                new_pos = [pos[0], pos[1] - 1.];
-                self.push_move(new_pos, true, &mut v);
-                new_pos = [pos[0] + 1., pos[1] - 1.];
-                self.push_move(new_pos, true, &mut v);
-            }
-            _ => (),
+        });
+        self.solving = Some(receiver);
+        self.mate_solution = None;
+    }
+
+    /// keeps the mate solver's solution once it is done.
+    fn update_solving(&mut self) {
+        let solution = match self.solving.as_ref().map(|receiver| receiver.try_recv()) {
+            Some(Ok(solution)) => solution,
+            _ => return,
         };
-        v
+        self.solving = None;
+        self.mate_solution = Some(solution);
     }
 
-    /// true if a point is outside of the chess board
-    fn point_out_of_bounds(&mut self, pos: [f32; 2]) -> bool {
-        pos[0] < 0. || pos[0] >= BOARD_SIZE as f32 || pos[1] < 0. || pos[1] >= BOARD_SIZE as f32
+    /// shows the position after the given number of moves of the game,
+    /// going back to playing once it is the latest one.
+    fn jump_to(&mut self, ply: usize) {
+        let ply = ply.min(self.moves.len());
+        self.board = Board::from_fen(&self.start_fen).unwrap();
+        for &m in self.moves[..ply].iter() {
+            self.board.make_move(m);
+        }
+        self.viewing = if ply < self.moves.len() {
+            Some(ply)
+        } else {
+            None
+        };
+        self.selected_pos = None;
+        self.hint = None;
+        self.hint_taken = false;
+        if self.show_analysis {
+            self.analysis.start(&self.board);
+        }
     }
 
-    /// checks if a space is available to be inhabited
-    fn can_move_to(&mut self, pos: [f32; 2], can_kill: bool) -> bool {
-        if self.point_out_of_bounds(pos) {
-            return false;
+    /// shows one more line in the analysis, going back to one after the most.
+    fn change_analysis_lines(&mut self) {
+        self.analysis.multi_pv = self.analysis.multi_pv % ARROW_COLORS.len() + 1;
+        if self.show_analysis {
+            self.analysis.start(&self.board);
         }
-        let piece = self.board[pos[1] as usize][pos[0] as usize];
-        match piece {
-            Piece::Empty => true,
-            Piece::Black(_) => {
-                if self.color == Color::Black {
-                    false
-                } else {
-                    can_kill
-                }
+    }
+
+    /// turns the analysis on or off.
+    fn toggle_analysis(&mut self) {
+        self.show_analysis = !self.show_analysis;
+        if self.show_analysis {
+            self.analysis.start(&self.board);
+        } else {
+            self.analysis.stop();
+        }
+    }
+
+    /// draws an arrow from the center of one square to the center of another.
+    fn draw_arrow(&mut self, ctx: &mut Context, m: Move, color: graphics::Color) {
+        let center = |pos: board::Pos| {
+            [
+                (pos[0] as f32 + 0.5) * SQUARE_SIZE[0],
+                (pos[1] as f32 + 0.5) * SQUARE_SIZE[1],
+            ]
+        };
+        let (from, to) = (center(m.from), center(m.to));
+        let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+        let length = (dx * dx + dy * dy).sqrt();
+        let (dx, dy) = (dx / length, dy / length);
+        let head = SQUARE_SIZE[0] * 0.3;
+        let base = [to[0] - dx * head, to[1] - dy * head];
+        let line =
+            graphics::Mesh::new_line(ctx, &[from, base], SQUARE_SIZE[0] * 0.12, color).unwrap();
+        let tip = graphics::Mesh::new_polygon(
+            ctx,
+            graphics::DrawMode::fill(),
+            &[
+                to,
+                [base[0] - dy * head / 2., base[1] + dx * head / 2.],
+                [base[0] + dy * head / 2., base[1] - dx * head / 2.],
+            ],
+            color,
+        )
+        .unwrap();
+        graphics::draw(ctx, &line, (na::Point2::new(0., 0.),)).unwrap();
+        graphics::draw(ctx, &tip, (na::Point2::new(0., 0.),)).unwrap();
+    }
+
+    /// draws a bar beside the board that fills with white as white's position gets better.
+    fn draw_eval_bar(&mut self, ctx: &mut Context, score: i32) {
+        let share = white_share(score);
+        let background = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect {
+                x: BOARD_PIXELS[0],
+                y: 0.,
+                w: EVAL_BAR_WIDTH,
+                h: BOARD_PIXELS[1],
+            },
+            [0.2, 0.2, 0.2, 1.0].into(),
+        )
+        .unwrap();
+        graphics::draw(ctx, &background, (na::Point2::new(0., 0.),)).unwrap();
+        if share > 0. {
+            // white sits at the top of the board, so white's share fills from the top
+            let white = graphics::Mesh::new_rectangle(
+                ctx,
+                graphics::DrawMode::fill(),
+                graphics::Rect {
+                    x: BOARD_PIXELS[0],
+                    y: 0.,
+                    w: EVAL_BAR_WIDTH,
+                    h: BOARD_PIXELS[1] * share,
+                },
+                [0.8, 0.8, 0.8, 1.0].into(),
+            )
+            .unwrap();
+            graphics::draw(ctx, &white, (na::Point2::new(0., 0.),)).unwrap();
+        }
+    }
+
+    /// writes text in the side panel starting at the given height,
+    /// and returns the height just below it.
+    fn draw_panel_text(&mut self, ctx: &mut Context, text: &str, y: f32, size: f32) -> f32 {
+        self.draw_colored_panel_text(ctx, text, graphics::WHITE, y, size)
+    }
+
+    /// like draw_panel_text, in another color.
+    fn draw_colored_panel_text(
+        &mut self,
+        ctx: &mut Context,
+        text: &str,
+        color: graphics::Color,
+        y: f32,
+        size: f32,
+    ) -> f32 {
+        let x = BOARD_PIXELS[0] + EVAL_BAR_WIDTH + 10.;
+        let mut text = graphics::Text::new(
+            graphics::TextFragment::new(text)
+                .color(color)
+                .scale(graphics::Scale { x: size, y: size }),
+        );
+        text.set_bounds([PANEL_WIDTH - 20., f32::INFINITY], graphics::Align::Left);
+        graphics::draw(ctx, &text, (na::Point2::new(x, y),)).unwrap();
+        y + text.height(ctx) as f32 + size / 2.
+    }
+
+    /// where the evaluation graph is drawn, at the bottom of the side panel.
+    fn graph_rect() -> graphics::Rect {
+        graphics::Rect {
+            x: BOARD_PIXELS[0] + EVAL_BAR_WIDTH + 10.,
+            y: BOARD_PIXELS[1] - GRAPH_HEIGHT - 10.,
+            w: PANEL_WIDTH - 20.,
+            h: GRAPH_HEIGHT,
+        }
+    }
+
+    /// the number of moves into the game of the graph's point nearest the mouse,
+    /// or None when the mouse is not over the graph.
+    fn graph_ply(&self, x: f32, y: f32) -> Option<usize> {
+        let scores = self.eval_graph.as_ref()?;
+        let rect = Self::graph_rect();
+        if !rect.contains([x, y]) {
+            return None;
+        }
+        let step = rect.w / (scores.len() - 1) as f32;
+        Some(((x - rect.x) / step).round() as usize)
+    }
+
+    /// draws the score of each position of the analyzed game as a white area that rises
+    /// above the middle line when white is better, marking the position on the board.
+    fn draw_eval_graph(&mut self, ctx: &mut Context) {
+        let scores = match self.eval_graph.as_ref() {
+            Some(scores) => scores,
+            None => return,
+        };
+        let rect = Self::graph_rect();
+        let step = rect.w / (scores.len() - 1) as f32;
+        let point = |ply: usize, score: i32| {
+            na::Point2::new(
+                rect.x + ply as f32 * step,
+                rect.y + rect.h * (1. - white_share(score)),
+            )
+        };
+        let bottom = rect.y + rect.h;
+        let mut area = vec![na::Point2::new(rect.x, bottom)];
+        area.extend(
+            scores
+                .iter()
+                .enumerate()
+                .map(|(ply, &score)| point(ply, score)),
+        );
+        area.push(na::Point2::new(rect.x + rect.w, bottom));
+        let ply = self
+            .viewing
+            .unwrap_or(self.moves.len())
+            .min(scores.len() - 1);
+        let marker = rect.x + ply as f32 * step;
+        let background = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            rect,
+            [0.2, 0.2, 0.2, 1.0].into(),
+        )
+        .unwrap();
+        let middle = rect.y + rect.h / 2.;
+        let middle = graphics::Mesh::new_line(
+            ctx,
+            &[
+                na::Point2::new(rect.x, middle),
+                na::Point2::new(rect.x + rect.w, middle),
+            ],
+            1.,
+            [0.5, 0.5, 0.5, 1.0].into(),
+        )
+        .unwrap();
+        let marker = graphics::Mesh::new_line(
+            ctx,
+            &[
+                na::Point2::new(marker, rect.y),
+                na::Point2::new(marker, rect.y + rect.h),
+            ],
+            2.,
+            [1., 0., 0., 1.].into(),
+        )
+        .unwrap();
+        graphics::draw(ctx, &background, (na::Point2::new(0., 0.),)).unwrap();
+        // there is no white to fill when black was mating all game
+        if let Ok(white) = graphics::Mesh::new_polygon(
+            ctx,
+            graphics::DrawMode::fill(),
+            &area,
+            [0.8, 0.8, 0.8, 1.0].into(),
+        ) {
+            graphics::draw(ctx, &white, (na::Point2::new(0., 0.),)).unwrap();
+        }
+        graphics::draw(ctx, &middle, (na::Point2::new(0., 0.),)).unwrap();
+        graphics::draw(ctx, &marker, (na::Point2::new(0., 0.),)).unwrap();
+        self.draw_panel_text(ctx, "Click the graph to see a position", rect.y - 24., 18.);
+    }
+
+    /// draws the choices for the next game in the side panel.
+    fn draw_setup(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let mut y = self.draw_panel_text(ctx, "New game", y, 24.);
+        let opponent = match (self.engine.as_ref(), self.level) {
+            (Some(engine), _) => engine.name.clone(),
+            (None, Some(level)) => format!(
+                "{} (about {} Elo)\nLeft and Right to change",
+                LEVELS[level].name, LEVELS[level].elo
+            ),
+            (None, None) => "Nobody, two players\nLeft and Right to change".to_owned(),
+        };
+        y = self.draw_panel_text(ctx, &format!("Opponent: {opponent}"), y, 18.);
+        let team = format!("You play: {:?}\nC to change", self.engine_color.opponent());
+        y = self.draw_panel_text(ctx, &team, y, 18.);
+        let threads = format!("Analysis threads: {}\nUp and Down to change", self.threads);
+        y = self.draw_panel_text(ctx, &threads, y, 18.);
+        let position = match self.fen_entry.as_ref() {
+            Some(fen) => format!("Type the position as FEN, Enter when done:\n{fen}_"),
+            None if self.start_fen == START_FEN => {
+                "Position: the usual start\nF to type a FEN".to_owned()
             }
-            Piece::White(_) => {
-                if self.color == Color::White {
-                    false
-                } else {
-                    can_kill
-                }
+            None => format!("Position: {}\nF to type a FEN", self.start_fen),
+        };
+        y = self.draw_panel_text(ctx, &position, y, 18.);
+        if self.fen_entry.is_some() {
+            return y;
+        }
+        self.draw_panel_text(ctx, "Enter to start", y, 18.)
+    }
+
+    /// draws the analysis: the evaluation bar, an arrow for each of the best moves,
+    /// and the depth and each line with its score in the side panel,
+    /// in the color of its arrow.
+    fn draw_analysis(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let mut y = self.draw_panel_text(ctx, "Analysis (A)", y, 24.);
+        if !self.show_analysis {
+            return self.draw_panel_text(ctx, "off", y, 18.);
+        }
+        let info = match self.analysis.latest.clone() {
+            Some(info) => info,
+            None => return self.draw_panel_text(ctx, "thinking...", y, 18.),
+        };
+        let score = analysis::white_score(&self.board, info.score);
+        self.draw_eval_bar(ctx, score);
+        let lines = if info.lines.is_empty() {
+            vec![(info.score, info.pv.clone())]
+        } else {
+            info.lines.clone()
+        };
+        // the best line's arrow is drawn last so it is on top
+        for ((_, line), color) in lines.iter().zip(ARROW_COLORS.iter()).rev() {
+            if let Some(&m) = line.first() {
+                self.draw_arrow(ctx, m, (*color).into());
             }
         }
+        let summary = format!(
+            "{}  depth {}  {} nodes\nM for more lines",
+            analysis::format_score(score),
+            info.depth,
+            info.nodes
+        );
+        y = self.draw_panel_text(ctx, &summary, y, 18.);
+        for ((line_score, line), color) in lines.iter().zip(ARROW_COLORS.iter()) {
+            let line_score = analysis::white_score(&self.board, *line_score);
+            let text = format!(
+                "{}  {}",
+                analysis::format_score(line_score),
+                analysis::line_to_san(&self.board, line)
+            );
+            let mut color = *color;
+            color[3] = 1.;
+            y = self.draw_colored_panel_text(ctx, &text, color.into(), y, 18.);
+        }
+        y
     }
 
-    /// Checks if a new point can be moved to then pushed to a vector
-    fn push_move(&mut self, new_pos: [f32; 2], can_kill: bool, v: &mut Vec<[f32; 2]>) -> bool {
-        if self.can_move_to(new_pos, can_kill) {
-            v.push(new_pos);
-            return true;
+    /// names the opening played so far, up to the position looked at, in the side panel.
+    fn draw_opening(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let ply = self.viewing.unwrap_or(self.moves.len());
+        match eco::classify(&self.start_fen, &self.moves[..ply]) {
+            Some(opening) => {
+                let text = format!("{} {}", opening.eco, opening.name);
+                self.draw_panel_text(ctx, &text, y, 18.)
+            }
+            None => y,
         }
-        false
     }
 
-    /// move the piece in the position self.selected_pos to the argument pos
-    fn move_selected_piece(&mut self, pos: [f32; 2]) -> bool {
-        let s_pos = self.selected_pos.unwrap();
-        let moves = self.get_valid_moves(self.selected_pos.unwrap());
-        if moves.contains(&pos) {
-            self.board[pos[1] as usize][pos[0] as usize] =
-                self.board[s_pos[1] as usize][s_pos[0] as usize];
-            self.board[s_pos[1] as usize][s_pos[0] as usize] = Piece::Empty;
-            return true;
+    /// lists the book moves for the position in the side panel.
+    fn draw_book_moves(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let moves = match self.book.as_ref() {
+            Some(book) => book.moves(&mut self.board),
+            None => return y,
+        };
+        let y = self.draw_panel_text(ctx, "Book", y, 24.);
+        if moves.is_empty() {
+            return self.draw_panel_text(ctx, "out of book", y, 18.);
         }
-        false
+        let total: u32 = moves.iter().map(|&(_, weight)| weight as u32).sum();
+        let lines: Vec<String> = moves
+            .iter()
+            .map(|&(m, weight)| {
+                let percent = weight as u32 * 100 / total.max(1);
+                format!("{}  {weight} ({percent}%)", self.board.san(m))
+            })
+            .collect();
+        self.draw_panel_text(ctx, &lines.join("\n"), y, 18.)
     }
 
-    /// checks if the piece being clicked on is of the right team
-    fn is_piece_selectable(&mut self, pos: [f32; 2]) -> bool {
-        match self.board[pos[1] as usize][pos[0] as usize] {
-            Piece::Black(_) => self.color == Color::Black,
-            Piece::White(_) => self.color == Color::White,
-            Piece::Empty => false,
-            _ => unreachable!(),
+    /// shows what the tablebases say about the position in the side panel.
+    fn draw_tablebase(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        if self.tablebase.is_none() {
+            return y;
+        }
+        let y = self.draw_panel_text(ctx, "Tablebase", y, 24.);
+        let text = match self.tablebase_probe {
+            // the lookup of this position is still running
+            Some((hash, _)) if hash != self.board.hash => "looking it up...".to_owned(),
+            None => "looking it up...".to_owned(),
+            Some((_, Some(probe))) => format!(
+                "{:?} to move: {}, DTZ {}",
+                self.board.color,
+                syzygy::wdl_name(probe.wdl),
+                probe.dtz.abs()
+            ),
+            Some((_, None)) => "not in the tables".to_owned(),
+        };
+        self.draw_panel_text(ctx, &text, y, 18.)
+    }
+
+    /// shows the puzzle being solved and how the training is going in the side panel.
+    fn draw_puzzle(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let trainer = match self.trainer.as_ref() {
+            Some(trainer) => trainer,
+            None => return y,
+        };
+        let puzzle = trainer.puzzle();
+        let text = format!(
+            "You play {:?}\nRating {}\nThemes: {}\nSolved {}, failed {} of {} puzzles\nNext puzzle (P)",
+            self.engine_color.opponent(),
+            puzzle.rating,
+            puzzle.themes.join(", "),
+            trainer.solved,
+            trainer.failed,
+            trainer.count()
+        );
+        let y = self.draw_panel_text(ctx, &format!("Puzzle {}", puzzle.id), y, 24.);
+        let y = self.draw_panel_text(ctx, &text, y, 18.);
+        self.draw_review_stats(ctx, y)
+    }
+
+    /// shows the repertoire being trained and the lines missed in the side panel.
+    fn draw_repertoire(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let quiz = match self.quiz.as_ref() {
+            Some(quiz) => quiz,
+            None => return y,
+        };
+        let mut text = format!(
+            "You play {:?}, {} lines\nNext line (P)",
+            quiz.color,
+            quiz.count()
+        );
+        if !self.missed_lines.is_empty() {
+            text += "\nMissed:";
+            // only the last few fit
+            for line in self.missed_lines.iter().rev().take(3) {
+                text += &format!("\n{line}");
+            }
+        }
+        let y = self.draw_panel_text(ctx, "Repertoire", y, 24.);
+        let y = self.draw_panel_text(ctx, &text, y, 18.);
+        self.draw_review_stats(ctx, y)
+    }
+
+    /// shows the mate solver's solution for the position on the board in the side panel.
+    fn draw_mate_solution(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let text = match (self.solving.as_ref(), self.mate_solution.as_ref()) {
+            (Some(_), _) => "solving... (K to stop)".to_owned(),
+            (None, Some((hash, lines))) if *hash == self.board.hash => {
+                let mut text = lines[..lines.len().min(SOLUTION_LINES)].join("\n");
+                if lines.len() > SOLUTION_LINES {
+                    text += &format!("\nand {} more lines", lines.len() - SOLUTION_LINES);
+                }
+                text
+            }
+            _ => return y,
+        };
+        let y = self.draw_panel_text(ctx, "Mate solver (K, 1 to 5)", y, 24.);
+        self.draw_panel_text(ctx, &text, y, 18.)
+    }
+
+    /// shows the ending being practiced and its goal in the side panel.
+    fn draw_endgame(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let practice = match self.practice.as_ref() {
+            Some(practice) => practice,
+            None => return y,
+        };
+        let drill = practice.drill();
+        let text = format!(
+            "You play {:?}\n{}\nPassed {}, failed {} of {} endings\nNext ending (P)",
+            practice.color,
+            drill.goal.describe(),
+            practice.passed,
+            practice.failed,
+            endgame::DRILLS.len()
+        );
+        let y = self.draw_panel_text(ctx, drill.name, y, 24.);
+        self.draw_panel_text(ctx, &text, y, 18.)
+    }
+
+    /// shows the lesson step's text in the side panel, with its arrows and highlighted
+    /// squares on the board.
+    fn draw_lesson(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let course = match self.course.as_ref() {
+            Some(course) => course,
+            None => return y,
+        };
+        let step = &course.steps[self.course_step];
+        let (arrows, highlights) = (step.arrows.clone(), step.highlights.clone());
+        let title = course.title.clone();
+        let mut text = format!(
+            "Step {} of {}\n{}\n",
+            self.course_step + 1,
+            course.steps.len(),
+            step.text
+        );
+        text += if step.move_to_find.is_some() {
+            "Find the move"
+        } else {
+            "Next step (P)"
+        };
+        for pos in highlights {
+            self.highlight_square(
+                ctx,
+                [pos[0] as f32, pos[1] as f32],
+                [1., 0.6, 0., 0.4].into(),
+            );
+        }
+        for m in arrows {
+            self.draw_arrow(ctx, m, ARROW_COLORS[1].into());
         }
+        let y = self.draw_panel_text(ctx, &title, y, 24.);
+        self.draw_panel_text(ctx, &text, y, 18.)
+    }
+
+    /// shows how the spaced repetition of the training is going in the side panel.
+    fn draw_review_stats(&mut self, ctx: &mut Context, y: f32) -> f32 {
+        let stats = self.schedule.stats(review::today());
+        let text = format!(
+            "Due today: {}\nTrained: {}, {} learned well\nRemembered {:.0}% of {} reviews",
+            stats.due, stats.items, stats.mature, stats.retention, stats.reviews
+        );
+        let y = self.draw_panel_text(ctx, "Reviews", y, 24.);
+        self.draw_panel_text(ctx, &text, y, 18.)
+    }
+
+    /// writes a message across the top of the board.
+    fn draw_message(&mut self, ctx: &mut Context, message: &str) {
+        let background = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect {
+                x: 0.,
+                y: 0.,
+                w: BOARD_PIXELS[0],
+                h: SQUARE_SIZE[1] / 2.,
+            },
+            [0., 0., 0., 0.7].into(),
+        )
+        .unwrap();
+        graphics::draw(ctx, &background, (na::Point2::new(0., 0.),)).unwrap();
+        graphics::draw(
+            ctx,
+            graphics::Text::new(
+                graphics::TextFragment::new(message)
+                    .color(graphics::WHITE)
+                    .scale(graphics::Scale { x: 24., y: 24. }),
+            )
+            .set_bounds(
+                [BOARD_PIXELS[0], SQUARE_SIZE[1] / 2.],
+                graphics::Align::Center,
+            ),
+            (na::Point2::new(0., SQUARE_SIZE[1] / 8.),),
+        )
+        .unwrap();
     }
 }
 
 impl event::EventHandler for State {
     /// The game logic function.
     fn update(&mut self, _ctx: &mut Context) -> GameResult {
+        self.analysis.poll();
+        self.update_annotating();
+        self.update_solving();
+        self.update_hint();
+        self.update_tablebase();
+        let moving = self.is_engine_turn() && !self.is_game_over();
+        // the outside engine is read every frame while it starts up
+        let starting = self
+            .engine
+            .as_ref()
+            .is_some_and(|engine| !engine.is_ready());
+        if moving && self.engine.is_none() {
+            self.update_computer();
+        } else if moving || starting {
+            if let Err(e) = self.update_engine() {
+                // carry on without the engine rather than ending the game
+                self.engine = None;
+                self.level = None;
+                self.message = Some(format!("{e}, both teams are now played by hand"));
+            }
+        }
         Ok(())
     }
 
@@ -558,49 +1774,175 @@ impl event::EventHandler for State {
         &mut self,
         ctx: &mut Context,
         button: input::mouse::MouseButton,
-        _x: f32,
-        _y: f32,
+        x: f32,
+        y: f32,
     ) {
         if button == input::mouse::MouseButton::Left {
-            let pos = self.get_current_square(ctx);
-            if self.selected_pos == None {
+            if let Some(ply) = self.graph_ply(x, y) {
+                self.jump_to(ply);
+                return;
+            }
+            let pos = match self.get_current_square(ctx) {
+                Some(pos) => pos,
+                None => return,
+            };
+            if self.selected_pos.is_none() {
                 if !self.is_piece_selectable(pos) {
                     return;
                 }
                 self.selected_pos = Some(pos);
             } else {
-                if self.move_selected_piece(pos) {
-                    self.color = match self.color {
-                        Color::Black => Color::White,
-                        Color::White => Color::Black,
-                        _ => unreachable!(),
-                    };
-                }
+                self.move_selected_piece(pos);
                 self.selected_pos = None;
             }
         }
     }
 
+    /// when a key is pressed
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        keycode: input::keyboard::KeyCode,
+        _keymods: input::keyboard::KeyMods,
+        _repeat: bool,
+    ) {
+        if keycode == input::keyboard::KeyCode::Escape {
+            event::quit(ctx);
+        } else if self.fen_entry.is_some() {
+            match keycode {
+                input::keyboard::KeyCode::Back => {
+                    self.fen_entry.as_mut().map(String::pop);
+                }
+                input::keyboard::KeyCode::Return => self.enter_fen(),
+                _ => (),
+            }
+            return;
+        } else if self.setting_up {
+            match keycode {
+                input::keyboard::KeyCode::Left if self.engine.is_none() => self.change_level(-1),
+                input::keyboard::KeyCode::Right if self.engine.is_none() => self.change_level(1),
+                input::keyboard::KeyCode::C => self.engine_color = self.engine_color.opponent(),
+                input::keyboard::KeyCode::Up => self.change_threads(1),
+                input::keyboard::KeyCode::Down => self.change_threads(-1),
+                input::keyboard::KeyCode::Return => self.start_game(),
+                _ => (),
+            }
+            return;
+        }
+        match keycode {
+            input::keyboard::KeyCode::A => self.toggle_analysis(),
+            input::keyboard::KeyCode::M => self.change_analysis_lines(),
+            input::keyboard::KeyCode::H => self.show_hint(),
+            input::keyboard::KeyCode::S => self.save_game(),
+            input::keyboard::KeyCode::G => self.analyze_game(),
+            input::keyboard::KeyCode::P => self.next_training(),
+            input::keyboard::KeyCode::K => self.solve_mate(None),
+            input::keyboard::KeyCode::Key1 => self.solve_mate(Some(1)),
+            input::keyboard::KeyCode::Key2 => self.solve_mate(Some(2)),
+            input::keyboard::KeyCode::Key3 => self.solve_mate(Some(3)),
+            input::keyboard::KeyCode::Key4 => self.solve_mate(Some(4)),
+            input::keyboard::KeyCode::Key5 => self.solve_mate(Some(5)),
+            input::keyboard::KeyCode::N => self.setting_up = true,
+            _ => (),
+        }
+    }
+
+    /// when a key is let go
+    fn key_up_event(
+        &mut self,
+        _ctx: &mut Context,
+        keycode: input::keyboard::KeyCode,
+        _keymods: input::keyboard::KeyMods,
+    ) {
+        // not on the key press, or the f it types would be the start of the position
+        if keycode == input::keyboard::KeyCode::F && self.setting_up && self.fen_entry.is_none() {
+            self.fen_entry = Some(String::new());
+        }
+    }
+
+    /// when a character is typed
+    fn text_input_event(&mut self, _ctx: &mut Context, character: char) {
+        if let Some(fen) = self.fen_entry.as_mut() {
+            if !character.is_control() {
+                fen.push(character);
+            }
+        }
+    }
+
     /// the function that draws everything to the screen.
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         self.draw_board(ctx);
         self.draw_pieces(ctx);
-        let current_square_pos = self.get_current_square(ctx);
-        self.highlight_square(ctx, current_square_pos, [1., 1., 0., 0.3].into());
-        if self.selected_pos != None {
+        if let Some(current_square_pos) = self.get_current_square(ctx) {
+            self.highlight_square(ctx, current_square_pos, [1., 1., 0., 0.3].into());
+        }
+        if self.selected_pos.is_some() {
             self.highlight_square(ctx, self.selected_pos.unwrap(), [1., 0., 0., 0.3].into());
             let moves = self.get_valid_moves(self.selected_pos.unwrap());
             for m in moves {
                 self.highlight_square(ctx, m, [0., 1., 0., 0.3].into());
             }
         }
+        if let Some((m, revealed)) = self.hint {
+            let blue: graphics::Color = [0., 0.4, 1., 0.4].into();
+            self.highlight_square(ctx, [m.from[0] as f32, m.from[1] as f32], blue);
+            if revealed {
+                self.highlight_square(ctx, [m.to[0] as f32, m.to[1] as f32], blue);
+            }
+        }
+        if self.setting_up {
+            self.draw_setup(ctx, 10.);
+        } else {
+            let y = self.draw_opening(ctx, 10.);
+            let y = self.draw_analysis(ctx, y);
+            let y = self.draw_book_moves(ctx, y);
+            let y = self.draw_tablebase(ctx, y);
+            let y = self.draw_puzzle(ctx, y);
+            let y = self.draw_repertoire(ctx, y);
+            let y = self.draw_endgame(ctx, y);
+            let y = self.draw_lesson(ctx, y);
+            let y = self.draw_mate_solution(ctx, y);
+            let hints = format!(
+                "Hints used: {} (H)\nSave game (S)\nAnalyze game (G)\nNew game (N)",
+                self.hints_used
+            );
+            self.draw_panel_text(ctx, &hints, y, 18.);
+            self.draw_eval_graph(ctx);
+        }
+        if let Some(message) = self.message.clone() {
+            self.draw_message(ctx, &message);
+        }
         graphics::present(ctx)?;
         Ok(())
     }
 }
 
+/// the share of the evaluation bar that is white for a score for white,
+/// using the usual logistic curve of centipawns.
+fn white_share(score: i32) -> f32 {
+    match search::mate_in(score) {
+        Some(n) if n > 0 => 1.,
+        Some(_) => 0.,
+        None => 1. / (1. + 10f32.powf(-score as f32 / 400.)),
+    }
+}
+
 /// Driver function
 fn main() -> GameResult {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let state = &mut State::new();
+    if args.is_empty() || WINDOW_ARGS.contains(&args[0].as_str()) {
+        if let Err(e) = state.configure(&args) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    } else {
+        if let Err(e) = cli::run(&args) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     let (mut ctx, mut event_loop) =
         ggez::ContextBuilder::new("Chess", "Patrick and Shane McDonough")
             .window_setup(ggez::conf::WindowSetup {
@@ -623,6 +1965,5 @@ fn main() -> GameResult {
                 resizable: false,
             })
             .build()?;
-    let state = &mut State::new();
     event::run(&mut ctx, &mut event_loop, state)
 }
@@ -0,0 +1,250 @@
+//! An efficiently updatable neural network evaluation, loaded from a file.
+//! The network sees a piece of a color on a square as one of 768 inputs, from each side's
+//! point of view, and has one hidden layer for each side and a single output.
+//! Files use the layout of bullet's simple networks: little endian i16 numbers for the
+//! hidden weights (the hidden size for each input), the hidden biases, the output weights
+//! (the side to move's half first) and the output bias, with a squared clipped ReLU
+//! between the layers.
+
+use crate::board::{Color, Piece, Pos, Type};
+use crate::BOARD_SIZE;
+use std::sync::Arc;
+
+/// The number of inputs for each side.
+const INPUTS: usize = 768;
+/// What 1.0 is in the hidden layer.
+const QA: i32 = 255;
+/// What 1.0 is in the output weights.
+const QB: i32 = 64;
+/// Turns the output into centipawns.
+const SCALE: i32 = 400;
+/// How many bytes of padding a file may end with.
+const MAX_PADDING: usize = 64;
+
+/// A network read from a file.
+pub struct Network {
+    /// the size of each side's hidden layer.
+    size: usize,
+    /// size numbers for each input, followed by size zeros for an empty square.
+    hidden_weights: Vec<i16>,
+    hidden_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i16,
+}
+
+impl Network {
+    /// reads a network, working out the hidden size from the length of the file.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("could not read {path}: {e}"))?;
+        let numbers: Vec<i16> = bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let size = numbers.len().saturating_sub(1) / (INPUTS + 3);
+        let used = size * (INPUTS + 3) + 1;
+        if size == 0 || bytes.len() - used * 2 >= MAX_PADDING {
+            return Err(format!("{path} is not a network"));
+        }
+        let (hidden_weights, rest) = numbers.split_at(INPUTS * size);
+        let (hidden_biases, rest) = rest.split_at(size);
+        let (output_weights, rest) = rest.split_at(2 * size);
+        let mut hidden_weights = hidden_weights.to_vec();
+        hidden_weights.resize((INPUTS + 1) * size, 0);
+        Ok(Self {
+            size,
+            hidden_weights,
+            hidden_biases: hidden_biases.to_vec(),
+            output_weights: output_weights.to_vec(),
+            output_bias: rest[0],
+        })
+    }
+
+    /// the hidden weights of the input for a piece on a square from one side's point of view.
+    fn weights(&self, side: Color, piece: Piece, pos: Pos) -> &[i16] {
+        let input = match (piece.color(), piece.kind()) {
+            (Some(color), Some(kind)) => {
+                let kind = match kind {
+                    Type::Pawn => 0,
+                    Type::Knight => 1,
+                    Type::Bishop => 2,
+                    Type::Rook => 3,
+                    Type::Queen => 4,
+                    Type::King => 5,
+                };
+                // black sees the board upside down
+                let rank = match side {
+                    Color::White => pos[1] as usize,
+                    Color::Black => BOARD_SIZE - 1 - pos[1] as usize,
+                };
+                let theirs = if color == side { 0 } else { 384 };
+                theirs + kind * 64 + rank * BOARD_SIZE + pos[0] as usize
+            }
+            _ => INPUTS,
+        };
+        &self.hidden_weights[input * self.size..(input + 1) * self.size]
+    }
+}
+
+/// The hidden layers of both sides for the pieces on a board,
+/// kept up to date by the board as pieces are placed and taken away.
+#[derive(Clone)]
+pub struct Accumulator {
+    network: Arc<Network>,
+    /// indexed by Color::index.
+    values: [Vec<i16>; 2],
+}
+
+impl Accumulator {
+    /// works out the hidden layers for the pieces on the squares from scratch.
+    pub fn new(network: Arc<Network>, squares: &[[Piece; BOARD_SIZE]; BOARD_SIZE]) -> Self {
+        let mut accumulator = Self {
+            values: [network.hidden_biases.clone(), network.hidden_biases.clone()],
+            network,
+        };
+        for (y, row) in squares.iter().enumerate() {
+            for (x, &piece) in row.iter().enumerate() {
+                if piece != Piece::Empty {
+                    accumulator.replace([x as i8, y as i8], Piece::Empty, piece);
+                }
+            }
+        }
+        accumulator
+    }
+
+    /// updates the hidden layers for a piece on a square being replaced by another,
+    /// either of which may be empty.
+    pub fn replace(&mut self, pos: Pos, old: Piece, new: Piece) {
+        for side in [Color::White, Color::Black].iter() {
+            let removed = self.network.weights(*side, old, pos);
+            let added = self.network.weights(*side, new, pos);
+            sub_add(&mut self.values[side.index()], removed, added);
+        }
+    }
+
+    /// the score in centipawns for the side to move.
+    pub fn evaluate(&self, color: Color) -> i32 {
+        let network = &self.network;
+        let (ours, theirs) = network.output_weights.split_at(network.size);
+        let sum = output(&self.values[color.index()], ours)
+            .wrapping_add(output(&self.values[color.opponent().index()], theirs));
+        (sum / QA + network.output_bias as i32) * SCALE / (QA * QB)
+    }
+}
+
+// The loops below are written so the compiler can turn them into vector instructions.
+// On x86_64 they are compiled a second time for AVX2, which is used when the processor has it.
+
+/// takes one row of weights away from the values and adds another.
+fn sub_add(values: &mut [i16], removed: &[i16], added: &[i16]) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // the processor was just checked for AVX2
+            return unsafe { sub_add_avx2(values, removed, added) };
+        }
+    }
+    sub_add_loop(values, removed, added)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn sub_add_avx2(values: &mut [i16], removed: &[i16], added: &[i16]) {
+    sub_add_loop(values, removed, added)
+}
+
+#[inline(always)]
+fn sub_add_loop(values: &mut [i16], removed: &[i16], added: &[i16]) {
+    for ((value, removed), added) in values.iter_mut().zip(removed).zip(added) {
+        *value = value.wrapping_sub(*removed).wrapping_add(*added);
+    }
+}
+
+/// one side's part of the output, before it is scaled.
+fn output(values: &[i16], weights: &[i16]) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // the processor was just checked for AVX2
+            return unsafe { output_avx2(values, weights) };
+        }
+    }
+    output_loop(values, weights)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn output_avx2(values: &[i16], weights: &[i16]) -> i32 {
+    output_loop(values, weights)
+}
+
+#[inline(always)]
+fn output_loop(values: &[i16], weights: &[i16]) -> i32 {
+    values
+        .iter()
+        .zip(weights)
+        .fold(0i32, |sum, (&value, &weight)| {
+            let value = (value as i32).clamp(0, QA);
+            sum.wrapping_add((value * weight as i32).wrapping_mul(value))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::random::Rng;
+
+    /// writes a network of small random numbers with the hidden size and opens it.
+    fn random_network(size: usize) -> Arc<Network> {
+        let mut rng = Rng::new(7);
+        let count = size * (INPUTS + 3) + 1;
+        let bytes: Vec<u8> = (0..count)
+            .flat_map(|_| (rng.below(201) as i16 - 100).to_le_bytes().to_vec())
+            .collect();
+        let path = std::env::temp_dir().join(format!("nnue-test-{}.bin", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        Arc::new(Network::open(path.to_str().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn needs_a_whole_network() {
+        let path = std::env::temp_dir().join(format!("nnue-test-bad-{}.bin", std::process::id()));
+        std::fs::write(&path, [0u8; 1000]).unwrap();
+        assert!(Network::open(path.to_str().unwrap()).is_err());
+        assert!(Network::open("/no/such/network.bin").is_err());
+        assert_eq!(random_network(8).size, 8);
+    }
+
+    #[test]
+    fn updates_match_starting_over() {
+        let network = random_network(16);
+        // en passant, castling both ways and a promotion that captures
+        let mut board = Board::from_fen("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+        board.set_network(Some(network.clone()));
+        let start = board.accumulator().unwrap().values.clone();
+        for m in ["e5d6", "e8g8", "b7a8q", "f8a8", "e1c1"].iter() {
+            let m = board.parse_uci_move(m).unwrap();
+            board.make_move(m);
+            let fresh = Accumulator::new(network.clone(), &board.squares);
+            assert_eq!(board.accumulator().unwrap().values, fresh.values);
+        }
+        for _ in 0..5 {
+            board.unmake_move();
+        }
+        assert_eq!(board.accumulator().unwrap().values, start);
+    }
+
+    #[test]
+    fn both_sides_see_the_same() {
+        let network = random_network(16);
+        let mut white = Board::from_fen("4k3/2n5/8/8/3P4/8/8/R3K3 w - - 0 1").unwrap();
+        // the same position with the colors swapped and the board turned over
+        let mut black = Board::from_fen("r3k3/8/8/3p4/8/8/2N5/4K3 b - - 0 1").unwrap();
+        white.set_network(Some(network.clone()));
+        black.set_network(Some(network));
+        assert_eq!(
+            white.accumulator().unwrap().evaluate(Color::White),
+            black.accumulator().unwrap().evaluate(Color::Black)
+        );
+    }
+}
@@ -0,0 +1,498 @@
+//! Portable Game Notation, the text format chess games are saved and shared in.
+
+use crate::board::{Board, Color, Move, START_FEN};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// The longest line of moves written before wrapping.
+const LINE_LENGTH: usize = 80;
+
+/// A game and everything about it that goes into a PGN file.
+pub struct Game {
+    /// the tag pairs in the order they are written, starting with the seven required ones.
+    pub tags: Vec<(String, String)>,
+    /// the FEN of the position the game started from.
+    pub start_fen: String,
+    pub moves: Vec<Move>,
+    /// a comment about the whole game, written before the first move.
+    pub comment: String,
+    /// a comment after each move, empty for none.
+    pub comments: Vec<String>,
+    /// the annotation glyph of each move, like 2 for "?", 0 for none.
+    pub nags: Vec<u8>,
+    /// the lines that could have been played instead of each move.
+    pub variations: Vec<Vec<Variation>>,
+}
+
+/// A line of moves played instead of a move of the game, which may have variations of its own.
+#[derive(Default)]
+pub struct Variation {
+    /// a comment written before the first move.
+    pub comment: String,
+    pub moves: Vec<Move>,
+    pub comments: Vec<String>,
+    pub nags: Vec<u8>,
+    pub variations: Vec<Vec<Variation>>,
+}
+
+impl Variation {
+    /// creates a variation of moves with no comments or glyphs.
+    pub fn new(moves: Vec<Move>) -> Self {
+        Self {
+            comment: String::new(),
+            comments: vec![String::new(); moves.len()],
+            nags: vec![0; moves.len()],
+            variations: moves.iter().map(|_| vec![]).collect(),
+            moves,
+        }
+    }
+}
+
+impl Game {
+    /// creates a game with no moves and the seven required tags filled with unknowns.
+    pub fn new(start_fen: &str) -> Self {
+        let tags = [
+            ("Event", "Casual game"),
+            ("Site", "?"),
+            ("Date", "????.??.??"),
+            ("Round", "-"),
+            ("White", "?"),
+            ("Black", "?"),
+            ("Result", "*"),
+        ];
+        Self {
+            tags: tags
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            start_fen: start_fen.to_owned(),
+            moves: vec![],
+            comment: String::new(),
+            comments: vec![],
+            nags: vec![],
+            variations: vec![],
+        }
+    }
+
+    /// sets a tag, replacing its value if it is already there.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value.to_owned(),
+            None => self.tags.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// the value of a tag, if the game has it.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// writes the game as PGN.
+    /// The result is worked out from the final position when the rules decide it,
+    /// otherwise the Result tag is kept.
+    pub fn write(&self) -> String {
+        let mut board = Board::from_fen(&self.start_fen).unwrap();
+        let mut words = vec![];
+        if !self.comment.is_empty() {
+            words.push(format_comment(&self.comment));
+        }
+        let line = Line {
+            moves: &self.moves,
+            comments: &self.comments,
+            nags: &self.nags,
+            variations: &self.variations,
+        };
+        write_line(&mut board, line, &mut words);
+        let result = match result(&mut board) {
+            "*" => self.tag("Result").unwrap_or("*"),
+            result => result,
+        };
+        words.push(result.to_owned());
+
+        let mut out = String::new();
+        for (name, value) in self.tags.iter() {
+            // written below from start_fen, so they are not repeated
+            if name == "SetUp" || name == "FEN" {
+                continue;
+            }
+            let value = if name == "Result" { result } else { value };
+            out += &format!(
+                "[{name} \"{}\"]\n",
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            );
+        }
+        if self.start_fen != START_FEN {
+            out += &format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", self.start_fen);
+        }
+        out.push('\n');
+        let mut line = String::new();
+        for word in words {
+            if !line.is_empty() && line.len() + 1 + word.len() > LINE_LENGTH {
+                out += &line;
+                out.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &word;
+        }
+        out += &line;
+        out.push('\n');
+        out
+    }
+}
+
+/// The moves of the game or a variation and what goes with them.
+#[derive(Clone, Copy)]
+struct Line<'a> {
+    moves: &'a [Move],
+    comments: &'a [String],
+    nags: &'a [u8],
+    variations: &'a [Vec<Variation>],
+}
+
+/// adds the words of a line of moves played from the board, leaving the board where it ends.
+fn write_line(board: &mut Board, line: Line, words: &mut Vec<String>) {
+    let mut number = true;
+    for (i, m) in line.moves.iter().enumerate() {
+        if board.color == Color::White {
+            words.push(format!("{}.", board.fullmove_number));
+        } else if number {
+            words.push(format!("{}...", board.fullmove_number));
+        }
+        number = false;
+        let mut san = board.san(*m);
+        match line.nags.get(i) {
+            Some(0) | None => (),
+            Some(nag @ 1..=6) => san += ["!", "?", "!!", "??", "!?", "?!"][*nag as usize - 1],
+            Some(nag) => san += &format!(" ${nag}"),
+        }
+        words.push(san);
+        match line.comments.get(i) {
+            Some(comment) if !comment.is_empty() => words.push(format_comment(comment)),
+            _ => (),
+        }
+        for variation in line.variations.get(i).into_iter().flatten() {
+            let start = words.len();
+            if !variation.comment.is_empty() {
+                words.push(format_comment(&variation.comment));
+            }
+            let mut board = board.clone();
+            let line = Line {
+                moves: &variation.moves,
+                comments: &variation.comments,
+                nags: &variation.nags,
+                variations: &variation.variations,
+            };
+            write_line(&mut board, line, words);
+            if words.len() > start {
+                words[start].insert(0, '(');
+                words.last_mut().unwrap().push(')');
+            }
+            // black's next move needs its number again after a variation
+            number = true;
+        }
+        board.make_move(*m);
+    }
+}
+
+/// wraps a comment in braces, which it may not contain itself.
+fn format_comment(comment: &str) -> String {
+    format!("{{{}}}", comment.replace('}', ")"))
+}
+
+/// the result of a game that ended in the given position, or "*" if it is still going.
+fn result(board: &mut Board) -> &'static str {
+    if board.is_checkmate() {
+        match board.color {
+            Color::White => "0-1",
+            Color::Black => "1-0",
+        }
+    } else if board.is_stalemate() || board.is_draw() {
+        "1/2-1/2"
+    } else {
+        "*"
+    }
+}
+
+/// A piece of PGN text.
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    StartVariation,
+    EndVariation,
+    Result(String),
+    /// a move in SAN, with any move number taken off.
+    Move(String),
+    /// a numeric annotation glyph, or the one for a symbol like "?!".
+    Nag(u8),
+}
+
+/// reads every game in a PGN file.
+/// A game that can not be read gives an error instead, so the rest of the file still can be.
+pub fn read_games(text: &str) -> Vec<Result<Game, String>> {
+    let mut tokens = tokenize(text).into_iter().peekable();
+    let mut games = vec![];
+    while tokens.peek().is_some() {
+        let game = read_game(&mut tokens).map_err(|e| format!("game {}: {e}", games.len() + 1));
+        games.push(game);
+    }
+    games
+}
+
+/// reads the tags, moves and variations of one game.
+fn read_game(tokens: &mut Peekable<std::vec::IntoIter<Token>>) -> Result<Game, String> {
+    let mut game = Game::new(START_FEN);
+    while let Some(Token::Tag(name, value)) = tokens.next_if(|t| matches!(t, Token::Tag(..))) {
+        game.set_tag(&name, &value);
+    }
+    if let Some(fen) = game.tag("FEN") {
+        game.start_fen = fen.to_owned();
+    }
+    let mut line = Variation::default();
+    let read = Board::from_fen(&game.start_fen)
+        .and_then(|mut board| read_line(tokens, &mut board, &mut line, 0));
+    match read {
+        Ok(result) => {
+            if let Some(result) = result {
+                game.set_tag("Result", &result);
+            }
+            game.comment = line.comment;
+            game.moves = line.moves;
+            game.comments = line.comments;
+            game.nags = line.nags;
+            game.variations = line.variations;
+            Ok(game)
+        }
+        Err(e) => {
+            // the rest of the game is skipped over
+            let mut depth = 0;
+            while let Some(token) = tokens.next_if(|t| !matches!(t, Token::Tag(..))) {
+                match token {
+                    Token::Result(_) if depth == 0 => break,
+                    Token::StartVariation => depth += 1,
+                    Token::EndVariation => depth -= 1,
+                    _ => (),
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+/// reads moves played from the board into a line, up to the end of its variation
+/// or, for the game itself, its result, which is given back.
+/// The game ends where the tags of the next game start even without a result.
+fn read_line(
+    tokens: &mut Peekable<std::vec::IntoIter<Token>>,
+    board: &mut Board,
+    line: &mut Variation,
+    depth: usize,
+) -> Result<Option<String>, String> {
+    while let Some(token) = tokens.next_if(|t| !matches!(t, Token::Tag(..))) {
+        match token {
+            Token::Result(result) if depth == 0 => return Ok(Some(result)),
+            Token::EndVariation if depth > 0 => break,
+            Token::StartVariation => {
+                // a variation is played instead of the last move
+                let last = match line.moves.last() {
+                    Some(&last) => last,
+                    None => return Err("variation before the first move".to_owned()),
+                };
+                board.unmake_move();
+                let mut variation = Variation::default();
+                read_line(tokens, board, &mut variation, depth + 1)?;
+                for _ in variation.moves.iter() {
+                    board.unmake_move();
+                }
+                board.make_move(last);
+                line.variations.last_mut().unwrap().push(variation);
+            }
+            Token::Comment(comment) => {
+                let last = match line.comments.last_mut() {
+                    Some(last) => last,
+                    None => &mut line.comment,
+                };
+                if !last.is_empty() {
+                    last.push(' ');
+                }
+                *last += &comment;
+            }
+            Token::Nag(nag) => {
+                if let Some(last) = line.nags.last_mut() {
+                    *last = nag;
+                }
+            }
+            Token::Move(san) => match board.parse_san(&san) {
+                Some(m) => {
+                    board.make_move(m);
+                    line.moves.push(m);
+                    line.comments.push(String::new());
+                    line.nags.push(0);
+                    line.variations.push(vec![]);
+                }
+                None => return Err(format!("illegal move {san}")),
+            },
+            _ => (),
+        }
+    }
+    Ok(None)
+}
+
+/// splits PGN text into tokens.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut chars = text.chars().peekable();
+    let mut tokens = vec![];
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let tag = read_until(&mut chars, ']');
+                let (name, value) = tag
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((&tag, ""));
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                let value = value.replace("\\\"", "\"").replace("\\\\", "\\");
+                tokens.push(Token::Tag(name.to_owned(), value));
+            }
+            '{' => tokens.push(Token::Comment(
+                read_until(&mut chars, '}').trim().to_owned(),
+            )),
+            // a comment to the end of the line
+            ';' => {
+                read_until(&mut chars, '\n');
+            }
+            '(' => tokens.push(Token::StartVariation),
+            ')' => tokens.push(Token::EndVariation),
+            c if c.is_whitespace() => (),
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"{}()[];".contains(*c))
+                {
+                    word.push(c);
+                }
+                if let Some(nag) = word.strip_prefix('$') {
+                    if let Ok(nag) = nag.parse() {
+                        tokens.push(Token::Nag(nag));
+                    }
+                    continue;
+                }
+                if ["1-0", "0-1", "1/2-1/2", "*"].contains(&word.as_str()) {
+                    tokens.push(Token::Result(word));
+                    continue;
+                }
+                // move numbers like "12." and "12..." may be joined to the move, as in "12.e4"
+                let rest = word.trim_start_matches(|c: char| c.is_ascii_digit());
+                let san = if rest.starts_with('.') {
+                    rest.trim_start_matches('.')
+                } else {
+                    &word
+                };
+                // a symbol like "?!" may be joined to the move or stand on its own
+                let symbol = san.trim_start_matches(|c| c != '!' && c != '?');
+                let san = &san[..san.len() - symbol.len()];
+                if !san.is_empty() {
+                    tokens.push(Token::Move(san.to_owned()));
+                }
+                let nag = ["!", "?", "!!", "??", "!?", "?!"]
+                    .iter()
+                    .position(|s| *s == symbol);
+                if let Some(nag) = nag {
+                    tokens.push(Token::Nag(nag as u8 + 1));
+                }
+            }
+        }
+    }
+    tokens
+}
+
+/// reads characters up to the given one, which is skipped.
+fn read_until(chars: &mut Peekable<Chars>, end: char) -> String {
+    let mut s = String::new();
+    for c in chars.by_ref() {
+        if c == end {
+            break;
+        }
+        s.push(c);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME: &str = r#"[Event "Test"]
+[Site "?"]
+[Date "2020.12.20"]
+[Round "1"]
+[White "White"]
+[Black "Black"]
+[Result "1-0"]
+
+{A short game} 1. e4 e5 2. Nf3 Nc6 3. Bc4 Nd4? {Black sets a trap} (3... Nf6 4. Ng5 $1
+(4. d3) 4... d5) 4. Nxe5?? Qg5 (4... Nxc2+ $6) 5. Nxf7 $4 Qxg2 1-0
+"#;
+
+    fn read(text: &str) -> Game {
+        let mut games = read_games(text);
+        assert_eq!(games.len(), 1);
+        games.remove(0).unwrap()
+    }
+
+    #[test]
+    fn reads_glyphs_and_variations() {
+        let game = read(GAME);
+        assert_eq!(game.tag("Event"), Some("Test"));
+        assert_eq!(game.comment, "A short game");
+        assert_eq!(game.moves.len(), 10);
+        assert_eq!(game.nags[5], 2);
+        assert_eq!(game.nags[6], 4);
+        assert_eq!(game.nags[8], 4);
+        assert_eq!(game.comments[5], "Black sets a trap");
+        // 3... Nf6 with 4. d3 in it
+        let variation = &game.variations[5][0];
+        assert_eq!(variation.moves.len(), 3);
+        assert_eq!(variation.nags[1], 1);
+        assert_eq!(variation.variations[1][0].moves.len(), 1);
+        assert_eq!(game.variations[7][0].nags[0], 6);
+    }
+
+    #[test]
+    fn round_trip() {
+        let written = read(GAME).write();
+        let game = read(&written);
+        assert_eq!(game.write(), written);
+        let original = read(GAME);
+        assert_eq!(game.moves, original.moves);
+        assert_eq!(game.nags, original.nags);
+        assert_eq!(game.comments, original.comments);
+        assert_eq!(game.variations[5][0].moves, original.variations[5][0].moves);
+    }
+
+    #[test]
+    fn set_up_position_is_written_once() {
+        let text = r#"[Event "Endgame"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+[Result "*"]
+
+1. e4 Kd7 *
+"#;
+        let game = read(text);
+        assert_eq!(game.start_fen, "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        assert_eq!(game.moves.len(), 2);
+        let written = game.write();
+        assert_eq!(written.matches("[SetUp ").count(), 1);
+        assert_eq!(written.matches("[FEN ").count(), 1);
+        assert_eq!(read(&written).write(), written);
+    }
+}
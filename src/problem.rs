@@ -0,0 +1,236 @@
+//! A solver for mate in N problems. Unlike the engine's search, it looks at every move of
+//! the attacker and every defense, so it proves a mate or proves there is none, finds every
+//! key move that works, and so shows when a problem is cooked by a second solution.
+//! Draws by repetition and the fifty move rule are left out, as problems do.
+
+use crate::board::{Board, Color, Move};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A move of the attacker that forces mate, with the defenses to it.
+pub struct Line {
+    pub m: Move,
+    /// how many moves the mate takes, counting this one.
+    pub moves: usize,
+    /// every reply, none when the move mates.
+    pub defenses: Vec<Defense>,
+}
+
+/// A defense and the attacker's quickest mates after it. More than one is a dual.
+pub struct Defense {
+    pub m: Move,
+    pub continuations: Vec<Line>,
+}
+
+/// Every key move of a problem.
+pub struct Solution {
+    /// the number of moves the problem asks to mate in.
+    pub moves: usize,
+    /// the first moves that mate within that many moves, quickest first.
+    pub keys: Vec<Line>,
+}
+
+impl Solution {
+    /// says if the mate is there and how many solutions it has.
+    pub fn summary(&self, board: &mut Board) -> String {
+        // a key that mates sooner than asked says so
+        let keys: Vec<String> = self
+            .keys
+            .iter()
+            .map(|key| match key.moves < self.moves {
+                true => format!("{} (mate in {})", board.san(key.m), key.moves),
+                false => board.san(key.m),
+            })
+            .collect();
+        match keys.len() {
+            0 => format!("No mate in {}", self.moves),
+            1 => format!("Mate in {}, the key is {}", self.moves, keys[0]),
+            count => format!(
+                "Mate in {} is cooked, {count} key moves: {}",
+                self.moves,
+                keys.join(", ")
+            ),
+        }
+    }
+
+    /// writes the solution tree of every key move, a line for each variation.
+    pub fn tree(&self, board: &mut Board) -> Vec<String> {
+        let mut out = vec![];
+        for key in self.keys.iter() {
+            write_line(board, key, "", 0, &mut out);
+        }
+        out
+    }
+}
+
+/// Proves mates, remembering the positions already solved.
+#[derive(Default)]
+pub struct Solver {
+    /// whether the side to move can mate, by the position's hash and the moves allowed.
+    solved: HashMap<(u64, usize), bool>,
+    /// how many moves were tried.
+    pub nodes: u64,
+    /// set to true from another thread to give up. What was found after that means nothing.
+    pub stop: Arc<AtomicBool>,
+}
+
+impl Solver {
+    /// finds every key move that mates within the number of moves, with its solution tree.
+    pub fn solve(&mut self, board: &mut Board, moves: usize) -> Solution {
+        let mut keys = vec![];
+        for m in board.legal_moves() {
+            if let Some(length) = (1..=moves).find(|&n| self.mates_with(board, m, n)) {
+                keys.push(self.line(board, m, length));
+            }
+        }
+        keys.sort_by_key(|key| key.moves);
+        Solution { moves, keys }
+    }
+
+    /// true if the side to move can mate within n moves.
+    fn can_mate(&mut self, board: &mut Board, n: usize) -> bool {
+        if n == 0 {
+            return false;
+        }
+        if let Some(&mates) = self.solved.get(&(board.hash, n)) {
+            return mates;
+        }
+        let mates = board
+            .legal_moves()
+            .into_iter()
+            .any(|m| self.mates_with(board, m, n));
+        self.solved.insert((board.hash, n), mates);
+        mates
+    }
+
+    /// true if the move mates within n moves, counting itself, whatever the defense.
+    fn mates_with(&mut self, board: &mut Board, m: Move, n: usize) -> bool {
+        if self.stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.nodes += 1;
+        board.make_move(m);
+        let mates = if board.is_checkmate() {
+            true
+        } else if n == 1 {
+            false
+        } else {
+            // a stalemate has no defenses but is no mate
+            let defenses = board.legal_moves();
+            !defenses.is_empty()
+                && defenses.into_iter().all(|defense| {
+                    board.make_move(defense);
+                    let mates = self.can_mate(board, n - 1);
+                    board.unmake_move();
+                    mates
+                })
+        };
+        board.unmake_move();
+        mates
+    }
+
+    /// the solution tree of a move that mates in the given number of moves,
+    /// following the quickest mates after each defense.
+    fn line(&mut self, board: &mut Board, m: Move, moves: usize) -> Line {
+        board.make_move(m);
+        let mut defenses = vec![];
+        if !board.is_checkmate() {
+            for defense in board.legal_moves() {
+                board.make_move(defense);
+                let attacks = board.legal_moves();
+                let quickest = (1..moves)
+                    .find(|&n| attacks.iter().any(|&a| self.mates_with(board, a, n)))
+                    .unwrap_or(moves - 1);
+                let mut continuations = vec![];
+                for a in attacks {
+                    if self.mates_with(board, a, quickest) {
+                        continuations.push(self.line(board, a, quickest));
+                    }
+                }
+                board.unmake_move();
+                defenses.push(Defense {
+                    m: defense,
+                    continuations,
+                });
+            }
+        }
+        board.unmake_move();
+        Line { m, moves, defenses }
+    }
+}
+
+/// a move with its number, like "1. Qg7+" or "1... Kxg7". Black's move after white's
+/// on the same line goes without one.
+fn move_text(board: &mut Board, m: Move, after_white: bool) -> String {
+    let san = board.san(m);
+    match board.color {
+        Color::White => format!("{}. {san}", board.fullmove_number),
+        Color::Black if after_white => san,
+        Color::Black => format!("{}... {san}", board.fullmove_number),
+    }
+}
+
+/// writes a line and its defenses, indented by how deep they are. A defense with one
+/// continuation is written on the same line as it.
+fn write_line(board: &mut Board, line: &Line, before: &str, indent: usize, out: &mut Vec<String>) {
+    out.push(format!(
+        "{:indent$}{before}{}",
+        "",
+        move_text(board, line.m, !before.is_empty())
+    ));
+    board.make_move(line.m);
+    for defense in line.defenses.iter() {
+        let text = move_text(board, defense.m, false);
+        board.make_move(defense.m);
+        match defense.continuations.as_slice() {
+            [only] => write_line(board, only, &format!("{text} "), indent + 2, out),
+            duals => {
+                out.push(format!("{:width$}{text}", "", width = indent + 2));
+                for dual in duals {
+                    write_line(board, dual, "", indent + 4, out);
+                }
+            }
+        }
+        board.unmake_move();
+    }
+    board.unmake_move();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBLEM: &str = "kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1";
+
+    #[test]
+    fn solves_mate_in_two() {
+        let mut board = Board::from_fen(PROBLEM).unwrap();
+        let solution = Solver::default().solve(&mut board, 2);
+        assert_eq!(solution.keys.len(), 1);
+        assert_eq!(board.san(solution.keys[0].m), "Ra6");
+        assert_eq!(solution.summary(&mut board), "Mate in 2, the key is Ra6");
+        // every defense is answered by a mate
+        let tree = solution.tree(&mut board);
+        assert_eq!(tree[0], "1. Ra6");
+        assert!(tree[1..].iter().all(|line| line.ends_with('#')));
+        assert_eq!(board.to_fen(), PROBLEM);
+    }
+
+    #[test]
+    fn finds_no_mate_in_one() {
+        let mut board = Board::from_fen(PROBLEM).unwrap();
+        let solution = Solver::default().solve(&mut board, 1);
+        assert!(solution.keys.is_empty());
+        assert_eq!(solution.summary(&mut board), "No mate in 1");
+    }
+
+    #[test]
+    fn stops_when_told() {
+        let mut board = Board::from_fen(PROBLEM).unwrap();
+        let mut solver = Solver::default();
+        solver.stop.store(true, Ordering::Relaxed);
+        assert!(solver.solve(&mut board, 2).keys.is_empty());
+        assert_eq!(solver.nodes, 0);
+    }
+}
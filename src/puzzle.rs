@@ -0,0 +1,416 @@
+//! Tactics puzzles in the CSV format of the Lichess puzzle database, one a line:
+//! PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags.
+//! The FEN is the position before the opponent's move that sets the puzzle up,
+//! and the moves, in UCI notation, start with that move and then alternate with the solution.
+
+use crate::board::{Board, Move};
+use crate::pgn::Game;
+use crate::review::Schedule;
+use crate::search::{Limits, Search};
+
+/// The first line of a puzzle file, naming the fields.
+pub const CSV_HEADER: &str =
+    "PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags";
+/// How many centipawns ahead a solution has to leave the side solving it.
+const WINNING: i32 = 300;
+/// How many centipawns ahead counts as crushing rather than just an advantage.
+const CRUSHING: i32 = 600;
+/// How much worse than the solution every other move has to be.
+const MARGIN: i32 = 200;
+/// The most moves a solution asks for.
+const MAX_SOLUTION_MOVES: usize = 3;
+
+/// One puzzle.
+pub struct Puzzle {
+    pub id: String,
+    pub fen: String,
+    /// the opponent's setup move followed by the solution.
+    pub moves: Vec<Move>,
+    pub rating: u32,
+    pub themes: Vec<String>,
+}
+
+impl Puzzle {
+    /// writes the puzzle as a line of a puzzle file, leaving out what only Lichess knows.
+    pub fn to_csv(&self) -> String {
+        let moves: Vec<String> = self.moves.iter().map(|m| m.to_uci()).collect();
+        format!(
+            "{},{},{},{},0,0,0,{},,",
+            self.id,
+            self.fen,
+            moves.join(" "),
+            self.rating,
+            self.themes.join(" ")
+        )
+    }
+}
+
+/// Which puzzles to train on.
+pub struct Filter {
+    /// only puzzles with this theme, like "fork" or "mateIn2".
+    pub theme: Option<String>,
+    pub min_rating: u32,
+    pub max_rating: u32,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            theme: None,
+            min_rating: 0,
+            max_rating: u32::MAX,
+        }
+    }
+}
+
+impl Filter {
+    fn matches(&self, rating: u32, themes: &[String]) -> bool {
+        (self.min_rating..=self.max_rating).contains(&rating)
+            && self
+                .theme
+                .as_ref()
+                .is_none_or(|theme| themes.contains(theme))
+    }
+}
+
+/// reads the puzzles in a CSV file that pass the filter, skipping a header line.
+pub fn read_puzzles(path: &str, filter: &Filter) -> Result<Vec<Puzzle>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let mut puzzles = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.trim().split(',').collect();
+        if line.trim().is_empty() || fields[0] == "PuzzleId" {
+            continue;
+        }
+        let error = |e: &str| format!("{path} line {}: {e}", i + 1);
+        if fields.len() < 8 {
+            return Err(error("not enough fields"));
+        }
+        let rating = fields[3].parse().map_err(|_| error("bad rating"))?;
+        let themes: Vec<String> = fields[7].split_whitespace().map(str::to_owned).collect();
+        if !filter.matches(rating, &themes) {
+            continue;
+        }
+        // the moves are checked against the position as they are read
+        let mut board = Board::from_fen(fields[1]).map_err(|e| error(&e))?;
+        let mut moves = vec![];
+        for uci in fields[2].split_whitespace() {
+            let m = board
+                .parse_uci_move(uci)
+                .ok_or_else(|| error(&format!("illegal move {uci}")))?;
+            board.make_move(m);
+            moves.push(m);
+        }
+        if moves.len() < 2 {
+            return Err(error("no solution"));
+        }
+        puzzles.push(Puzzle {
+            id: fields[0].to_owned(),
+            fen: fields[1].to_owned(),
+            moves,
+            rating,
+            themes,
+        });
+    }
+    if puzzles.is_empty() {
+        return Err(format!("no puzzles in {path} match"));
+    }
+    Ok(puzzles)
+}
+
+/// What became of a move played in a puzzle.
+pub enum Answer {
+    /// the move was right, to be played as the first move, and the opponent replies
+    /// with the second.
+    Correct(Move, Move),
+    /// the move was right, to be played as given, and the puzzle is solved.
+    Solved(Move),
+    /// the move was wrong, and this was the answer.
+    Wrong(Move),
+}
+
+/// Goes through puzzles one after another, checking the moves played against the solutions.
+pub struct Trainer {
+    puzzles: Vec<Puzzle>,
+    /// the index of the puzzle being solved.
+    index: usize,
+    /// how many moves of the puzzle have been played, counting the setup move.
+    played: usize,
+    /// true once the puzzle has been solved or failed.
+    pub finished: bool,
+    pub solved: u32,
+    pub failed: u32,
+}
+
+impl Trainer {
+    /// creates a trainer that goes through the puzzles due for review first,
+    /// then the ones never tried, then the rest.
+    pub fn new(mut puzzles: Vec<Puzzle>, schedule: &Schedule, today: u64) -> Self {
+        puzzles.sort_by_key(|puzzle| schedule.priority(&puzzle.id, today));
+        Self {
+            puzzles,
+            index: 0,
+            played: 0,
+            finished: false,
+            solved: 0,
+            failed: 0,
+        }
+    }
+
+    /// the puzzle being solved.
+    pub fn puzzle(&self) -> &Puzzle {
+        &self.puzzles[self.index]
+    }
+
+    /// the number of puzzles that passed the filter.
+    pub fn count(&self) -> usize {
+        self.puzzles.len()
+    }
+
+    /// moves on to the next puzzle, going back to the first after the last.
+    pub fn next(&mut self) {
+        self.index = (self.index + 1) % self.puzzles.len();
+        self.played = 0;
+        self.finished = false;
+    }
+
+    /// the opponent's move that sets the puzzle up, to be played on the puzzle's position.
+    pub fn setup_move(&mut self) -> Move {
+        self.played = 1;
+        self.finished = false;
+        self.puzzle().moves[0]
+    }
+
+    /// checks a move about to be played on the board against the solution.
+    /// A move that mates is always right, even when the solution mates another way.
+    pub fn answer(&mut self, board: &mut Board, m: Move) -> Answer {
+        let expected = self.puzzle().moves[self.played];
+        board.make_move(m);
+        let mate = board.is_checkmate();
+        board.unmake_move();
+        // pawns clicked to the last rank always become queens, so the solution's piece is used
+        let m = if m.from == expected.from && m.to == expected.to {
+            expected
+        } else if mate {
+            m
+        } else {
+            self.finished = true;
+            self.failed += 1;
+            return Answer::Wrong(expected);
+        };
+        self.played += 2;
+        match self.puzzle().moves.get(self.played - 1) {
+            Some(&reply) if !mate => Answer::Correct(m, reply),
+            _ => {
+                self.finished = true;
+                self.solved += 1;
+                Answer::Solved(m)
+            }
+        }
+    }
+}
+
+/// finds puzzles in a game annotated with glyphs: the position after a blunder, where the
+/// opponent can punish it, and the position before a mistake, where the player may have
+/// missed a win. A position only makes a puzzle when the search finds one clearly winning
+/// move at each step, with every other move well behind it.
+pub fn find_puzzles(
+    game: &Game,
+    search: &mut Search,
+    limits: Limits,
+) -> Result<Vec<Puzzle>, String> {
+    // the second best move decides if the solution is unique
+    search.multi_pv = 2;
+    let mut board = Board::from_fen(&game.start_fen)?;
+    let mut previous: Option<(Board, Move)> = None;
+    // the ply of the last setup move searched, as a blunder answered by another mistake
+    // would have it searched twice
+    let mut searched = None;
+    let mut puzzles = vec![];
+    for (i, &m) in game.moves.iter().enumerate() {
+        let nag = game.nags.get(i).copied().unwrap_or(0);
+        if nag == 2 || nag == 4 {
+            if let Some((before, setup)) = previous.as_ref() {
+                if searched != Some(i - 1) {
+                    puzzles.extend(make_puzzle(before, *setup, search, limits));
+                }
+            }
+        }
+        if nag == 4 {
+            puzzles.extend(make_puzzle(&board, m, search, limits));
+            searched = Some(i);
+        }
+        previous = Some((board.clone(), m));
+        board.make_move(m);
+    }
+    Ok(puzzles)
+}
+
+/// plays the setup move on the board and works out the solution of the puzzle it sets,
+/// if there is one. Puzzles are rated by how many moves they take to solve.
+fn make_puzzle(board: &Board, setup: Move, search: &mut Search, limits: Limits) -> Option<Puzzle> {
+    let fen = board.to_fen();
+    let mut board = board.clone();
+    board.make_move(setup);
+    let id = format!("{:016x}", board.hash);
+    let mut moves = vec![setup];
+    let mut score = 0;
+    for _ in 0..MAX_SOLUTION_MOVES {
+        let info = search.search(&mut board, limits, &mut |_| ());
+        let (best, line) = match info.lines.first() {
+            Some((best, line)) if !line.is_empty() => (*best, line),
+            _ => break,
+        };
+        let second = info.lines.get(1).map(|(second, _)| *second);
+        if best < WINNING || second.is_some_and(|s| s >= WINNING || s > best - MARGIN) {
+            break;
+        }
+        score = best;
+        board.make_move(line[0]);
+        moves.push(line[0]);
+        match line.get(1) {
+            Some(&reply) if !board.is_checkmate() => {
+                board.make_move(reply);
+                moves.push(reply);
+            }
+            _ => break,
+        }
+    }
+    // the solution ends with a move of the side solving it
+    if moves.len() > 1 && moves.len() % 2 == 1 {
+        moves.pop();
+        board.unmake_move();
+    }
+    let solution_moves = moves.len() / 2;
+    if solution_moves == 0 {
+        return None;
+    }
+    let mut themes = vec![];
+    if board.is_checkmate() {
+        themes.push("mate".to_owned());
+        themes.push(format!("mateIn{solution_moves}"));
+    } else if score >= CRUSHING {
+        themes.push("crushing".to_owned());
+    } else {
+        themes.push("advantage".to_owned());
+    }
+    themes.push(
+        match solution_moves {
+            1 => "oneMove",
+            2 => "short",
+            _ => "long",
+        }
+        .to_owned(),
+    );
+    Some(Puzzle {
+        id,
+        fen,
+        moves,
+        rating: 1200 + 300 * (solution_moves as u32 - 1),
+        themes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUZZLES: &str = "\
+PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags
+00sHx,q3k1nr/1pp1nQpp/3p4/1P2p3/4P3/B1PP1b2/B5PP/5K2 b k - 0 17,e8d7 a2e6 d7d8 f7f8,1760,80,83,72,mate mateIn2 middlegame short,https://lichess.org/yyznGmXs/black#34,Italian_Game
+00sJ9,r3r1k1/p4ppp/2p2n2/1p6/3P1qb1/2NQR3/PPB2PP1/R1B3K1 w - - 5 18,e3g3 e8e1 g1h2 e1c1 a1c1 f4h6 h2g1 h6c1,2671,105,87,325,advantage attraction fork middlegame sacrifice veryLong,https://lichess.org/gyFeQsOE#35,French_Defense
+";
+
+    /// writes the text to a file in the temp directory and reads the puzzles in it.
+    fn read(name: &str, text: &str, filter: &Filter) -> Result<Vec<Puzzle>, String> {
+        let path =
+            std::env::temp_dir().join(format!("puzzle-test-{}-{name}.csv", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        read_puzzles(path.to_str().unwrap(), filter)
+    }
+
+    #[test]
+    fn reads_and_filters() {
+        let puzzles = read("all", PUZZLES, &Filter::default()).unwrap();
+        assert_eq!(puzzles.len(), 2);
+        assert_eq!(puzzles[0].id, "00sHx");
+        assert_eq!(puzzles[0].moves.len(), 4);
+        assert_eq!(puzzles[1].rating, 2671);
+        let filter = Filter {
+            theme: Some("fork".to_owned()),
+            ..Filter::default()
+        };
+        assert_eq!(read("fork", PUZZLES, &filter).unwrap()[0].id, "00sJ9");
+        let filter = Filter {
+            max_rating: 2000,
+            ..Filter::default()
+        };
+        assert_eq!(read("easy", PUZZLES, &filter).unwrap().len(), 1);
+        let filter = Filter {
+            min_rating: 3000,
+            ..Filter::default()
+        };
+        assert!(read("hard", PUZZLES, &filter).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        let filter = Filter::default();
+        assert!(read("short", "1,8/8/8/8/8/8/8/8 w - - 0 1\n", &filter).is_err());
+        let illegal = "1,4k3/8/8/8/8/8/8/4K3 w - - 0 1,e1e3 e8e7,1500,0,0,0,endgame,,\n";
+        let error = read("illegal", illegal, &filter).err().unwrap();
+        assert!(error.contains("illegal move e1e3"));
+    }
+
+    #[test]
+    fn trains() {
+        let puzzles = read("train", PUZZLES, &Filter::default()).unwrap();
+        let mut trainer = Trainer::new(puzzles, &Schedule::default(), 0);
+        assert_eq!(trainer.puzzle().id, "00sHx");
+        let mut board = Board::from_fen(&trainer.puzzle().fen).unwrap();
+        board.make_move(trainer.setup_move());
+        let m = board.parse_uci_move("a2e6").unwrap();
+        match trainer.answer(&mut board, m) {
+            Answer::Correct(m, reply) => {
+                board.make_move(m);
+                board.make_move(reply);
+            }
+            _ => panic!("a2e6 is the first move"),
+        }
+        let m = board.parse_uci_move("f7f8").unwrap();
+        assert!(matches!(trainer.answer(&mut board, m), Answer::Solved(_)));
+        assert!(trainer.finished);
+
+        trainer.next();
+        let mut board = Board::from_fen(&trainer.puzzle().fen).unwrap();
+        board.make_move(trainer.setup_move());
+        let m = board.parse_uci_move("a7a6").unwrap();
+        assert!(matches!(trainer.answer(&mut board, m), Answer::Wrong(_)));
+        assert_eq!((trainer.solved, trainer.failed), (1, 1));
+    }
+
+    #[test]
+    fn finds_the_punishment_of_a_blunder_once() {
+        let text = "1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0";
+        let mut game = crate::pgn::read_games(text).remove(0).unwrap();
+        game.nags = vec![0, 0, 0, 0, 0, 4, 0];
+        let mut search = Search::default();
+        let limits = Limits {
+            depth: Some(3),
+            ..Limits::default()
+        };
+        let puzzles = find_puzzles(&game, &mut search, limits).unwrap();
+        assert_eq!(puzzles.len(), 1);
+        let puzzle = &puzzles[0];
+        let moves: Vec<String> = puzzle.moves.iter().map(|m| m.to_uci()).collect();
+        assert_eq!(moves, ["g8f6", "h5f7"]);
+        assert_eq!(puzzle.themes, ["mate", "mateIn1", "oneMove"]);
+        assert_eq!(puzzle.rating, 1200);
+
+        // the mate marked as a mistake too has the same position before it
+        game.nags[6] = 2;
+        assert_eq!(find_puzzles(&game, &mut search, limits).unwrap().len(), 1);
+        let line = puzzle.to_csv();
+        assert!(line.starts_with(&format!("{},{},g8f6 h5f7,1200,", puzzle.id, puzzle.fen)));
+    }
+}
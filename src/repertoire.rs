@@ -0,0 +1,200 @@
+//! Opening repertoire training. A repertoire is a PGN file whose games and variations hold
+//! the moves prepared for one side and the replies expected from the other. The trainer
+//! plays the replies and checks that the prepared moves come back, line by line, with the
+//! lines scheduled for review like puzzles.
+
+use crate::board::{Board, Color, Move};
+use crate::pgn::{self, Variation};
+use crate::review::Schedule;
+
+/// Every line of a repertoire, from its start to the end of a variation.
+pub struct Repertoire {
+    pub start_fen: String,
+    lines: Vec<Vec<Move>>,
+}
+
+impl Repertoire {
+    /// reads the lines of every game in a PGN file, which all have to start from the same
+    /// position.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+        let mut start_fen = None;
+        let mut lines = vec![];
+        for game in pgn::read_games(&text) {
+            let game = game.map_err(|e| format!("{path}: {e}"))?;
+            match start_fen.as_ref() {
+                Some(fen) if *fen != game.start_fen => {
+                    return Err(format!(
+                        "the games in {path} start from different positions"
+                    ))
+                }
+                _ => start_fen = Some(game.start_fen.clone()),
+            }
+            let line = Variation {
+                comment: String::new(),
+                moves: game.moves,
+                comments: game.comments,
+                nags: game.nags,
+                variations: game.variations,
+            };
+            add_lines(&line, &mut vec![], &mut lines);
+        }
+        // a line that another one goes on from is trained as part of the longer one
+        let mut unique: Vec<Vec<Move>> = vec![];
+        for line in lines.iter() {
+            let longer = lines
+                .iter()
+                .any(|other| other.len() > line.len() && other.starts_with(line));
+            if !line.is_empty() && !longer && !unique.contains(line) {
+                unique.push(line.clone());
+            }
+        }
+        match start_fen {
+            Some(start_fen) if !unique.is_empty() => Ok(Self {
+                start_fen,
+                lines: unique,
+            }),
+            _ => Err(format!("there are no moves in {path}")),
+        }
+    }
+}
+
+/// adds the line that goes on from the moves before it to the end of a variation,
+/// and the lines of its variations.
+fn add_lines(variation: &Variation, before: &mut Vec<Move>, lines: &mut Vec<Vec<Move>>) {
+    let start = before.len();
+    for (i, &m) in variation.moves.iter().enumerate() {
+        for other in variation.variations.get(i).into_iter().flatten() {
+            add_lines(other, before, lines);
+        }
+        before.push(m);
+    }
+    lines.push(before.clone());
+    before.truncate(start);
+}
+
+/// Quizzes one side of a repertoire a line at a time.
+pub struct Quiz {
+    repertoire: Repertoire,
+    /// the side whose prepared moves are asked for.
+    pub color: Color,
+    /// the index of the line being trained.
+    line: usize,
+    /// the moves played so far in the line.
+    played: Vec<Move>,
+    /// true once a prepared move was missed in the line.
+    pub missed: bool,
+    /// true once the end of the line is reached.
+    pub finished: bool,
+}
+
+impl Quiz {
+    /// creates a quiz that has not started a line yet.
+    pub fn new(repertoire: Repertoire) -> Self {
+        Self {
+            repertoire,
+            color: Color::White,
+            line: 0,
+            played: vec![],
+            missed: false,
+            finished: false,
+        }
+    }
+
+    /// the position every line starts from.
+    pub fn start_fen(&self) -> &str {
+        &self.repertoire.start_fen
+    }
+
+    /// the number of lines with moves prepared for the side being quizzed.
+    pub fn count(&self) -> usize {
+        self.candidates(&[]).len()
+    }
+
+    /// the id the line being trained is scheduled under.
+    pub fn line_id(&self) -> String {
+        self.id(self.line)
+    }
+
+    /// the id a line is scheduled under, which is the hash of the position it ends in.
+    fn id(&self, line: usize) -> String {
+        let mut board = Board::from_fen(&self.repertoire.start_fen).unwrap();
+        for &m in self.repertoire.lines[line].iter() {
+            board.make_move(m);
+        }
+        format!("line-{:016x}", board.hash)
+    }
+
+    /// the moves of the line being trained.
+    pub fn line(&self) -> &[Move] {
+        &self.repertoire.lines[self.line]
+    }
+
+    /// starts the line for the side that is due for review first.
+    pub fn start(&mut self, color: Color, schedule: &Schedule, today: u64) -> Result<(), String> {
+        self.color = color;
+        self.played.clear();
+        self.missed = false;
+        self.finished = false;
+        match self.pick(&[], schedule, today) {
+            Some(line) => {
+                self.line = line;
+                Ok(())
+            }
+            None => {
+                self.finished = true;
+                Err(format!("there are no {color:?} moves in the repertoire"))
+            }
+        }
+    }
+
+    /// the indices of the lines that start with the moves and have a move of the side
+    /// being quizzed in them.
+    fn candidates(&self, moves: &[Move]) -> Vec<usize> {
+        let start = Board::from_fen(&self.repertoire.start_fen).unwrap();
+        // the quizzed side's first move is the first or the second of the line
+        let first = if start.color == self.color { 0 } else { 1 };
+        (0..self.repertoire.lines.len())
+            .filter(|&i| {
+                let line = &self.repertoire.lines[i];
+                line.starts_with(moves) && line.len() > first
+            })
+            .collect()
+    }
+
+    /// the line starting with the moves that is due for review first.
+    fn pick(&self, moves: &[Move], schedule: &Schedule, today: u64) -> Option<usize> {
+        let mut candidates = self.candidates(moves);
+        candidates.sort_by_key(|&i| schedule.priority(&self.id(i), today));
+        candidates.first().copied()
+    }
+
+    /// the other side's next move in the line, which is taken as played.
+    pub fn reply(&mut self) -> Option<Move> {
+        let m = *self.line().get(self.played.len())?;
+        self.played.push(m);
+        self.finished = self.played.len() == self.line().len();
+        Some(m)
+    }
+
+    /// checks a move of the side being quizzed. A move prepared in another line is right too,
+    /// and the quiz goes on with that line. Otherwise the prepared move is given back.
+    pub fn check(&mut self, m: Move, schedule: &Schedule, today: u64) -> Result<(), Move> {
+        let expected = self.line()[self.played.len()];
+        if m != expected {
+            let mut moves = self.played.clone();
+            moves.push(m);
+            match self.pick(&moves, schedule, today) {
+                Some(line) => self.line = line,
+                None => {
+                    self.missed = true;
+                    return Err(expected);
+                }
+            }
+        }
+        self.played.push(m);
+        self.finished = self.played.len() == self.line().len();
+        Ok(())
+    }
+}
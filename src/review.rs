@@ -0,0 +1,250 @@
+//! Spaced repetition for training items like puzzles and repertoire lines, scheduled with
+//! the SM-2 algorithm: an item that is remembered comes back after a longer and longer
+//! interval, and one that is forgotten starts again from a day.
+//! The history is kept in a text file with a line for each item:
+//! the item's id (which has no spaces), the interval in days, the number of reviews in a row
+//! it was remembered, its ease, the day it is due, and how often it was reviewed and forgotten.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The quality of a review of an item that was remembered, on SM-2's scale of 0 to 5.
+pub const REMEMBERED: u32 = 4;
+/// The quality of a review of an item that was forgotten.
+pub const FORGOTTEN: u32 = 1;
+/// The ease new items start with, which multiplies the interval after each review.
+const START_EASE: f64 = 2.5;
+/// The lowest ease an item can get down to.
+const MIN_EASE: f64 = 1.3;
+/// How many days apart reviews have to be for an item to count as learned well.
+const MATURE_INTERVAL: u32 = 21;
+
+/// The review history of one item.
+#[derive(Clone, Copy)]
+struct Card {
+    interval: u32,
+    repetitions: u32,
+    ease: f64,
+    /// the day the item is next due, counted from 1970.
+    due: u64,
+    reviews: u32,
+    lapses: u32,
+}
+
+/// How the reviews have gone, for the statistics shown while training.
+pub struct Stats {
+    /// the number of items that have been reviewed.
+    pub items: usize,
+    /// how many of them are due today or earlier.
+    pub due: usize,
+    /// how many of them come back three weeks or more apart.
+    pub mature: usize,
+    pub reviews: u32,
+    /// the share of reviews in percent where the item was remembered.
+    pub retention: f64,
+}
+
+/// The review history of every item that has been trained.
+#[derive(Default)]
+pub struct Schedule {
+    cards: HashMap<String, Card>,
+}
+
+/// today's day number, counted from 1970.
+pub fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() / (24 * 60 * 60))
+}
+
+impl Schedule {
+    /// reads the history from a file, starting an empty one if there is no file yet.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("could not read {path}: {e}")),
+        };
+        let mut cards = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let error = || format!("{path} line {}: bad review history", i + 1);
+            if fields.len() != 7 {
+                return Err(error());
+            }
+            let number = |i: usize| fields[i].parse::<u64>().map_err(|_| error());
+            let card = Card {
+                interval: number(1)? as u32,
+                repetitions: number(2)? as u32,
+                ease: fields[3].parse().map_err(|_| error())?,
+                due: number(4)?,
+                reviews: number(5)? as u32,
+                lapses: number(6)? as u32,
+            };
+            cards.insert(fields[0].to_owned(), card);
+        }
+        Ok(Self { cards })
+    }
+
+    /// writes the history to a file, which is replaced.
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let mut ids: Vec<&String> = self.cards.keys().collect();
+        ids.sort();
+        let mut out = String::new();
+        for id in ids {
+            let card = &self.cards[id];
+            out += &format!(
+                "{id} {} {} {:.2} {} {} {}\n",
+                card.interval, card.repetitions, card.ease, card.due, card.reviews, card.lapses
+            );
+        }
+        std::fs::write(path, out).map_err(|e| format!("could not write {path}: {e}"))
+    }
+
+    /// records a review of an item with a quality from 0 to 5, 3 or more meaning it was
+    /// remembered, and works out when it is due next.
+    pub fn review(&mut self, id: &str, quality: u32, today: u64) {
+        let card = self.cards.entry(id.to_owned()).or_insert(Card {
+            interval: 0,
+            repetitions: 0,
+            ease: START_EASE,
+            due: today,
+            reviews: 0,
+            lapses: 0,
+        });
+        card.reviews += 1;
+        if quality >= 3 {
+            card.interval = match card.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (card.interval as f64 * card.ease).round() as u32,
+            };
+            card.repetitions += 1;
+        } else {
+            card.interval = 1;
+            card.repetitions = 0;
+            card.lapses += 1;
+        }
+        let missed = 5. - quality.min(5) as f64;
+        card.ease = (card.ease + 0.1 - missed * (0.08 + missed * 0.02)).max(MIN_EASE);
+        card.due = today + card.interval as u64;
+    }
+
+    /// a key to sort items by so the ones to train come first: items that are due,
+    /// the longest overdue first, then items never seen, then the rest by when they are due.
+    pub fn priority(&self, id: &str, today: u64) -> (u8, u64) {
+        match self.cards.get(id) {
+            Some(card) if card.due <= today => (0, card.due),
+            Some(card) => (2, card.due),
+            None => (1, 0),
+        }
+    }
+
+    /// how the reviews of every item have gone.
+    pub fn stats(&self, today: u64) -> Stats {
+        let cards = self.cards.values();
+        let reviews: u32 = cards.clone().map(|card| card.reviews).sum();
+        let lapses: u32 = cards.clone().map(|card| card.lapses).sum();
+        Stats {
+            items: self.cards.len(),
+            due: cards.clone().filter(|card| card.due <= today).count(),
+            mature: cards
+                .filter(|card| card.interval >= MATURE_INTERVAL)
+                .count(),
+            reviews,
+            retention: if reviews == 0 {
+                0.
+            } else {
+                100. * (reviews - lapses) as f64 / reviews as f64
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// reviews an item with the same quality each time it is due and returns the intervals.
+    fn intervals(schedule: &mut Schedule, quality: u32, count: usize) -> Vec<u32> {
+        let mut day = schedule.cards.get("item").map_or(100, |card| card.due);
+        let mut intervals = vec![];
+        for _ in 0..count {
+            schedule.review("item", quality, day);
+            let card = schedule.cards["item"];
+            intervals.push(card.interval);
+            day = card.due;
+        }
+        intervals
+    }
+
+    #[test]
+    fn remembered_items_come_back_later_and_later() {
+        let mut schedule = Schedule::default();
+        // a quality of 4 keeps the ease at 2.5
+        assert_eq!(intervals(&mut schedule, REMEMBERED, 5), [1, 6, 15, 38, 95]);
+        assert_eq!(schedule.cards["item"].ease, START_EASE);
+    }
+
+    #[test]
+    fn forgotten_items_start_again() {
+        let mut schedule = Schedule::default();
+        intervals(&mut schedule, REMEMBERED, 3);
+        assert_eq!(intervals(&mut schedule, FORGOTTEN, 1), [1]);
+        let card = schedule.cards["item"];
+        assert_eq!((card.repetitions, card.lapses, card.reviews), (0, 1, 4));
+        assert!((card.ease - 1.96).abs() < 1e-9);
+        // the lower ease makes the intervals grow slower
+        assert_eq!(intervals(&mut schedule, REMEMBERED, 3), [1, 6, 12]);
+        // and it never goes below the floor
+        intervals(&mut schedule, FORGOTTEN, 10);
+        assert_eq!(schedule.cards["item"].ease, MIN_EASE);
+    }
+
+    #[test]
+    fn due_items_come_first() {
+        let mut schedule = Schedule::default();
+        schedule.review("early", REMEMBERED, 100);
+        schedule.review("late", REMEMBERED, 110);
+        assert_eq!(schedule.priority("early", 101), (0, 101));
+        assert_eq!(schedule.priority("new", 101), (1, 0));
+        assert_eq!(schedule.priority("late", 101), (2, 111));
+        let stats = schedule.stats(101);
+        assert_eq!((stats.items, stats.due, stats.reviews), (2, 1, 2));
+    }
+
+    #[test]
+    fn history_round_trip() {
+        let mut schedule = Schedule::default();
+        intervals(&mut schedule, REMEMBERED, 4);
+        schedule.review("other", FORGOTTEN, 100);
+        let path = std::env::temp_dir().join(format!("reviews-test-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        schedule.write(path).unwrap();
+        let read = Schedule::read(path).unwrap();
+        let _ = std::fs::remove_file(path);
+        for id in ["item", "other"] {
+            let (card, read) = (schedule.cards[id], read.cards[id]);
+            assert_eq!(
+                (
+                    card.interval,
+                    card.repetitions,
+                    card.due,
+                    card.reviews,
+                    card.lapses
+                ),
+                (
+                    read.interval,
+                    read.repetitions,
+                    read.due,
+                    read.reviews,
+                    read.lapses
+                )
+            );
+            assert!((card.ease - read.ease).abs() < 0.01);
+        }
+    }
+}
@@ -0,0 +1,912 @@
+//! The engine's search for the best move.
+//! It is an iterative deepening alpha-beta search with a transposition table,
+//! a quiescence search, and a set of pruning and move ordering tricks that can
+//! each be turned off to measure what they are worth.
+
+use crate::board::{square_index, Board, Move, Type};
+use crate::dtm::{DtmTables, Outcome};
+use crate::eval::{self, Weights};
+use crate::nnue::Network;
+use crate::syzygy::{self, Tablebase};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The score of delivering checkmate right now.
+pub const MATE: i32 = 30000;
+/// Larger than any score a search can return.
+pub const INFINITY: i32 = 32000;
+/// The deepest the search will ever go, counting extensions.
+pub const MAX_PLY: usize = 128;
+/// Scores beyond this are mates.
+const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+/// The score of a tablebase win, low enough that it is never taken for a mate.
+const TABLEBASE_WIN: i32 = MATE_BOUND - MAX_PLY as i32 - 1;
+
+/// the score of a tablebase result at a ply, preferring wins that are found sooner.
+/// Wins and losses the fifty move rule turns into draws score as draws.
+fn tablebase_score(wdl: i32, ply: usize) -> i32 {
+    match wdl {
+        syzygy::WIN => TABLEBASE_WIN - ply as i32,
+        syzygy::LOSS => -TABLEBASE_WIN + ply as i32,
+        _ => 0,
+    }
+}
+
+/// the score of a depth to mate table result at a ply, the same as a mate found by searching.
+fn dtm_score(outcome: Outcome, ply: usize) -> i32 {
+    match outcome {
+        Outcome::Win(plies) => MATE - ply as i32 - plies as i32,
+        Outcome::Draw => 0,
+        Outcome::Loss(plies) => -MATE + ply as i32 + plies as i32,
+    }
+}
+
+/// Switches for each of the search's pruning and move ordering tricks.
+#[derive(Copy, Clone, Debug)]
+pub struct SearchOptions {
+    /// try captures of valuable pieces by cheap pieces first.
+    pub mvv_lva: bool,
+    /// try quiet moves that caused a cutoff at the same ply first.
+    pub killers: bool,
+    /// try quiet moves that have caused many cutoffs anywhere first.
+    pub history: bool,
+    /// skip a turn and prune if the position is still too good for the opponent.
+    pub null_move: bool,
+    /// search late quiet moves less deep.
+    pub late_move_reductions: bool,
+    /// search one ply deeper after a check.
+    pub check_extensions: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            mvv_lva: true,
+            killers: true,
+            history: true,
+            null_move: true,
+            late_move_reductions: true,
+            check_extensions: true,
+        }
+    }
+}
+
+/// When the search has to stop. Anything left as None is not a limit.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+}
+
+/// What the search knows after finishing a depth.
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: u32,
+    /// from the point of view of the side to move.
+    pub score: i32,
+    pub nodes: u64,
+    pub time: Duration,
+    /// the principal variation, the line both sides are expected to play.
+    pub pv: Vec<Move>,
+    /// the best lines from the root with their scores, best first, so the first one
+    /// is score and pv. There are more than one when Search::multi_pv asks for them.
+    pub lines: Vec<(i32, Vec<Move>)>,
+}
+
+/// the number of moves until mate if the score is a mate score,
+/// negative when the side to move is getting mated.
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score > MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score < -MATE_BOUND {
+        Some(-(MATE + score + 1) / 2)
+    } else {
+        None
+    }
+}
+
+/// How a stored score relates to the real score of the position.
+#[derive(Copy, Clone, PartialEq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+/// A position remembered in the transposition table.
+#[derive(Copy, Clone)]
+struct Entry {
+    key: u64,
+    mv: Option<Move>,
+    score: i32,
+    depth: i32,
+    bound: Bound,
+}
+
+/// The types a promotion is packed as, by their index.
+const TYPES: [Type; 6] = [
+    Type::Pawn,
+    Type::Rook,
+    Type::Knight,
+    Type::Bishop,
+    Type::Queen,
+    Type::King,
+];
+
+impl Entry {
+    /// packs everything but the key into 64 bits, never all zero.
+    fn pack(self) -> u64 {
+        let mv = match self.mv {
+            Some(m) => {
+                let promotion = m.promotion.map_or(0, |kind| kind.index() as u64 + 1);
+                1 << 15
+                    | square_index(m.from) as u64
+                    | (square_index(m.to) as u64) << 6
+                    | promotion << 12
+            }
+            None => 0,
+        };
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        mv | (self.score as i16 as u16 as u64) << 16
+            | (self.depth.clamp(-128, 127) as i8 as u8 as u64) << 32
+            | bound << 40
+            | 1 << 42
+    }
+
+    fn unpack(key: u64, data: u64) -> Self {
+        let square = |bits: u64| [(bits & 7) as i8, (bits >> 3 & 7) as i8];
+        let mv = match data & 1 << 15 {
+            0 => None,
+            _ => Some(Move {
+                from: square(data),
+                to: square(data >> 6),
+                promotion: match data >> 12 & 7 {
+                    0 => None,
+                    kind => Some(TYPES[kind as usize - 1]),
+                },
+            }),
+        };
+        Self {
+            key,
+            mv,
+            score: (data >> 16) as u16 as i16 as i32,
+            depth: (data >> 32) as u8 as i8 as i32,
+            bound: match data >> 40 & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+        }
+    }
+}
+
+/// A hash table of positions that have already been searched.
+/// It can be shared by threads searching at the same time. Each slot holds the key
+/// xored with the packed entry and then the packed entry, so a slot that two threads
+/// wrote to at once no longer matches its key and is skipped.
+pub struct TranspositionTable {
+    slots: Vec<[AtomicU64; 2]>,
+}
+
+impl TranspositionTable {
+    /// creates a table that takes up about the given number of megabytes.
+    pub fn new(megabytes: usize) -> Self {
+        let size = (megabytes * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()).max(1);
+        Self {
+            slots: (0..size)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    /// forgets every position.
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot[0].store(0, Ordering::Relaxed);
+            slot[1].store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn probe(&self, key: u64) -> Option<Entry> {
+        let slot = &self.slots[(key % self.slots.len() as u64) as usize];
+        let data = slot[1].load(Ordering::Relaxed);
+        if data != 0 && slot[0].load(Ordering::Relaxed) ^ data == key {
+            Some(Entry::unpack(key, data))
+        } else {
+            None
+        }
+    }
+
+    fn store(&self, entry: Entry) {
+        // keep deeper results of the same position
+        if let Some(old) = self.probe(entry.key) {
+            if old.depth > entry.depth && entry.bound != Bound::Exact {
+                return;
+            }
+        }
+        let slot = &self.slots[(entry.key % self.slots.len() as u64) as usize];
+        let data = entry.pack();
+        slot[0].store(entry.key ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+}
+
+/// picks the move most threads agree on, counting deeper and better scoring results
+/// for more. Ties go to the thread that comes first, so the pick is always the same.
+fn vote(results: &[SearchInfo]) -> SearchInfo {
+    let worst = results.iter().map(|info| info.score).min().unwrap();
+    let mut votes: Vec<(Move, i64)> = vec![];
+    for info in results.iter() {
+        if let Some(&m) = info.pv.first() {
+            let weight = (info.score - worst + 20) as i64 * info.depth as i64;
+            match votes.iter_mut().find(|(voted, _)| *voted == m) {
+                Some((_, total)) => *total += weight,
+                None => votes.push((m, weight)),
+            }
+        }
+    }
+    let most = votes.iter().map(|&(_, total)| total).max();
+    let winner = votes.iter().find(|&&(_, total)| Some(total) == most);
+    match winner {
+        Some(&(m, _)) => results
+            .iter()
+            .find(|info| info.pv.first() == Some(&m))
+            .unwrap()
+            .clone(),
+        None => results[0].clone(),
+    }
+}
+
+/// mate scores are stored relative to the position instead of the root.
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_BOUND {
+        score + ply as i32
+    } else if score < -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_BOUND {
+        score - ply as i32
+    } else if score < -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// The state of the search that lasts between moves.
+pub struct Search {
+    pub options: SearchOptions,
+    pub weights: Weights,
+    /// set to true from another thread to stop the search early.
+    /// It is never cleared by the search itself.
+    pub stop: Arc<AtomicBool>,
+    /// endgame tables to look positions up in once few enough pieces are left.
+    pub tablebase: Option<Arc<Tablebase>>,
+    /// depth to mate tables for the smallest endings, tried before the tablebases.
+    pub dtm: Option<Arc<DtmTables>>,
+    /// the network to evaluate with instead of the weights.
+    pub network: Option<Arc<Network>>,
+    /// how many threads search at once, sharing the transposition table unless only
+    /// a depth is given.
+    pub threads: usize,
+    /// how many of the best root moves get a line of their own.
+    pub multi_pv: usize,
+    /// root moves left out because they already have a line at this depth.
+    excluded: Vec<Move>,
+    /// how far a helper thread turns the order of the root moves, so the threads
+    /// look at different moves first.
+    root_rotation: usize,
+    stopped: bool,
+    tt: Arc<TranspositionTable>,
+    killers: Vec<[Option<Move>; 2]>,
+    history: Vec<[i32; 64]>,
+    pv: Vec<Vec<Move>>,
+    nodes: u64,
+    start: Instant,
+    limits: Limits,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self::new(SearchOptions::default())
+    }
+}
+
+impl Search {
+    /// creates a search with a 16 megabyte transposition table.
+    pub fn new(options: SearchOptions) -> Self {
+        Self {
+            options,
+            weights: Weights::default(),
+            stop: Arc::new(AtomicBool::new(false)),
+            tablebase: None,
+            dtm: None,
+            network: None,
+            threads: 1,
+            multi_pv: 1,
+            excluded: vec![],
+            root_rotation: 0,
+            stopped: false,
+            tt: Arc::new(TranspositionTable::new(16)),
+            killers: vec![[None; 2]; MAX_PLY],
+            history: vec![[0; 64]; 64],
+            pv: vec![vec![]; MAX_PLY + 1],
+            nodes: 0,
+            start: Instant::now(),
+            limits: Limits::default(),
+        }
+    }
+
+    /// replaces the transposition table with one of the given size in megabytes.
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.tt = Arc::new(TranspositionTable::new(megabytes));
+    }
+
+    /// forgets everything learned in earlier searches, for when a new game starts.
+    pub fn clear(&mut self) {
+        self.tt.clear();
+        self.killers = vec![[None; 2]; MAX_PLY];
+        self.history = vec![[0; 64]; 64];
+    }
+
+    /// searches the position until a limit is reached and returns the last completed result.
+    /// report is called after every completed depth of the main thread.
+    ///
+    /// With more than one thread, helper threads search the same position and share
+    /// the transposition table, so the main thread finds more of the tree already done
+    /// (Lazy SMP). The deepest result wins. The order the threads write the table in
+    /// changes from run to run, so when only a depth is given the threads keep their own
+    /// tables instead, and their moves are voted on the same way every time.
+    pub fn search(
+        &mut self,
+        board: &mut Board,
+        limits: Limits,
+        report: &mut dyn FnMut(&SearchInfo),
+    ) -> SearchInfo {
+        // the network's hidden layers follow the moves the search makes on the board
+        board.set_network(self.network.clone());
+        let info = self.search_threads(board, limits, report);
+        board.set_network(None);
+        info
+    }
+
+    /// runs the main thread's search along with any helper threads.
+    fn search_threads(
+        &mut self,
+        board: &mut Board,
+        limits: Limits,
+        report: &mut dyn FnMut(&SearchInfo),
+    ) -> SearchInfo {
+        if self.threads <= 1 {
+            return self.iterate(board, limits, report);
+        }
+        let fixed_depth =
+            limits.depth.is_some() && limits.nodes.is_none() && limits.movetime.is_none();
+        let helper_stop = Arc::new(AtomicBool::new(false));
+        let mut helpers: Vec<Search> = (1..self.threads)
+            .map(|i| self.helper(i, fixed_depth, helper_stop.clone()))
+            .collect();
+        let helper_limits = if fixed_depth {
+            limits
+        } else {
+            Limits::default()
+        };
+        let (main, results) = thread::scope(|scope| {
+            let threads: Vec<_> = helpers
+                .iter_mut()
+                .map(|helper| {
+                    let mut board = board.clone();
+                    scope.spawn(move || helper.iterate(&mut board, helper_limits, &mut |_| ()))
+                })
+                .collect();
+            let main = self.iterate(board, limits, report);
+            // at a fixed depth the helpers finish on their own unless told to stop
+            if !fixed_depth || self.stop.load(Ordering::Relaxed) {
+                helper_stop.store(true, Ordering::Relaxed);
+            }
+            let results: Vec<SearchInfo> = threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect();
+            (main, results)
+        });
+
+        let nodes = main.nodes + results.iter().map(|info| info.nodes).sum::<u64>();
+        let mut all = vec![main.clone()];
+        all.extend(results);
+        // only the main thread looks for more than one line
+        let mut best = if self.multi_pv > 1 {
+            main.clone()
+        } else if fixed_depth {
+            vote(&all)
+        } else {
+            // the first of the deepest, which is the main thread's if it got as deep
+            let deepest = all.iter().map(|info| info.depth).max().unwrap();
+            all.into_iter().find(|info| info.depth == deepest).unwrap()
+        };
+        best.nodes = nodes;
+        best.time = main.time;
+        if best.pv != main.pv {
+            report(&best);
+        }
+        best
+    }
+
+    /// creates a search for a helper thread with its own killers and history,
+    /// sharing the table unless it should have its own.
+    fn helper(&self, index: usize, own_table: bool, stop: Arc<AtomicBool>) -> Search {
+        let mut helper = Search::new(self.options);
+        helper.weights = self.weights.clone();
+        helper.stop = stop;
+        helper.tablebase = self.tablebase.clone();
+        helper.dtm = self.dtm.clone();
+        helper.network = self.network.clone();
+        helper.root_rotation = index;
+        helper.tt = if own_table {
+            let megabytes =
+                self.tt.slots.len() * std::mem::size_of::<[AtomicU64; 2]>() / (1024 * 1024);
+            Arc::new(TranspositionTable::new(megabytes.max(1)))
+        } else {
+            self.tt.clone()
+        };
+        helper
+    }
+
+    /// the iterative deepening search of one thread.
+    fn iterate(
+        &mut self,
+        board: &mut Board,
+        limits: Limits,
+        report: &mut dyn FnMut(&SearchInfo),
+    ) -> SearchInfo {
+        self.nodes = 0;
+        self.stopped = false;
+        self.start = Instant::now();
+        self.limits = limits;
+        self.killers = vec![[None; 2]; MAX_PLY];
+        for row in self.history.iter_mut() {
+            for value in row.iter_mut() {
+                *value /= 8;
+            }
+        }
+        let max_depth = limits.depth.unwrap_or(MAX_PLY as u32 - 1).max(1);
+        let mut best = SearchInfo {
+            depth: 0,
+            score: 0,
+            nodes: 0,
+            time: Duration::from_secs(0),
+            pv: vec![],
+            lines: vec![],
+        };
+        // the tables already know the best move, so there is nothing to search
+        if let Some((m, outcome)) = self.dtm.as_ref().and_then(|dtm| dtm.best_move(board)) {
+            best.depth = 1;
+            best.score = dtm_score(outcome, 0);
+            best.pv = vec![m];
+            best.lines = vec![(best.score, best.pv.clone())];
+            report(&best);
+            return best;
+        }
+        if let Some((m, wdl)) = self.tablebase.as_ref().and_then(|tb| tb.best_move(board)) {
+            best.depth = 1;
+            best.score = tablebase_score(wdl, 0);
+            best.pv = vec![m];
+            best.lines = vec![(best.score, best.pv.clone())];
+            report(&best);
+            return best;
+        }
+        let wanted = self.multi_pv.clamp(1, board.legal_moves().len().max(1));
+        for depth in 1..=max_depth {
+            // each line searches the root again without the moves of the lines before it
+            let mut lines = vec![];
+            while lines.len() < wanted {
+                let score = self.alpha_beta(board, -INFINITY, INFINITY, depth as i32, 0, true);
+                // a search that was cut short can not be trusted, except that the first move
+                // it looked at is the best move of the last depth
+                if self.stopped && (depth > 1 || !lines.is_empty()) {
+                    break;
+                }
+                lines.push((score, self.pv[0].clone()));
+                match self.pv[0].first() {
+                    Some(&m) if !self.stopped => self.excluded.push(m),
+                    _ => break,
+                }
+            }
+            self.excluded.clear();
+            if lines.len() < wanted && depth > 1 {
+                break;
+            }
+            lines.sort_by_key(|&(score, _)| -score);
+            let (score, pv) = lines[0].clone();
+            best = SearchInfo {
+                depth,
+                score,
+                nodes: self.nodes,
+                time: self.start.elapsed(),
+                pv,
+                lines,
+            };
+            report(&best);
+            if self.stopped
+                || self.should_stop()
+                || mate_in(score).is_some_and(|n| n.unsigned_abs() * 2 <= depth)
+            {
+                break;
+            }
+        }
+        best.nodes = self.nodes;
+        best.time = self.start.elapsed();
+        if best.pv.is_empty() {
+            // always give an answer if there is a legal move
+            best.pv = board.legal_moves().into_iter().take(1).collect();
+        }
+        best
+    }
+
+    /// true once a limit has been reached or the search was told to stop.
+    fn should_stop(&self) -> bool {
+        if self.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        if let Some(nodes) = self.limits.nodes {
+            if self.nodes >= nodes {
+                return true;
+            }
+        }
+        if let Some(movetime) = self.limits.movetime {
+            if self.start.elapsed() >= movetime {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// the negamax alpha-beta search.
+    fn alpha_beta(
+        &mut self,
+        board: &mut Board,
+        mut alpha: i32,
+        beta: i32,
+        mut depth: i32,
+        ply: usize,
+        allow_null: bool,
+    ) -> i32 {
+        self.pv[ply].clear();
+        if self.nodes.is_multiple_of(2048) && self.should_stop() {
+            self.stopped = true;
+        }
+        if self.stopped {
+            return 0;
+        }
+        if ply > 0 && (board.halfmove_clock >= 100 || board.repetitions() > 0) {
+            return 0;
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate(board);
+        }
+        let in_check = board.is_in_check();
+        if in_check && self.options.check_extensions && ply > 0 {
+            depth += 1;
+        }
+        if depth <= 0 {
+            return self.quiescence(board, alpha, beta, ply);
+        }
+        self.nodes += 1;
+        let pv_node = beta - alpha > 1;
+
+        if ply > 0 {
+            if let Some(outcome) = self.dtm.as_ref().and_then(|dtm| dtm.probe(board)) {
+                return dtm_score(outcome, ply);
+            }
+        }
+        // the tables only hold results for positions just after a capture or pawn move,
+        // where the fifty move rule starts over
+        if ply > 0 && board.halfmove_clock == 0 {
+            if let Some(wdl) = self.tablebase.as_ref().and_then(|tb| tb.probe_wdl(board)) {
+                return tablebase_score(wdl, ply);
+            }
+        }
+
+        let entry = self.tt.probe(board.hash);
+        if let Some(entry) = entry {
+            let score = score_from_tt(entry.score, ply);
+            if ply > 0 && !pv_node && entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => (),
+                }
+            }
+        }
+
+        if self.options.null_move
+            && allow_null
+            && !pv_node
+            && !in_check
+            && depth >= 3
+            && beta.abs() < MATE_BOUND
+            && board.has_non_pawn_material(board.color)
+        {
+            let reduction = 2 + depth / 6;
+            board.make_null_move();
+            let score = -self.alpha_beta(
+                board,
+                -beta,
+                -beta + 1,
+                depth - 1 - reduction,
+                ply + 1,
+                false,
+            );
+            board.unmake_move();
+            if score >= beta && !self.stopped {
+                return beta;
+            }
+        }
+
+        let mut moves = board.pseudo_legal_moves(false);
+        self.order_moves(board, &mut moves, entry.and_then(|e| e.mv), ply);
+        if ply == 0 && self.root_rotation > 0 && moves.len() > 2 {
+            let rotation = self.root_rotation % (moves.len() - 1);
+            moves[1..].rotate_left(rotation);
+        }
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        let mut searched = 0;
+        for m in moves {
+            if ply == 0 && self.excluded.contains(&m) {
+                continue;
+            }
+            let color = board.color;
+            let quiet = !board.is_capture(m) && m.promotion.is_none();
+            board.make_move(m);
+            if board.is_attacked(board.king_pos(color), color.opponent()) {
+                board.unmake_move();
+                continue;
+            }
+            let gives_check = board.is_in_check();
+            let mut score;
+            if searched == 0 {
+                score = -self.alpha_beta(board, -beta, -alpha, depth - 1, ply + 1, true);
+            } else {
+                let mut reduction = 0;
+                if self.options.late_move_reductions
+                    && depth >= 3
+                    && searched >= 3
+                    && quiet
+                    && !in_check
+                    && !gives_check
+                    && !self.is_killer(m, ply)
+                {
+                    reduction = if searched >= 8 && depth >= 6 { 2 } else { 1 };
+                }
+                score = -self.alpha_beta(
+                    board,
+                    -alpha - 1,
+                    -alpha,
+                    depth - 1 - reduction,
+                    ply + 1,
+                    true,
+                );
+                if score > alpha && reduction > 0 {
+                    score = -self.alpha_beta(board, -alpha - 1, -alpha, depth - 1, ply + 1, true);
+                }
+                if score > alpha && score < beta {
+                    score = -self.alpha_beta(board, -beta, -alpha, depth - 1, ply + 1, true);
+                }
+            }
+            board.unmake_move();
+            searched += 1;
+            if self.stopped {
+                return 0;
+            }
+            if score > best_score {
+                best_score = score;
+                best_move = Some(m);
+            }
+            if score > alpha {
+                alpha = score;
+                let mut line = vec![m];
+                line.extend_from_slice(&self.pv[ply + 1]);
+                self.pv[ply] = line;
+            }
+            if alpha >= beta {
+                if quiet {
+                    self.remember_cutoff(m, depth, ply);
+                }
+                break;
+            }
+        }
+
+        if searched == 0 {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        // the best of the moves left at the root is not the best move of the position
+        if ply > 0 || self.excluded.is_empty() {
+            self.tt.store(Entry {
+                key: board.hash,
+                mv: best_move,
+                score: score_to_tt(best_score, ply),
+                depth,
+                bound,
+            });
+        }
+        best_score
+    }
+
+    /// scores the position for the side to move with the network if there is one,
+    /// otherwise with the hand written evaluation.
+    fn evaluate(&self, board: &Board) -> i32 {
+        match board.accumulator() {
+            Some(accumulator) => accumulator.evaluate(board.color),
+            None => eval::evaluate(board, &self.weights),
+        }
+    }
+
+    /// searches only captures until the position is quiet, so the evaluation is not
+    /// fooled by a piece that is about to be taken.
+    fn quiescence(&mut self, board: &mut Board, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.pv[ply].clear();
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(2048) && self.should_stop() {
+            self.stopped = true;
+        }
+        if self.stopped {
+            return 0;
+        }
+        let stand_pat = self.evaluate(board);
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat;
+        }
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
+        let mut moves = board.pseudo_legal_moves(true);
+        self.order_moves(board, &mut moves, None, ply);
+        for m in moves {
+            let color = board.color;
+            board.make_move(m);
+            if board.is_attacked(board.king_pos(color), color.opponent()) {
+                board.unmake_move();
+                continue;
+            }
+            let score = -self.quiescence(board, -beta, -alpha, ply + 1);
+            board.unmake_move();
+            if score >= beta {
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+        alpha
+    }
+
+    /// sorts moves so the ones most likely to be best are searched first.
+    /// Moves with the same score keep the order they were generated in.
+    fn order_moves(&self, board: &Board, moves: &mut [Move], tt_move: Option<Move>, ply: usize) {
+        moves.sort_by_cached_key(|&m| -self.move_score(board, m, tt_move, ply));
+    }
+
+    fn move_score(&self, board: &Board, m: Move, tt_move: Option<Move>, ply: usize) -> i32 {
+        if Some(m) == tt_move {
+            return 3_000_000;
+        }
+        if board.is_capture(m) || m.promotion.is_some() {
+            if !self.options.mvv_lva {
+                return 2_000_000;
+            }
+            let victim = board.piece_at(m.to).kind().map_or(100, eval::piece_value);
+            let attacker = eval::piece_value(board.piece_at(m.from).kind().unwrap());
+            let promotion = m.promotion.map_or(0, eval::piece_value);
+            return 2_000_000 + victim * 10 + promotion - attacker / 10;
+        }
+        if self.options.killers {
+            if self.killers[ply][0] == Some(m) {
+                return 1_000_002;
+            }
+            if self.killers[ply][1] == Some(m) {
+                return 1_000_001;
+            }
+        }
+        if self.options.history {
+            return self.history[square_index(m.from)][square_index(m.to)];
+        }
+        0
+    }
+
+    fn is_killer(&self, m: Move, ply: usize) -> bool {
+        self.options.killers && (self.killers[ply][0] == Some(m) || self.killers[ply][1] == Some(m))
+    }
+
+    /// remembers a quiet move that caused a beta cutoff.
+    fn remember_cutoff(&mut self, m: Move, depth: i32, ply: usize) {
+        if self.killers[ply][0] != Some(m) {
+            self.killers[ply][1] = self.killers[ply][0];
+            self.killers[ply][0] = Some(m);
+        }
+        let value = &mut self.history[square_index(m.from)][square_index(m.to)];
+        // stay below the killer scores
+        *value = (*value + depth * depth).min(900_000);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// searches a position to a depth and returns the score and the best move.
+    fn search(fen: &str, depth: u32) -> (i32, String) {
+        let mut board = Board::from_fen(fen).unwrap();
+        let limits = Limits {
+            depth: Some(depth),
+            ..Limits::default()
+        };
+        let info = Search::default().search(&mut board, limits, &mut |_| ());
+        (info.score, info.pv[0].to_uci())
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let (score, m) = search("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", 3);
+        assert_eq!(mate_in(score), Some(1));
+        assert_eq!(m, "d1d8");
+    }
+
+    #[test]
+    fn finds_mate_in_two() {
+        let (score, m) = search("r5k1/5ppp/8/8/8/8/3R1PPP/3R2K1 w - - 0 1", 4);
+        assert_eq!(mate_in(score), Some(2));
+        assert_eq!(m, "d2d8");
+    }
+
+    #[test]
+    fn sees_it_is_getting_mated() {
+        let (score, m) = search("r2R2k1/5ppp/8/8/8/8/5PPP/3R2K1 b - - 0 1", 4);
+        assert_eq!(mate_in(score), Some(-1));
+        assert_eq!(m, "a8d8");
+    }
+
+    #[test]
+    fn threads_agree_at_a_fixed_depth() {
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+        let limits = Limits {
+            depth: Some(5),
+            ..Limits::default()
+        };
+        let results: Vec<(i32, Option<Move>)> = (0..5)
+            .map(|_| {
+                let mut board = Board::from_fen(fen).unwrap();
+                let mut search = Search {
+                    threads: 4,
+                    ..Search::default()
+                };
+                let info = search.search(&mut board, limits, &mut |_| ());
+                (info.score, info.pv.first().copied())
+            })
+            .collect();
+        assert!(results[0].1.is_some());
+        assert!(results.iter().all(|result| *result == results[0]));
+    }
+}
@@ -0,0 +1,502 @@
+//! Plays matches between two engines to find out if a change makes the engine stronger.
+//! Every opening is played twice with the colors swapped, several games at a time,
+//! and the match stops early once the sequential probability ratio test is sure either way.
+
+use crate::board::{Board, Color, Move};
+use crate::eval::Weights;
+use crate::level::LEVELS;
+use crate::nnue::Network;
+use crate::pgn::{self, Game};
+use crate::random::Rng;
+use crate::search::{Limits, Search, SearchOptions};
+use crate::uci_client::UciEngine;
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Games still going after this many plies are called a draw.
+const MAX_PLIES: usize = 600;
+/// How long an outside engine may take for a move when it is not given a move time.
+const MOVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// One side of a match.
+pub struct Player {
+    /// what the player is called in the PGN file.
+    pub name: String,
+    /// the path to an outside UCI engine, or None for this program's own search.
+    pub path: Option<String>,
+    /// the switches of the search when it is this program's own.
+    pub options: SearchOptions,
+    /// the evaluation weights when the search is this program's own.
+    pub weights: Weights,
+    /// the network to evaluate with instead of the weights.
+    pub network: Option<Arc<Network>>,
+    /// the difficulty level to play at instead of searching to the match limits.
+    pub level: Option<usize>,
+}
+
+/// A position games start from, with the moves that led to it.
+pub struct Opening {
+    pub fen: String,
+    pub moves: Vec<Move>,
+}
+
+/// reads openings from the games of a PGN file, or from an EPD file with one position per line.
+pub fn read_openings(path: &str) -> Result<Vec<Opening>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let mut openings = vec![];
+    if path.to_lowercase().ends_with(".pgn") {
+        for game in pgn::read_games(&text) {
+            let game = game.map_err(|e| format!("{path}: {e}"))?;
+            openings.push(Opening {
+                fen: game.start_fen,
+                moves: game.moves,
+            });
+        }
+    } else {
+        for line in text.lines() {
+            // the operations after the four position fields are not needed
+            let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let fen = format!("{} 0 1", fields.join(" "));
+            Board::from_fen(&fen).map_err(|e| format!("{path}: {e}"))?;
+            openings.push(Opening { fen, moves: vec![] });
+        }
+    }
+    if openings.is_empty() {
+        return Err(format!("there are no openings in {path}"));
+    }
+    Ok(openings)
+}
+
+/// The games won, drawn and lost by the first player.
+#[derive(Copy, Clone, Default)]
+pub struct Tally {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Tally {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// the average points per game and how much one game varies from it.
+    fn score(&self) -> (f64, f64) {
+        let games = self.games().max(1) as f64;
+        let score = (self.wins as f64 + self.draws as f64 / 2.) / games;
+        let variance = (self.wins as f64 * (1. - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / games;
+        (score, variance)
+    }
+
+    /// the Elo difference the score means, and how far the real one may be from it
+    /// with 95% confidence.
+    pub fn elo(&self) -> (f64, f64) {
+        let (score, variance) = self.score();
+        let margin = 1.96 * (variance / self.games().max(1) as f64).sqrt();
+        let low = elo_from_score(score - margin);
+        let high = elo_from_score(score + margin);
+        (elo_from_score(score), (high - low) / 2.)
+    }
+}
+
+/// the Elo difference that makes a player expect the score, kept finite for perfect scores.
+fn elo_from_score(score: f64) -> f64 {
+    let score = score.clamp(0.001, 0.999);
+    -400. * (1. / score - 1.).log10()
+}
+
+/// the score a player expects from an Elo difference.
+fn score_from_elo(elo: f64) -> f64 {
+    1. / (1. + 10f64.powf(-elo / 400.))
+}
+
+/// What the sequential probability ratio test has decided so far.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Verdict {
+    /// the first player is at least elo1 stronger.
+    Pass,
+    /// the first player is at most elo0 stronger.
+    Fail,
+    /// more games are needed.
+    Continue,
+}
+
+/// The hypotheses of the sequential probability ratio test and the chances of it being wrong.
+#[derive(Copy, Clone)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    /// the chance of passing a change that is not stronger.
+    pub alpha: f64,
+    /// the chance of failing a change that is stronger.
+    pub beta: f64,
+}
+
+impl Sprt {
+    /// the log likelihood ratio of elo1 over elo0, using the normal approximation
+    /// of the logistic Elo model.
+    pub fn llr(&self, tally: &Tally) -> f64 {
+        let (score, variance) = tally.score();
+        if variance == 0. {
+            return 0.;
+        }
+        let s0 = score_from_elo(self.elo0);
+        let s1 = score_from_elo(self.elo1);
+        tally.games() as f64 * (s1 - s0) * (2. * score - s0 - s1) / (2. * variance)
+    }
+
+    /// the ratios below which the test fails and above which it passes.
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1. - self.alpha)).ln(),
+            ((1. - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    pub fn verdict(&self, tally: &Tally) -> Verdict {
+        let llr = self.llr(tally);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            Verdict::Pass
+        } else if llr <= lower {
+            Verdict::Fail
+        } else {
+            Verdict::Continue
+        }
+    }
+}
+
+/// A running player, ready to be asked for moves.
+enum Engine {
+    Builtin(Box<Search>),
+    Level(usize, Rng),
+    Uci(UciEngine),
+}
+
+impl Engine {
+    fn start(player: &Player) -> Result<Self, String> {
+        Ok(match (&player.path, player.level) {
+            (Some(path), _) => Self::Uci(UciEngine::spawn(path)?),
+            (None, Some(level)) => Self::Level(level, Rng::from_time()),
+            (None, None) => {
+                let mut search = Search::new(player.options);
+                search.weights = player.weights.clone();
+                search.network = player.network.clone();
+                Self::Builtin(Box::new(search))
+            }
+        })
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        match self {
+            Self::Builtin(search) => search.clear(),
+            Self::Level(..) => (),
+            Self::Uci(engine) => {
+                engine.new_game()?;
+                engine.wait_until_ready()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// picks a move for the last position of the game, which board must be.
+    fn choose(&mut self, game: &Game, board: &mut Board, limits: Limits) -> Result<Move, String> {
+        match self {
+            Self::Builtin(search) => {
+                let info = search.search(board, limits, &mut |_| ());
+                info.pv.first().copied().ok_or_else(|| "no move".to_owned())
+            }
+            // a level keeps to its own limits, so levels can be measured against each other
+            Self::Level(level, rng) => LEVELS[*level]
+                .choose_move(board, rng)
+                .ok_or_else(|| "no move".to_owned()),
+            Self::Uci(engine) => {
+                let mut words = vec![];
+                if let Some(depth) = limits.depth {
+                    words.push(format!("depth {depth}"));
+                }
+                if let Some(nodes) = limits.nodes {
+                    words.push(format!("nodes {nodes}"));
+                }
+                if let Some(movetime) = limits.movetime {
+                    words.push(format!("movetime {}", movetime.as_millis()));
+                }
+                let expected = limits.movetime.unwrap_or(MOVE_TIMEOUT);
+                engine.go_with(&game.start_fen, &game.moves, &words.join(" "), expected)?;
+                loop {
+                    if let Some(m) = engine.poll(board)? {
+                        return Ok(m);
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+    }
+}
+
+/// A match between two players.
+pub struct Match {
+    pub players: [Player; 2],
+    pub openings: Vec<Opening>,
+    /// the most games to play, if the test does not decide sooner.
+    pub games: usize,
+    /// how many games are played at once.
+    pub concurrency: usize,
+    pub limits: Limits,
+    pub sprt: Sprt,
+}
+
+impl Match {
+    /// plays the match, adding every game to the PGN file as it ends
+    /// and calling report with the tally after each one.
+    pub fn run(
+        &self,
+        pgn_path: &str,
+        report: &mut dyn FnMut(&Tally),
+    ) -> Result<(Tally, Verdict), String> {
+        let mut file =
+            File::create(pgn_path).map_err(|e| format!("could not create {pgn_path}: {e}"))?;
+        let next = AtomicUsize::new(0);
+        let done = AtomicBool::new(false);
+        let mut tally = Tally::default();
+        let mut verdict = Verdict::Continue;
+        let mut error = None;
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..self.concurrency.clamp(1, self.games.max(1)) {
+                let sender = sender.clone();
+                let (next, done) = (&next, &done);
+                scope.spawn(move || {
+                    let mut engines = [None, None];
+                    while !done.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= self.games {
+                            break;
+                        }
+                        let result = self.play(index, &mut engines);
+                        if sender.send(result).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+            for result in receiver {
+                let (game, winner) = match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        error.get_or_insert(e);
+                        done.store(true, Ordering::Relaxed);
+                        continue;
+                    }
+                };
+                if let Err(e) = writeln!(file, "{}", game.write()) {
+                    error.get_or_insert(format!("could not write {pgn_path}: {e}"));
+                }
+                match winner {
+                    Some(0) => tally.wins += 1,
+                    Some(_) => tally.losses += 1,
+                    None => tally.draws += 1,
+                }
+                report(&tally);
+                verdict = self.sprt.verdict(&tally);
+                if verdict != Verdict::Continue {
+                    done.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok((tally, verdict)),
+        }
+    }
+
+    /// plays one game with the engines of a worker, starting any that are not running.
+    /// Returns the game and which player won it, None for a draw.
+    fn play(
+        &self,
+        index: usize,
+        engines: &mut [Option<Engine>; 2],
+    ) -> Result<(Game, Option<usize>), String> {
+        for (engine, player) in engines.iter_mut().zip(self.players.iter()) {
+            if engine.is_none() {
+                *engine = Some(Engine::start(player)?);
+            }
+            engine.as_mut().unwrap().new_game()?;
+        }
+        let opening = &self.openings[index / 2 % self.openings.len()];
+        // the first player is white in even games
+        let white = index % 2;
+        let mut game = Game::new(&opening.fen);
+        game.set_tag("Event", "Engine match");
+        game.set_tag("Round", &(index + 1).to_string());
+        game.set_tag("White", &self.players[white].name);
+        game.set_tag("Black", &self.players[1 - white].name);
+        let mut board = Board::from_fen(&opening.fen)?;
+        for &m in opening.moves.iter() {
+            board.make_move(m);
+            game.moves.push(m);
+        }
+
+        let winner = loop {
+            if board.is_checkmate() {
+                break Some(board.color.opponent());
+            }
+            if board.is_stalemate() || board.is_draw() {
+                break None;
+            }
+            if game.moves.len() >= MAX_PLIES {
+                game.set_tag("Termination", "adjudication");
+                break None;
+            }
+            let mover = match board.color {
+                Color::White => white,
+                Color::Black => 1 - white,
+            };
+            let engine = engines[mover].as_mut().unwrap();
+            match engine.choose(&game, &mut board, self.limits) {
+                Ok(m) => {
+                    board.make_move(m);
+                    game.moves.push(m);
+                }
+                Err(e) => {
+                    // a broken engine loses the game and is started again for the next one
+                    game.comment = e;
+                    game.set_tag("Termination", "rules infraction");
+                    engines[mover] = None;
+                    break Some(board.color.opponent());
+                }
+            }
+        };
+        let (result, winner) = match winner {
+            Some(Color::White) => ("1-0", Some(white)),
+            Some(Color::Black) => ("0-1", Some(1 - white)),
+            None => ("1/2-1/2", None),
+        };
+        game.set_tag("Result", result);
+        Ok((game, winner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a path in the temp directory for a test's file.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("sprt-test-{}-{name}", std::process::id()));
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn tally(wins: u32, draws: u32, losses: u32) -> Tally {
+        Tally {
+            wins,
+            draws,
+            losses,
+        }
+    }
+
+    const SPRT: Sprt = Sprt {
+        elo0: 0.,
+        elo1: 5.,
+        alpha: 0.05,
+        beta: 0.05,
+    };
+
+    #[test]
+    fn elo_of_scores() {
+        assert_eq!(tally(10, 0, 10).elo().0, 0.);
+        let (elo, margin) = tally(30, 0, 10).elo();
+        assert!((elo - 190.8).abs() < 0.1);
+        assert!(margin > 0.);
+        // perfect scores stay finite
+        assert!(tally(5, 0, 0).elo().0.is_finite());
+        assert!(tally(0, 0, 5).elo().0 < -1000.);
+    }
+
+    #[test]
+    fn verdicts() {
+        let (lower, upper) = SPRT.bounds();
+        assert!((upper - 2.944).abs() < 0.001);
+        assert!((lower + 2.944).abs() < 0.001);
+        assert_eq!(SPRT.llr(&tally(0, 10, 0)), 0.);
+        assert!(SPRT.verdict(&tally(3, 4, 2)) == Verdict::Continue);
+        assert!(SPRT.verdict(&tally(3000, 4000, 2000)) == Verdict::Pass);
+        assert!(SPRT.verdict(&tally(2000, 4000, 3000)) == Verdict::Fail);
+    }
+
+    #[test]
+    fn reads_openings() {
+        let epd = temp_path("openings.epd");
+        std::fs::write(
+            &epd,
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 bm e5;\n\n\
+             4k3/8/8/8/8/8/4P3/4K3 w - -\n",
+        )
+        .unwrap();
+        let openings = read_openings(&epd).unwrap();
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[1].fen, "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+
+        let pgn = temp_path("openings.pgn");
+        std::fs::write(&pgn, "1. e4 e5 2. Nf3 *\n\n1. d4 d5 *\n").unwrap();
+        let openings = read_openings(&pgn).unwrap();
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0].moves.len(), 3);
+
+        std::fs::write(&epd, "not a position\n").unwrap();
+        assert!(read_openings(&epd).is_err());
+        std::fs::write(&epd, "\n").unwrap();
+        assert!(read_openings(&epd).is_err());
+    }
+
+    #[test]
+    fn plays_both_colors() {
+        let player = |name: &str| Player {
+            name: name.to_owned(),
+            path: None,
+            options: SearchOptions::default(),
+            weights: Weights::default(),
+            network: None,
+            level: None,
+        };
+        let chess_match = Match {
+            players: [player("first"), player("second")],
+            openings: vec![Opening {
+                fen: "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1".to_owned(),
+                moves: vec![],
+            }],
+            games: 2,
+            concurrency: 1,
+            limits: Limits {
+                depth: Some(2),
+                ..Limits::default()
+            },
+            sprt: SPRT,
+        };
+        let path = temp_path("games.pgn");
+        let mut reports = 0;
+        let (tally, verdict) = chess_match.run(&path, &mut |_| reports += 1).unwrap();
+        assert_eq!(tally.games(), 2);
+        assert_eq!(reports, 2);
+        assert!(verdict == Verdict::Continue);
+        let games = pgn::read_games(&std::fs::read_to_string(&path).unwrap());
+        assert_eq!(games.len(), 2);
+        let white: Vec<String> = games
+            .into_iter()
+            .map(|game| game.unwrap().tag("White").unwrap().to_owned())
+            .collect();
+        assert_eq!(white, ["first", "second"]);
+    }
+}
@@ -0,0 +1,1125 @@
+//! Syzygy endgame tablebases.
+//! Reads the WDL (win/draw/loss) and DTZ (distance to zeroing) files of the Syzygy format,
+//! which hold perfect play for every position with few enough pieces.
+//! The layout of the files and the way positions are turned into indexes
+//! follow the probing code in Stockfish.
+
+use crate::board::{Board, Color, Move, Piece, Type};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The first bytes of every WDL file.
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+/// The first bytes of every DTZ file.
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+/// The most pieces a Syzygy table can have.
+const MAX_PIECES: usize = 7;
+
+// the flags of each part of a table
+/// DTZ tables only store one side to move. The flag is set when it is black.
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+// Results for the side to move. A cursed win is a win the fifty move rule turns into a draw,
+// and a blessed loss is a loss it saves.
+pub const LOSS: i32 = -2;
+pub const BLESSED_LOSS: i32 = -1;
+pub const DRAW: i32 = 0;
+pub const CURSED_WIN: i32 = 1;
+pub const WIN: i32 = 2;
+
+/// What the tables say about a position.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Probe {
+    /// one of LOSS, BLESSED_LOSS, DRAW, CURSED_WIN or WIN.
+    pub wdl: i32,
+    /// plies until a capture or pawn move that keeps the result, negative when losing.
+    pub dtz: i32,
+}
+
+/// the name of a result, like "win".
+pub fn wdl_name(wdl: i32) -> &'static str {
+    match wdl {
+        LOSS => "loss",
+        BLESSED_LOSS => "blessed loss",
+        CURSED_WIN => "cursed win",
+        WIN => "win",
+        _ => "draw",
+    }
+}
+
+/// The tables used to turn a position into an index into a table.
+struct Indexes {
+    /// binomial[k][n] is n choose k.
+    binomial: [[u64; 64]; MAX_PIECES + 1],
+    /// numbers the squares of the a1-d1-d4 triangle, below the diagonal first.
+    map_a1d1d4: [u64; 64],
+    /// numbers the squares below the a1-h8 diagonal.
+    map_b1h1h7: [u64; 64],
+    /// numbers the 462 ways to place two kings with the first in the a1-d1-d4 triangle.
+    map_kk: [[u64; 64]; 10],
+    /// numbers the pawn squares so the leading pawn has the highest number.
+    map_pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES + 1],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES + 1],
+}
+
+/// how far above the a1-h8 diagonal a square is, negative below it.
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+impl Indexes {
+    fn new() -> Self {
+        let mut indexes = Self {
+            binomial: [[0; 64]; MAX_PIECES + 1],
+            map_a1d1d4: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_kk: [[0; 64]; 10],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES + 1],
+            lead_pawns_size: [[0; 4]; MAX_PIECES + 1],
+        };
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                indexes.map_b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = vec![];
+        code = 0;
+        for square in (0..4).flat_map(|rank| (0..4).map(move |file| rank * 8 + file)) {
+            if off_diagonal(square) < 0 {
+                indexes.map_a1d1d4[square] = code;
+                code += 1;
+            } else if off_diagonal(square) == 0 {
+                diagonal.push(square);
+            }
+        }
+        for square in diagonal {
+            indexes.map_a1d1d4[square] = code;
+            code += 1;
+        }
+
+        // positions with both kings on the diagonal are numbered last
+        let mut both_on_diagonal = vec![];
+        code = 0;
+        for idx in 0..10 {
+            // squares outside the triangle are also mapped to 0, so only b1 counts for it
+            let map_a1d1d4 = indexes.map_a1d1d4;
+            for s1 in (0..28).filter(|&s1| map_a1d1d4[s1] == idx && (idx > 0 || s1 == 1)) {
+                for s2 in 0..64 {
+                    let touching = (s1 % 8).abs_diff(s2 % 8) <= 1 && (s1 / 8).abs_diff(s2 / 8) <= 1;
+                    if touching || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx as usize, s2));
+                    } else {
+                        indexes.map_kk[idx as usize][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            indexes.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        indexes.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..=MAX_PIECES.min(n) {
+                let with = if k > 0 {
+                    indexes.binomial[k - 1][n - 1]
+                } else {
+                    0
+                };
+                let without = if k < n { indexes.binomial[k][n - 1] } else { 0 };
+                indexes.binomial[k][n] = with + without;
+            }
+        }
+
+        // pawns nearer the edge and lower down get higher numbers
+        let mut available = 47;
+        for lead_pawns in 1..MAX_PIECES {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        indexes.map_pawns[square] = available;
+                        indexes.map_pawns[square ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    indexes.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += indexes.binomial[lead_pawns - 1][indexes.map_pawns[square] as usize];
+                }
+                indexes.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+        indexes
+    }
+}
+
+fn byte(bytes: &[u8], at: usize) -> Option<u8> {
+    bytes.get(at).copied()
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([byte(bytes, at)?, byte(bytes, at + 1)?]))
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> Option<u32> {
+    let mut word = [0; 4];
+    word.copy_from_slice(bytes.get(at..at + 4)?);
+    Some(u32::from_le_bytes(word))
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    let mut word = [0; 4];
+    word.copy_from_slice(bytes.get(at..at + 4)?);
+    Some(u32::from_be_bytes(word))
+}
+
+fn u64_be(bytes: &[u8], at: usize) -> Option<u64> {
+    let mut word = [0; 8];
+    word.copy_from_slice(bytes.get(at..at + 8)?);
+    Some(u64::from_be_bytes(word))
+}
+
+/// The part of a table for one side to move and, with pawns, one file of the leading pawn.
+/// The values are compressed with canonical Huffman codes of symbols that each stand for
+/// a pair of smaller symbols.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    /// the pieces in the order they are indexed, coded 1 to 6 for white pawn to king
+    /// and 9 to 14 for black.
+    pieces: [u8; MAX_PIECES],
+    /// how many pieces are indexed together, ending with a 0.
+    group_len: [usize; MAX_PIECES + 1],
+    /// what each group's index is multiplied by, with the size of the table after the last.
+    group_idx: [u64; MAX_PIECES + 1],
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    /// for a table part that only holds one value, the value.
+    min_sym_len: u8,
+    /// where the lowest symbol of each code length is stored.
+    lowest_sym: usize,
+    /// the lowest code of each length, shifted to the top of 64 bits.
+    base64: Vec<u64>,
+    /// one less than the number of values each symbol stands for.
+    symlen: Vec<u8>,
+    /// where the pair each symbol stands for is stored.
+    btree: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_length: usize,
+    block_length_size: usize,
+    data: usize,
+    /// where the value maps for each result start, for DTZ tables.
+    map_idx: [usize; 4],
+}
+
+/// A WDL or DTZ table file read into memory.
+struct Table {
+    bytes: Vec<u8>,
+    dtz: bool,
+    /// true when both sides have the same pieces, like KRvKR.
+    symmetric: bool,
+    has_pawns: bool,
+    /// true if some side has exactly one of some piece other than the king.
+    has_unique_pieces: bool,
+    piece_count: usize,
+    /// the pawns of the leading side first, which is the side with fewer pawns but some.
+    pawn_count: [usize; 2],
+    /// indexed by side to move, then by file of the leading pawn.
+    parts: Vec<Vec<PairsData>>,
+    /// where the DTZ value maps start.
+    map: usize,
+}
+
+impl Table {
+    /// reads a table named like "KRvK". Returns None if the file is not a valid table.
+    fn read(bytes: Vec<u8>, name: &str, dtz: bool, indexes: &Indexes) -> Option<Self> {
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.get(0..4)? != magic {
+            return None;
+        }
+        let (white, black) = name.split_once('v')?;
+        let count = |side: &str, piece: char| side.chars().filter(|&c| c == piece).count();
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let mut table = Self {
+            bytes: vec![],
+            dtz,
+            symmetric: white == black,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: [white, black]
+                .iter()
+                .any(|side| "QRBNP".chars().any(|piece| count(side, piece) == 1)),
+            piece_count: white.len() + black.len(),
+            pawn_count: if white_leads {
+                [white_pawns, black_pawns]
+            } else {
+                [black_pawns, white_pawns]
+            },
+            parts: vec![],
+            map: 0,
+        };
+        if table.piece_count > MAX_PIECES || (byte(&bytes, 4)? & 2 != 0) != table.has_pawns {
+            return None;
+        }
+        table.read_parts(&bytes, indexes)?;
+        table.bytes = bytes;
+        Some(table)
+    }
+
+    /// reads the headers of every part of the table.
+    fn read_parts(&mut self, bytes: &[u8], indexes: &Indexes) -> Option<()> {
+        let sides = if !self.dtz && !self.symmetric { 2 } else { 1 };
+        let files = if self.has_pawns { 4 } else { 1 };
+        // pawns on both sides
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        self.parts = vec![vec![PairsData::default(); files]; sides];
+        let mut at = 5;
+        for file in 0..files {
+            let first = byte(bytes, at)?;
+            let second = if both_pawns {
+                byte(bytes, at + 1)?
+            } else {
+                0xff
+            };
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            at += 1 + both_pawns as usize;
+            for k in 0..self.piece_count {
+                let pieces = byte(bytes, at)?;
+                for (side, part) in self.parts.iter_mut().enumerate() {
+                    part[file].pieces[k] = if side == 1 { pieces >> 4 } else { pieces & 0xf };
+                }
+                at += 1;
+            }
+            for (side, &order) in order.iter().enumerate().take(sides) {
+                self.set_groups(side, file, order, indexes)?;
+            }
+        }
+        at += at & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                at = set_sizes(&mut self.parts[side][file], bytes, at)?;
+            }
+        }
+
+        self.map = at;
+        if self.dtz {
+            for file in 0..files {
+                let part = &mut self.parts[0][file];
+                if part.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                for i in 0..4 {
+                    if part.flags & FLAG_WIDE != 0 {
+                        at += at & 1;
+                        part.map_idx[i] = (at - self.map) / 2 + 1;
+                        at += 2 * u16_le(bytes, at)? as usize + 2;
+                    } else {
+                        part.map_idx[i] = at - self.map + 1;
+                        at += byte(bytes, at)? as usize + 1;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+
+        for file in 0..files {
+            for side in 0..sides {
+                let part = &mut self.parts[side][file];
+                part.sparse_index = at;
+                at += part.sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let part = &mut self.parts[side][file];
+                part.block_length = at;
+                at += part.block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let part = &mut self.parts[side][file];
+                at = (at + 0x3f) & !0x3f;
+                part.data = at;
+                at += part.num_blocks * part.block_size;
+            }
+        }
+        if at > bytes.len() {
+            return None;
+        }
+        Some(())
+    }
+
+    /// splits the pieces into the groups that are indexed together,
+    /// and works out what each group's index is multiplied by.
+    fn set_groups(
+        &mut self,
+        side: usize,
+        file: usize,
+        order: [u8; 2],
+        indexes: &Indexes,
+    ) -> Option<()> {
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let part = &mut self.parts[side][file];
+        let mut n = 0;
+        // without pawns the kings and maybe one more piece go first whatever they are
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        part.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || part.pieces[i] == part.pieces[i - 1] {
+                part.group_len[n] += 1;
+            } else {
+                n += 1;
+                part.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        part.group_len[n] = 0;
+
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares =
+            64 - part.group_len[0] - if both_pawns { part.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                part.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    *indexes.lead_pawns_size.get(part.group_len[0])?.get(file)?
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                part.group_idx[1] = idx;
+                let squares = 48usize.checked_sub(part.group_len[0])?;
+                idx *= indexes.binomial.get(part.group_len[1])?[squares];
+            } else {
+                part.group_idx[next] = idx;
+                idx *= indexes.binomial.get(part.group_len[next])?[free_squares];
+                free_squares = free_squares.checked_sub(part.group_len[next])?;
+                next += 1;
+            }
+            k += 1;
+            if k > 15 || next > MAX_PIECES {
+                return None;
+            }
+        }
+        part.group_idx[n] = idx;
+        Some(())
+    }
+
+    /// looks up a position in the table.
+    /// black_stronger is true when black has the pieces of the first side of the table's name.
+    /// Returns Some(None) for a DTZ table that only stores the other side to move.
+    fn probe(
+        &self,
+        board: &Board,
+        black_stronger: bool,
+        wdl: i32,
+        indexes: &Indexes,
+    ) -> Option<Option<i32>> {
+        // symmetric tables only store white to move, so the colors are swapped for black
+        let flip = black_stronger || (self.symmetric && board.color == Color::Black);
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ (board.color == Color::Black) as usize;
+
+        let mut all = vec![];
+        for (y, row) in board.squares.iter().enumerate() {
+            for (x, &piece) in row.iter().enumerate() {
+                if let Some(code) = piece_code(piece) {
+                    all.push((y * 8 + x, code));
+                }
+            }
+        }
+        if all.len() != self.piece_count {
+            return None;
+        }
+        let mut squares = [0; MAX_PIECES];
+        let mut pieces = [0; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut file = 0;
+        let mut lead_pawn = None;
+        if self.has_pawns {
+            // the leading pawns are the first pieces of every part
+            let pawn = self.parts[0][0].pieces[0] ^ flip_color;
+            for &(square, _) in all.iter().filter(|&&(_, code)| code == pawn) {
+                squares[size] = square ^ flip_squares;
+                size += 1;
+            }
+            lead_pawns = size;
+            let lead = (0..lead_pawns).max_by_key(|&i| indexes.map_pawns[squares[i]])?;
+            squares.swap(0, lead);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+            lead_pawn = Some(pawn);
+        }
+
+        if self.dtz {
+            let flags = self.parts[0][file].flags;
+            // symmetric tables without pawns are the same for both sides to move
+            if (self.has_pawns || !self.symmetric) && (flags & FLAG_STM) as usize != stm {
+                return Some(None);
+            }
+        }
+
+        for &(square, code) in all.iter().filter(|&&(_, code)| Some(code) != lead_pawn) {
+            squares[size] = square ^ flip_squares;
+            pieces[size] = code ^ flip_color;
+            size += 1;
+        }
+        let part = &self.parts[stm.min(self.parts.len() - 1)][file];
+
+        // put the pieces in the order the table indexes them
+        for i in lead_pawns..size - 1 {
+            for j in i + 1..size {
+                if part.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // mirror so the leading piece is on the a-d files
+        if squares[0] % 8 > 3 {
+            for square in squares[..size].iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = indexes.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|&square| indexes.map_pawns[square]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += indexes.binomial[i][indexes.map_pawns[square] as usize];
+            }
+        } else {
+            // without pawns the board can also be mirrored top to bottom and on the diagonal
+            if squares[0] / 8 > 3 {
+                for square in squares[..size].iter_mut() {
+                    *square ^= 56;
+                }
+            }
+            for i in 0..part.group_len[0] {
+                if off_diagonal(squares[i]) == 0 {
+                    continue;
+                }
+                if off_diagonal(squares[i]) > 0 {
+                    for square in squares[i..size].iter_mut() {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+            idx = if self.has_unique_pieces {
+                self.unique_pieces_index(&squares, indexes)?
+            } else {
+                indexes.map_kk[indexes.map_a1d1d4[squares[0]] as usize][squares[1]]
+            };
+        }
+
+        idx *= part.group_idx[0];
+        let mut start = part.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while part.group_len[next] != 0 {
+            let len = part.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[start + i];
+                // squares taken by earlier groups are skipped
+                let taken = squares[..start].iter().filter(|&&s| square > s).count();
+                let first_rank = if remaining_pawns { 8 } else { 0 };
+                n += indexes.binomial[i + 1][square.checked_sub(taken + first_rank)?];
+            }
+            remaining_pawns = false;
+            idx += n * part.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = decompress(part, &self.bytes, idx)?;
+        if !self.dtz {
+            return Some(Some(value - 2));
+        }
+        Some(Some(self.map_dtz(file, value, wdl)?))
+    }
+
+    /// the index of the three leading pieces of a table without pawns.
+    fn unique_pieces_index(&self, squares: &[usize; MAX_PIECES], indexes: &Indexes) -> Option<u64> {
+        let [s0, s1, s2] = [squares[0], squares[1], squares[2]];
+        let adjust1 = (s1 > s0) as u64;
+        let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+        let (s1, s2) = (s1 as u64, s2 as u64);
+        let rank = |square: u64| square / 8;
+        Some(if off_diagonal(s0) != 0 {
+            (indexes.map_a1d1d4[s0] * 63 + s1 - adjust1) * 62 + s2 - adjust2
+        } else if off_diagonal(s1 as usize) != 0 {
+            (6 * 63 + rank(s0 as u64) * 28 + indexes.map_b1h1h7[s1 as usize]) * 62 + s2 - adjust2
+        } else if off_diagonal(s2 as usize) != 0 {
+            6 * 63 * 62
+                + 4 * 28 * 62
+                + rank(s0 as u64) * 7 * 28
+                + (rank(s1) - adjust1) * 28
+                + indexes.map_b1h1h7[s2 as usize]
+        } else {
+            6 * 63 * 62
+                + 4 * 28 * 62
+                + 4 * 7 * 28
+                + rank(s0 as u64) * 7 * 6
+                + (rank(s1) - adjust1) * 6
+                + (rank(s2) - adjust2)
+        })
+    }
+
+    /// turns a stored DTZ value into plies.
+    fn map_dtz(&self, file: usize, value: i32, wdl: i32) -> Option<i32> {
+        let part = &self.parts[0][file];
+        let mut value = value;
+        if part.flags & FLAG_MAPPED != 0 {
+            let map = [1, 3, 0, 2, 0][(wdl + 2) as usize];
+            let at = part.map_idx[map] + value as usize;
+            value = if part.flags & FLAG_WIDE != 0 {
+                u16_le(&self.bytes, self.map + 2 * at)? as i32
+            } else {
+                byte(&self.bytes, self.map + at)? as i32
+            };
+        }
+        // values are stored in moves unless the table says they are in plies
+        if (wdl == WIN && part.flags & FLAG_WIN_PLIES == 0)
+            || (wdl == LOSS && part.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == CURSED_WIN
+            || wdl == BLESSED_LOSS
+        {
+            value *= 2;
+        }
+        Some(value + 1)
+    }
+}
+
+/// reads the sizes and Huffman code tables of one part of a table,
+/// and returns where the next part starts.
+fn set_sizes(part: &mut PairsData, bytes: &[u8], mut at: usize) -> Option<usize> {
+    part.flags = byte(bytes, at)?;
+    at += 1;
+    if part.flags & FLAG_SINGLE_VALUE != 0 {
+        part.min_sym_len = byte(bytes, at)?;
+        return Some(at + 1);
+    }
+    let groups = part.group_len.iter().position(|&len| len == 0)?;
+    let table_size = part.group_idx[groups];
+    part.block_size = 1usize.checked_shl(byte(bytes, at)? as u32)?;
+    part.span = 1u64.checked_shl(byte(bytes, at + 1)? as u32)?;
+    part.sparse_index_size = table_size.div_ceil(part.span) as usize;
+    let padding = byte(bytes, at + 2)? as usize;
+    part.num_blocks = u32_le(bytes, at + 3)? as usize;
+    part.block_length_size = part.num_blocks + padding;
+    at += 7;
+    let max_sym_len = byte(bytes, at)?;
+    part.min_sym_len = byte(bytes, at + 1)?;
+    at += 2;
+    part.lowest_sym = at;
+    let lengths = (max_sym_len as usize + 1).checked_sub(part.min_sym_len as usize)?;
+    if lengths == 0 || lengths + part.min_sym_len as usize > 64 {
+        return None;
+    }
+
+    // the lowest code of each length, worked out from the longest code up
+    part.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        let lowest = u16_le(bytes, at + 2 * i)? as u64;
+        let next_lowest = u16_le(bytes, at + 2 * i + 2)? as u64;
+        part.base64[i] = (part.base64[i + 1] + lowest).wrapping_sub(next_lowest) / 2;
+    }
+    for (i, base) in part.base64.iter_mut().enumerate() {
+        *base = base
+            .checked_shl((64 - i - part.min_sym_len as usize) as u32)
+            .unwrap_or(0);
+    }
+    at += lengths * 2;
+
+    let symbols = u16_le(bytes, at)? as usize;
+    at += 2;
+    part.btree = at;
+    part.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for symbol in 0..symbols {
+        if !visited[symbol] {
+            part.symlen[symbol] = set_symlen(part, bytes, symbol, &mut visited)?;
+        }
+    }
+    Some(at + symbols * 3 + (symbols & 1))
+}
+
+/// the left and right halves of the pair a symbol stands for.
+fn pair(part: &PairsData, bytes: &[u8], symbol: usize) -> Option<(usize, usize)> {
+    let at = part.btree + 3 * symbol;
+    let (a, b, c) = (
+        byte(bytes, at)? as usize,
+        byte(bytes, at + 1)? as usize,
+        byte(bytes, at + 2)? as usize,
+    );
+    Some((((b & 0xf) << 8) | a, (c << 4) | (b >> 4)))
+}
+
+/// works out how many values a symbol stands for, less one.
+fn set_symlen(
+    part: &mut PairsData,
+    bytes: &[u8],
+    symbol: usize,
+    visited: &mut [bool],
+) -> Option<u8> {
+    visited[symbol] = true;
+    let (left, right) = pair(part, bytes, symbol)?;
+    // a symbol with no right half stands for one value
+    if right == 0xfff {
+        return Some(0);
+    }
+    if !*visited.get(left)? {
+        part.symlen[left] = set_symlen(part, bytes, left, visited)?;
+    }
+    if !*visited.get(right)? {
+        part.symlen[right] = set_symlen(part, bytes, right, visited)?;
+    }
+    Some(
+        part.symlen[left]
+            .wrapping_add(part.symlen[right])
+            .wrapping_add(1),
+    )
+}
+
+/// finds the value stored at an index of a part of a table.
+fn decompress(part: &PairsData, bytes: &[u8], idx: u64) -> Option<i32> {
+    if part.flags & FLAG_SINGLE_VALUE != 0 {
+        return Some(part.min_sym_len as i32);
+    }
+    // the sparse index gives the block of every span-th value,
+    // from which the block holding idx is found by walking the block lengths
+    let sparse = part.sparse_index + 6 * (idx / part.span) as usize;
+    let mut block = u32_le(bytes, sparse)? as usize;
+    let mut offset = u16_le(bytes, sparse + 4)? as i64;
+    offset += (idx % part.span) as i64 - (part.span / 2) as i64;
+    let block_length = |block: usize| -> Option<i64> {
+        if block >= part.block_length_size {
+            return None;
+        }
+        Some(u16_le(bytes, part.block_length + 2 * block)? as i64)
+    };
+    while offset < 0 {
+        block = block.checked_sub(1)?;
+        offset += block_length(block)? + 1;
+    }
+    while offset > block_length(block)? {
+        offset -= block_length(block)? + 1;
+        block += 1;
+    }
+
+    let mut at = part.data + block * part.block_size;
+    let mut buffer = u64_be(bytes, at)?;
+    at += 8;
+    let mut buffer_size = 64;
+    let min_sym_len = part.min_sym_len as usize;
+    let mut symbol;
+    loop {
+        let mut len = 0;
+        while buffer < *part.base64.get(len)? {
+            len += 1;
+        }
+        symbol = ((buffer - part.base64[len]) >> (64 - len - min_sym_len)) as usize;
+        symbol += u16_le(bytes, part.lowest_sym + 2 * len)? as usize;
+        let values = *part.symlen.get(symbol)? as i64 + 1;
+        if offset < values {
+            break;
+        }
+        offset -= values;
+        let len = len + min_sym_len;
+        buffer <<= len;
+        buffer_size -= len;
+        if buffer_size <= 32 {
+            buffer_size += 32;
+            buffer |= (u32_be(bytes, at)? as u64) << (64 - buffer_size);
+            at += 4;
+        }
+    }
+
+    // walk down the pairs to the single value
+    while part.symlen[symbol] != 0 {
+        let (left, right) = pair(part, bytes, symbol)?;
+        let values = *part.symlen.get(left)? as i64 + 1;
+        if offset < values {
+            symbol = left;
+        } else {
+            offset -= values;
+            symbol = right;
+        }
+    }
+    Some(pair(part, bytes, symbol)?.0 as i32)
+}
+
+/// the code of a piece in a table, 1 to 6 for white pawn to king and 9 to 14 for black.
+fn piece_code(piece: Piece) -> Option<u8> {
+    let kind = match piece.kind()? {
+        Type::Pawn => 1,
+        Type::Knight => 2,
+        Type::Bishop => 3,
+        Type::Rook => 4,
+        Type::Queen => 5,
+        Type::King => 6,
+    };
+    match piece.color()? {
+        Color::White => Some(kind),
+        Color::Black => Some(kind + 8),
+    }
+}
+
+/// the pieces of one side written the way table names are, like "KRP".
+fn material(board: &Board, color: Color) -> String {
+    let mut name = String::new();
+    for kind in [
+        Type::King,
+        Type::Queen,
+        Type::Rook,
+        Type::Bishop,
+        Type::Knight,
+        Type::Pawn,
+    ]
+    .iter()
+    {
+        for _ in board.find(Piece::new(color, *kind)) {
+            name.push(kind.to_char());
+        }
+    }
+    name
+}
+
+/// the DTZ of a position just before a capture or pawn move with the given result.
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        WIN => 1,
+        CURSED_WIN => 101,
+        BLESSED_LOSS => -101,
+        LOSS => -1,
+        _ => 0,
+    }
+}
+
+/// Tables by name and whether they are DTZ tables.
+type TableCache = HashMap<(String, bool), Option<Arc<Table>>>;
+
+/// A directory of Syzygy tables. Tables are read into memory the first time they are needed.
+pub struct Tablebase {
+    directory: PathBuf,
+    /// the names of the WDL and DTZ tables found in the directory, like "KRvK".
+    wdl_names: HashSet<String>,
+    dtz_names: HashSet<String>,
+    /// the most pieces of any table found.
+    pub max_pieces: usize,
+    indexes: Indexes,
+    /// the tables read so far, with None for ones that could not be read.
+    tables: Mutex<TableCache>,
+}
+
+impl Tablebase {
+    /// finds the tables in a directory. Fails if there are none.
+    pub fn open(directory: &str) -> Result<Self, String> {
+        let entries = std::fs::read_dir(directory)
+            .map_err(|e| format!("could not read tablebase directory {directory}: {e}"))?;
+        let mut wdl_names = HashSet::new();
+        let mut dtz_names = HashSet::new();
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(name) = file_name.strip_suffix(".rtbw") {
+                wdl_names.insert(name.to_owned());
+            } else if let Some(name) = file_name.strip_suffix(".rtbz") {
+                dtz_names.insert(name.to_owned());
+            }
+        }
+        let max_pieces = wdl_names
+            .iter()
+            .map(|name| name.len() - 1)
+            .max()
+            .unwrap_or(0);
+        if max_pieces == 0 {
+            return Err(format!("no Syzygy tables in {directory}"));
+        }
+        Ok(Self {
+            directory: PathBuf::from(directory),
+            wdl_names,
+            dtz_names,
+            max_pieces: max_pieces.min(MAX_PIECES),
+            indexes: Indexes::new(),
+            tables: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// true if the position has few enough pieces and no castling rights, which tables leave out.
+    fn can_probe(&self, board: &Board) -> bool {
+        let pieces = board
+            .squares
+            .iter()
+            .flatten()
+            .filter(|&&p| p != Piece::Empty)
+            .count();
+        pieces <= self.max_pieces && !board.castling.iter().any(|&c| c)
+    }
+
+    /// finds the table for the position's pieces, and whether black has the first side's pieces.
+    fn table(&self, board: &Board, dtz: bool) -> Option<(Arc<Table>, bool)> {
+        let (white, black) = (material(board, Color::White), material(board, Color::Black));
+        let names = if dtz {
+            &self.dtz_names
+        } else {
+            &self.wdl_names
+        };
+        let (name, black_stronger) = if names.contains(&format!("{white}v{black}")) {
+            (format!("{white}v{black}"), false)
+        } else {
+            (format!("{black}v{white}"), true)
+        };
+        if !names.contains(&name) {
+            return None;
+        }
+        let mut tables = self.tables.lock().unwrap();
+        let table = tables
+            .entry((name.clone(), dtz))
+            .or_insert_with(|| {
+                let extension = if dtz { "rtbz" } else { "rtbw" };
+                let path = self.directory.join(format!("{name}.{extension}"));
+                let bytes = std::fs::read(path).ok()?;
+                Table::read(bytes, &name, dtz, &self.indexes).map(Arc::new)
+            })
+            .clone()?;
+        Some((table, black_stronger))
+    }
+
+    /// the result stored in the WDL table, which leaves out en passant captures.
+    fn probe_wdl_table(&self, board: &Board) -> Option<i32> {
+        if board
+            .squares
+            .iter()
+            .flatten()
+            .filter(|&&p| p != Piece::Empty)
+            .count()
+            == 2
+        {
+            return Some(DRAW);
+        }
+        let (table, black_stronger) = self.table(board, false)?;
+        table.probe(board, black_stronger, DRAW, &self.indexes)?
+    }
+
+    /// the result of the position, found by trying captures as well as looking in the table,
+    /// since tables do not know about en passant and some stored values are for other moves.
+    /// With zeroing, pawn moves are tried too. Also returns true if the best move is one
+    /// of the moves tried, as the DTZ table's value is then not to be trusted.
+    fn search(&self, board: &mut Board, zeroing: bool) -> Option<(i32, bool)> {
+        let moves = board.legal_moves();
+        if moves.is_empty() {
+            return Some((if board.is_in_check() { LOSS } else { DRAW }, false));
+        }
+        let mut best = LOSS;
+        let mut tried = 0;
+        for &m in moves.iter() {
+            let pawn = board.piece_at(m.from).kind() == Some(Type::Pawn);
+            if !(board.is_capture(m) || (zeroing && pawn)) {
+                continue;
+            }
+            tried += 1;
+            board.make_move(m);
+            let result = self.search(board, false);
+            board.unmake_move();
+            let value = -result?.0;
+            if value > best {
+                best = value;
+                if value >= WIN {
+                    return Some((value, true));
+                }
+            }
+        }
+        let all_tried = tried == moves.len();
+        let value = if all_tried {
+            best
+        } else {
+            self.probe_wdl_table(board)?
+        };
+        if best >= value {
+            return Some((best, best > DRAW || all_tried));
+        }
+        Some((value, false))
+    }
+
+    /// the DTZ of the position, negative when losing.
+    fn probe_dtz(&self, board: &mut Board) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(board, true)?;
+        if wdl == DRAW {
+            return Some(0);
+        }
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        let (table, black_stronger) = self.table(board, true)?;
+        if let Some(dtz) = table.probe(board, black_stronger, wdl, &self.indexes)? {
+            let cursed = if wdl.abs() == 1 { 100 } else { 0 };
+            return Some((dtz + cursed) * wdl.signum());
+        }
+        // the table only stores the other side to move, so look one move ahead
+        let mut min_dtz = i32::MAX;
+        for m in board.legal_moves() {
+            let zeroing = board.is_capture(m) || board.piece_at(m.from).kind() == Some(Type::Pawn);
+            board.make_move(m);
+            let dtz = if zeroing {
+                self.search(board, false)
+                    .map(|(wdl, _)| -dtz_before_zeroing(wdl))
+            } else {
+                self.probe_dtz(board).map(|dtz| -dtz)
+            };
+            let mates = dtz == Some(1) && board.is_checkmate();
+            board.unmake_move();
+            let mut dtz = dtz?;
+            if mates {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+    }
+
+    /// the win, draw or loss for the side to move, or None if the tables do not have it.
+    pub fn probe_wdl(&self, board: &mut Board) -> Option<i32> {
+        if !self.can_probe(board) {
+            return None;
+        }
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    /// the result and DTZ of the position, or None if the tables do not have it.
+    pub fn probe(&self, board: &mut Board) -> Option<Probe> {
+        let wdl = self.probe_wdl(board)?;
+        let dtz = self.probe_dtz(board)?;
+        Some(Probe { wdl, dtz })
+    }
+
+    /// the move that keeps the best result, winning by the shortest DTZ
+    /// or losing by the longest, with the result of the position.
+    pub fn best_move(&self, board: &mut Board) -> Option<(Move, i32)> {
+        let wdl = self.probe_wdl(board)?;
+        let mut best = None;
+        for m in board.legal_moves() {
+            board.make_move(m);
+            let dtz = if board.halfmove_clock == 0 {
+                self.search(board, false)
+                    .map(|(wdl, _)| dtz_before_zeroing(-wdl))
+            } else {
+                self.probe_dtz(board).map(|dtz| -dtz - dtz.signum())
+            };
+            let mates = board.is_checkmate();
+            board.unmake_move();
+            let dtz = if mates { 1 } else { dtz? };
+            let rank = match dtz {
+                dtz if dtz > 0 => (2, -dtz),
+                0 => (1, 0),
+                dtz => (0, -dtz),
+            };
+            if best.is_none_or(|(_, best_rank)| rank > best_rank) {
+                best = Some((m, rank));
+            }
+        }
+        best.map(|(m, _)| (m, wdl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// makes an empty directory in the temp directory with the given files of garbage in it.
+    fn directory(name: &str, files: &[&str]) -> String {
+        let directory =
+            std::env::temp_dir().join(format!("syzygy-test-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for file in files {
+            std::fs::write(directory.join(file), [0u8; 64]).unwrap();
+        }
+        directory.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn needs_tables_to_open() {
+        assert!(Tablebase::open("/no/such/directory").is_err());
+        let empty = directory("empty", &[]);
+        assert_eq!(
+            Tablebase::open(&empty).err(),
+            Some(format!("no Syzygy tables in {empty}"))
+        );
+    }
+
+    #[test]
+    fn finds_table_names() {
+        let tablebase = Tablebase::open(&directory(
+            "names",
+            &["KQvK.rtbw", "KRPvKR.rtbw", "KQvK.rtbz"],
+        ))
+        .unwrap();
+        assert_eq!(tablebase.max_pieces, 5);
+        assert!(tablebase.dtz_names.contains("KQvK"));
+        assert!(!tablebase.dtz_names.contains("KRPvKR"));
+    }
+
+    #[test]
+    fn bad_tables_are_not_probed() {
+        let tablebase = Tablebase::open(&directory("bad", &["KQvK.rtbw"])).unwrap();
+        // two kings are a draw without looking anything up
+        let mut board = Board::from_fen("8/8/4k3/8/8/3K4/8/8 w - - 0 1").unwrap();
+        assert_eq!(tablebase.probe_wdl(&mut board), Some(DRAW));
+        let mut board = Board::from_fen("8/8/4k3/8/8/3K4/8/7Q w - - 0 1").unwrap();
+        assert_eq!(tablebase.probe_wdl(&mut board), None);
+        // too many pieces, and castling rights, are never in the tables
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        assert_eq!(tablebase.probe(&mut board), None);
+    }
+
+    #[test]
+    fn names_material() {
+        let board = Board::from_fen("8/8/4k3/3n4/8/3K4/2RP4/8 w - - 0 1").unwrap();
+        assert_eq!(material(&board, Color::White), "KRP");
+        assert_eq!(material(&board, Color::Black), "KN");
+        assert_eq!(wdl_name(WIN), "win");
+        assert_eq!(wdl_name(BLESSED_LOSS), "blessed loss");
+    }
+}
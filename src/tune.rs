@@ -0,0 +1,275 @@
+//! Tunes the evaluation weights on quiet positions labeled with the results of their games.
+//! The weights are moved until the evaluation, mapped through a sigmoid, predicts the results
+//! as well as it can, which is Texel's tuning method.
+
+use crate::board::Board;
+use crate::eval::{self, Weights};
+use crate::random::Rng;
+use std::thread;
+
+/// How far a weight is moved to find out how much the evaluation depends on it.
+/// A multiple of the phase of the starting position keeps the rounding of the taper small.
+const NUDGE: i32 = 24 * 1000;
+
+/// A position with the points white scored in its game.
+pub struct Position {
+    board: Board,
+    /// 1 for a win, 0.5 for a draw and 0 for a loss.
+    result: f64,
+}
+
+/// reads positions from an EPD file. The result is read from a c9 operation like c9 "1-0",
+/// from a number in brackets like [0.5], or from a result written on its own.
+pub fn read_positions(path: &str) -> Result<Vec<Position>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let mut positions = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let error = |e: String| format!("{path} line {}: {e}", i + 1);
+        let board = Board::from_fen(&fields[..fields.len().min(4)].join(" ")).map_err(error)?;
+        let result = parse_result(&fields[4.min(fields.len())..])
+            .ok_or_else(|| error("no result".into()))?;
+        positions.push(Position { board, result });
+    }
+    if positions.is_empty() {
+        return Err(format!("there are no positions in {path}"));
+    }
+    Ok(positions)
+}
+
+/// finds the result among the fields that follow the position.
+fn parse_result(fields: &[&str]) -> Option<f64> {
+    for field in fields {
+        match field.trim_matches(|c| c == '"' || c == ';') {
+            "1-0" | "[1.0]" | "[1]" => return Some(1.),
+            "0-1" | "[0.0]" | "[0]" => return Some(0.),
+            "1/2-1/2" | "[0.5]" => return Some(0.5),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// the expected points for white of a score from white's point of view.
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1. / (1. + 10f64.powf(-k * score / 400.))
+}
+
+/// runs the function on every position, split over all the cores, keeping the order.
+fn map_positions<T: Send>(positions: &[Position], f: &(dyn Fn(&Position) -> T + Sync)) -> Vec<T> {
+    let threads = thread::available_parallelism().map_or(1, |cores| cores.get());
+    let chunk = positions.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = positions
+            .chunks(chunk)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<T>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// the mean squared difference between the results and what the scores predict.
+fn mean_error(scores: &[f64], positions: &[Position], k: f64) -> f64 {
+    let total: f64 = scores
+        .iter()
+        .zip(positions.iter())
+        .map(|(score, position)| (position.result - sigmoid(*score, k)).powi(2))
+        .sum();
+    total / positions.len() as f64
+}
+
+/// the mean squared error of the evaluation with the weights over the positions.
+pub fn error(positions: &[Position], weights: &Weights, k: f64) -> f64 {
+    let scores = map_positions(positions, &|position| {
+        eval::evaluate_white(&position.board, weights) as f64
+    });
+    mean_error(&scores, positions, k)
+}
+
+/// finds the scaling of the sigmoid that makes the weights predict the results best.
+pub fn fit_k(positions: &[Position], weights: &Weights) -> f64 {
+    let scores = map_positions(positions, &|position| {
+        eval::evaluate_white(&position.board, weights) as f64
+    });
+    let (mut low, mut high) = (0., 4.);
+    for _ in 0..60 {
+        let a = low + (high - low) / 3.;
+        let b = high - (high - low) / 3.;
+        if mean_error(&scores, positions, a) < mean_error(&scores, positions, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2.
+}
+
+/// The evaluation of a position, and how much it moves with each weight it depends on.
+struct Terms {
+    score: f64,
+    /// the indexes of the weights, counted as in Weights::values, with their slopes.
+    slopes: Vec<(u32, f32)>,
+}
+
+/// works out the terms of a position by moving each weight in turn.
+fn terms(position: &Position, weights: &Weights, count: usize) -> Terms {
+    let score = eval::evaluate_white(&position.board, weights);
+    let mut nudged = weights.clone();
+    let mut slopes = vec![];
+    for index in 0..count {
+        nudged.nudge(index, NUDGE);
+        let change = eval::evaluate_white(&position.board, &nudged) - score;
+        nudged.nudge(index, -NUDGE);
+        if change != 0 {
+            slopes.push((index as u32, change as f32 / NUDGE as f32));
+        }
+    }
+    Terms {
+        score: score as f64,
+        slopes,
+    }
+}
+
+/// The settings of a tuning run.
+pub struct Tuner {
+    /// how many times every position is gone through.
+    pub epochs: usize,
+    /// how far the weights move with each step, in centipawns.
+    pub rate: f64,
+    /// how many positions each step looks at.
+    pub batch: usize,
+    /// picks the order the positions are gone through in, so a run can be repeated.
+    pub seed: u64,
+}
+
+impl Tuner {
+    /// tunes the weights with Adam steps on the squared error, calling report with
+    /// the error after each epoch. The evaluation is treated as linear in the weights,
+    /// which it is apart from rounding, so it is only worked out once per position.
+    pub fn tune(
+        &self,
+        positions: &[Position],
+        start: &Weights,
+        k: f64,
+        report: &mut dyn FnMut(usize, f64),
+    ) -> Weights {
+        let initial: Vec<f64> = start.values().iter().map(|&v| v as f64).collect();
+        let count = initial.len();
+        let terms = map_positions(positions, &|position| terms(position, start, count));
+        let mut weights = initial.clone();
+        let mut moment = vec![0.; count];
+        let mut velocity = vec![0.; count];
+        let mut gradient = vec![0.; count];
+        let mut order: Vec<usize> = (0..positions.len()).collect();
+        let mut rng = Rng::new(self.seed);
+        let mut steps = 0;
+        let score = |terms: &Terms, weights: &[f64]| {
+            terms.score
+                + terms
+                    .slopes
+                    .iter()
+                    .map(|&(i, slope)| slope as f64 * (weights[i as usize] - initial[i as usize]))
+                    .sum::<f64>()
+        };
+        for epoch in 1..=self.epochs {
+            for i in (1..order.len()).rev() {
+                order.swap(i, rng.below(i as u64 + 1) as usize);
+            }
+            for batch in order.chunks(self.batch.max(1)) {
+                gradient.iter_mut().for_each(|g| *g = 0.);
+                for &p in batch {
+                    let predicted = sigmoid(score(&terms[p], &weights), k);
+                    // the derivative of the squared error through the sigmoid
+                    let slope = 2.
+                        * (predicted - positions[p].result)
+                        * predicted
+                        * (1. - predicted)
+                        * k
+                        * std::f64::consts::LN_10
+                        / 400.;
+                    for &(i, s) in terms[p].slopes.iter() {
+                        gradient[i as usize] += slope * s as f64;
+                    }
+                }
+                steps += 1;
+                let (beta1, beta2) = (0.9, 0.999);
+                for i in 0..count {
+                    let g = gradient[i] / batch.len() as f64;
+                    moment[i] = beta1 * moment[i] + (1. - beta1) * g;
+                    velocity[i] = beta2 * velocity[i] + (1. - beta2) * g * g;
+                    let m = moment[i] / (1. - f64::powi(beta1, steps));
+                    let v = velocity[i] / (1. - f64::powi(beta2, steps));
+                    weights[i] -= self.rate * m / (v.sqrt() + 1e-8);
+                }
+            }
+            let scores: Vec<f64> = terms.iter().map(|t| score(t, &weights)).collect();
+            report(epoch, mean_error(&scores, positions, k));
+        }
+        let mut tuned = start.clone();
+        let rounded: Vec<i32> = weights.iter().map(|w| w.round() as i32).collect();
+        tuned.set_values(&rounded);
+        tuned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// writes the lines to a file in the temp directory and reads positions from it.
+    fn read(name: &str, lines: &str) -> Result<Vec<Position>, String> {
+        let path =
+            std::env::temp_dir().join(format!("tune-test-{}-{name}.epd", std::process::id()));
+        std::fs::write(&path, lines).unwrap();
+        read_positions(path.to_str().unwrap())
+    }
+
+    /// positions where the side with more material went on to win, and even ones drawn.
+    const POSITIONS: &str = "\
+4k3/8/8/8/8/8/8/3QK3 w - - c9 \"1-0\";
+3qk3/8/8/8/8/8/8/4K3 w - - c9 \"0-1\";
+4k3/8/8/8/8/8/8/R3K3 b - - [1.0]
+r3k3/8/8/8/8/8/8/4K3 b - - [0.0]
+4k3/pppp4/8/8/8/8/PPPP4/4K3 w - - [0.5]
+4k3/8/8/3n4/8/8/8/3NK3 w - - 1/2-1/2
+4k3/8/8/8/8/8/PPP5/4K3 w - - 1-0
+4k3/ppp5/8/8/8/8/8/4K3 b - - 0-1
+";
+
+    #[test]
+    fn reads_results() {
+        let positions = read("results", POSITIONS).unwrap();
+        let results: Vec<f64> = positions.iter().map(|p| p.result).collect();
+        assert_eq!(results, [1., 0., 1., 0., 0.5, 0.5, 1., 0.]);
+        assert!(read("none", "4k3/8/8/8/8/8/8/3QK3 w - -\n").is_err());
+        assert!(read("bad", "4k3/8/9/8/8/8/8/3QK3 w - - 1-0\n").is_err());
+        assert!(read("empty", "\n\n").is_err());
+    }
+
+    #[test]
+    fn tuning_predicts_better() {
+        let positions = read("tune", POSITIONS).unwrap();
+        let weights = Weights::default();
+        let k = fit_k(&positions, &weights);
+        assert!(k > 0.);
+        assert_eq!(sigmoid(0., k), 0.5);
+        let tuner = Tuner {
+            epochs: 20,
+            rate: 2.,
+            batch: 4,
+            seed: 1,
+        };
+        let before = error(&positions, &weights, k);
+        let tuned = tuner.tune(&positions, &weights, k, &mut |_, _| ());
+        assert!(error(&positions, &tuned, k) < before);
+        // the same seed tunes the same way
+        let again = tuner.tune(&positions, &weights, k, &mut |_, _| ());
+        assert_eq!(again.values(), tuned.values());
+    }
+}
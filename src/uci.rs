@@ -0,0 +1,463 @@
+//! The Universal Chess Interface.
+//! Lets the engine be used by chess GUIs and tournament managers by talking
+//! over standard input and output instead of opening a window.
+
+use crate::board::{Board, Color};
+use crate::book::Book;
+use crate::dtm::DtmTables;
+use crate::eval::Weights;
+use crate::nnue::Network;
+use crate::random::Rng;
+use crate::search::{mate_in, Limits, Search, SearchInfo};
+use crate::syzygy::Tablebase;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The name the engine gives itself.
+const ENGINE_NAME: &str = "Chess";
+/// The authors the engine reports.
+const ENGINE_AUTHOR: &str = "Patrick and Shane McDonough";
+
+/// The most search threads the Threads option allows.
+const MAX_THREADS: usize = 256;
+/// The largest transposition table the Hash option allows, in megabytes.
+const MAX_HASH: usize = 1024;
+/// The most lines the MultiPV option allows.
+const MAX_MULTI_PV: usize = 256;
+/// The names of the check options that switch parts of the search on and off.
+const OPTION_NAMES: [&str; 6] = [
+    "MVVLVA",
+    "Killers",
+    "History",
+    "NullMove",
+    "LMR",
+    "CheckExtensions",
+];
+
+/// reads UCI commands from standard input until "quit" or the end of the input.
+pub fn run() -> Result<(), String> {
+    let mut engine = Engine::new();
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if !engine.handle(&line) {
+            break;
+        }
+    }
+    engine.stop_search();
+    Ok(())
+}
+
+/// writes a search score the way UCI expects it.
+fn format_score(score: i32) -> String {
+    match mate_in(score) {
+        Some(n) => format!("mate {n}"),
+        None => format!("cp {score}"),
+    }
+}
+
+/// writes the info lines about a completed depth, one for each line of MultiPV.
+fn format_info(info: &SearchInfo) -> String {
+    let millis = info.time.as_millis().max(1);
+    let main_line = [(info.score, info.pv.clone())];
+    let lines = if info.lines.len() > 1 {
+        &info.lines[..]
+    } else {
+        &main_line[..]
+    };
+    let mut out = vec![];
+    for (i, (score, pv)) in lines.iter().enumerate() {
+        let multi_pv = if lines.len() > 1 {
+            format!(" multipv {}", i + 1)
+        } else {
+            String::new()
+        };
+        let pv: Vec<String> = pv.iter().map(|m| m.to_uci()).collect();
+        out.push(format!(
+            "info depth {}{multi_pv} score {} nodes {} nps {} time {} pv {}",
+            info.depth,
+            format_score(*score),
+            info.nodes,
+            info.nodes as u128 * 1000 / millis,
+            millis,
+            pv.join(" ")
+        ));
+    }
+    out.join("\n")
+}
+
+/// The engine's side of a UCI conversation.
+struct Engine {
+    board: Board,
+    /// None while a search thread is using it.
+    search: Option<Search>,
+    thread: Option<JoinHandle<Search>>,
+    /// set to stop the running search.
+    stop: Arc<AtomicBool>,
+    /// the opening book from the BookFile option.
+    book: Option<Book>,
+    /// true to play from the book before searching.
+    own_book: bool,
+    /// true to pick book moves at random by weight instead of always the heaviest.
+    book_random: bool,
+    /// the network from the EvalFile option.
+    network: Option<Arc<Network>>,
+    /// true to evaluate with the network instead of the hand written evaluation.
+    use_nnue: bool,
+}
+
+impl Engine {
+    fn new() -> Self {
+        let search = Search::default();
+        Self {
+            board: Board::new(),
+            stop: search.stop.clone(),
+            search: Some(search),
+            thread: None,
+            book: None,
+            own_book: false,
+            book_random: true,
+            network: None,
+            use_nnue: false,
+        }
+    }
+
+    /// handles one line of input. Returns false when the engine should quit.
+    fn handle(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.first() {
+            Some(command) => *command,
+            None => return true,
+        };
+        match command {
+            "uci" => {
+                println!("id name {ENGINE_NAME}");
+                println!("id author {ENGINE_AUTHOR}");
+                println!("option name Hash type spin default 16 min 1 max {MAX_HASH}");
+                println!("option name Threads type spin default 1 min 1 max {MAX_THREADS}");
+                println!("option name MultiPV type spin default 1 min 1 max {MAX_MULTI_PV}");
+                println!("option name OwnBook type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookRandom type check default true");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name DtmPath type string default <empty>");
+                println!("option name WeightsFile type string default <empty>");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name UseNNUE type check default false");
+                for name in OPTION_NAMES.iter() {
+                    println!("option name {name} type check default true");
+                }
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                self.stop_search();
+                self.search.as_mut().unwrap().clear();
+                self.board = Board::new();
+            }
+            "setoption" => {
+                self.stop_search();
+                self.set_option(&words[1..]);
+            }
+            "position" => {
+                self.stop_search();
+                if let Err(e) = self.set_position(&words[1..]) {
+                    println!("info string {e}");
+                }
+            }
+            "go" => {
+                self.stop_search();
+                self.go(&words[1..]);
+            }
+            // pondering is treated as an infinite search, so a ponder hit just asks for the move
+            "stop" | "ponderhit" => self.stop_search(),
+            "quit" => return false,
+            _ => (),
+        }
+        true
+    }
+
+    /// tells the running search to stop and waits for it to print its move.
+    /// A new command stops the search too, so an infinite one cannot keep it waiting.
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            self.search = Some(thread.join().unwrap());
+        }
+    }
+
+    /// handles "setoption name <name> value <value>".
+    fn set_option(&mut self, words: &[&str]) {
+        let value_at = words.iter().position(|&w| w == "value");
+        let name = words
+            .get(1..value_at.unwrap_or(words.len()))
+            .unwrap_or(&[])
+            .join(" ");
+        let value = value_at.map_or(String::new(), |i| words[i + 1..].join(" "));
+        let search = self.search.as_mut().unwrap();
+        let flag = value == "true";
+        match name.as_str() {
+            "Hash" => match value.parse::<usize>() {
+                Ok(megabytes) => search.set_hash_size(megabytes.clamp(1, MAX_HASH)),
+                Err(_) => println!("info string bad Hash value {value}"),
+            },
+            "Threads" => match value.parse() {
+                Ok(threads) if (1..=MAX_THREADS).contains(&threads) => search.threads = threads,
+                _ => println!("info string bad Threads value {value}"),
+            },
+            "MultiPV" => match value.parse() {
+                Ok(lines) if (1..=MAX_MULTI_PV).contains(&lines) => search.multi_pv = lines,
+                _ => println!("info string bad MultiPV value {value}"),
+            },
+            "OwnBook" => self.own_book = flag,
+            "BookFile" if value.is_empty() || value == "<empty>" => self.book = None,
+            "BookFile" => match Book::open(&value) {
+                Ok(book) => self.book = Some(book),
+                Err(e) => println!("info string {e}"),
+            },
+            "BookRandom" => self.book_random = flag,
+            "SyzygyPath" if value.is_empty() || value == "<empty>" => search.tablebase = None,
+            // without the tables the engine just searches as before
+            "SyzygyPath" => match Tablebase::open(&value) {
+                Ok(tablebase) => search.tablebase = Some(Arc::new(tablebase)),
+                Err(e) => {
+                    search.tablebase = None;
+                    println!("info string {e}");
+                }
+            },
+            "DtmPath" if value.is_empty() || value == "<empty>" => search.dtm = None,
+            "DtmPath" => match DtmTables::open(&value) {
+                Ok(tables) => search.dtm = Some(Arc::new(tables)),
+                Err(e) => {
+                    search.dtm = None;
+                    println!("info string {e}");
+                }
+            },
+            "WeightsFile" if value.is_empty() || value == "<empty>" => {
+                search.weights = Weights::default()
+            }
+            "WeightsFile" => match Weights::read(&value) {
+                Ok(weights) => search.weights = weights,
+                Err(e) => println!("info string {e}"),
+            },
+            "EvalFile" if value.is_empty() || value == "<empty>" => self.network = None,
+            "EvalFile" => match Network::open(&value) {
+                Ok(network) => self.network = Some(Arc::new(network)),
+                Err(e) => {
+                    self.network = None;
+                    println!("info string {e}");
+                }
+            },
+            "UseNNUE" => self.use_nnue = flag,
+            "MVVLVA" => search.options.mvv_lva = flag,
+            "Killers" => search.options.killers = flag,
+            "History" => search.options.history = flag,
+            "NullMove" => search.options.null_move = flag,
+            "LMR" => search.options.late_move_reductions = flag,
+            "CheckExtensions" => search.options.check_extensions = flag,
+            _ => println!("info string unknown option {name}"),
+        }
+        // the network is only used once it is both loaded and switched on
+        search.network = match self.network {
+            Some(ref network) if self.use_nnue => Some(network.clone()),
+            _ => None,
+        };
+    }
+
+    /// handles "position [startpos | fen <fen>] moves <moves>".
+    fn set_position(&mut self, words: &[&str]) -> Result<(), String> {
+        let moves_at = words
+            .iter()
+            .position(|&w| w == "moves")
+            .unwrap_or(words.len());
+        let mut board = match words.first() {
+            Some(&"startpos") => Board::new(),
+            Some(&"fen") => Board::from_fen(&words[1..moves_at].join(" "))?,
+            _ => return Err("position needs startpos or fen".to_owned()),
+        };
+        for word in words.iter().skip(moves_at + 1) {
+            let m = board
+                .parse_uci_move(word)
+                .ok_or_else(|| format!("illegal move {word}"))?;
+            board.make_move(m);
+        }
+        self.board = board;
+        Ok(())
+    }
+
+    /// handles "go" by starting a search on another thread,
+    /// so "stop" and "isready" can still be answered.
+    fn go(&mut self, words: &[&str]) {
+        let (limits, infinite) = self.limits(words);
+
+        // an infinite search is for analysis, so it is not answered from the book
+        if let (true, false, Some(book)) = (self.own_book, infinite, self.book.as_ref()) {
+            if let Some(m) = book.pick(&mut self.board, self.book_random, &mut Rng::from_time()) {
+                println!("bestmove {}", m.to_uci());
+                return;
+            }
+        }
+
+        let mut search = self.search.take().unwrap();
+        let mut board = self.board.clone();
+        let stop = self.stop.clone();
+        stop.store(false, Ordering::Relaxed);
+        self.thread = Some(thread::spawn(move || {
+            let info = search.search(&mut board, limits, &mut |info| {
+                println!("{}", format_info(info))
+            });
+            // an infinite search must not answer before it is told to stop
+            while infinite && !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            match info.pv.first() {
+                Some(m) => println!("bestmove {}", m.to_uci()),
+                None => println!("bestmove 0000"),
+            }
+            search
+        }));
+    }
+
+    /// works out the limits of the search "go" asks for, and true if it is an infinite one.
+    fn limits(&self, words: &[&str]) -> (Limits, bool) {
+        let mut limits = Limits::default();
+        let mut time = [None, None];
+        let mut increment = [0, 0];
+        let mut moves_to_go = None;
+        let mut infinite = false;
+        let mut i = 0;
+        while i < words.len() {
+            // a GUI may send a negative time once a clock has run out
+            let value = words
+                .get(i + 1)
+                .and_then(|v| v.parse::<i64>().ok())
+                .map(|v| v.max(0) as u64);
+            match words[i] {
+                "depth" => limits.depth = value.map(|v| v as u32),
+                "nodes" => limits.nodes = value,
+                "movetime" => limits.movetime = value.map(Duration::from_millis),
+                "wtime" => time[Color::White.index()] = value,
+                "btime" => time[Color::Black.index()] = value,
+                "winc" => increment[Color::White.index()] = value.unwrap_or(0),
+                "binc" => increment[Color::Black.index()] = value.unwrap_or(0),
+                "movestogo" => moves_to_go = value,
+                "infinite" | "ponder" => {
+                    infinite = true;
+                    i += 1;
+                    continue;
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            }
+            i += 2;
+        }
+        let side = self.board.color.index();
+        if let (Some(left), None, false) = (time[side], limits.movetime, infinite) {
+            limits.movetime = Some(Duration::from_millis(time_for_move(
+                left,
+                increment[side],
+                moves_to_go,
+            )));
+        }
+        (limits, infinite)
+    }
+}
+
+/// decides how many milliseconds to think given the time left on the clock.
+fn time_for_move(left: u64, increment: u64, moves_to_go: Option<u64>) -> u64 {
+    let moves = moves_to_go.unwrap_or(30).max(1);
+    let time = left / moves + increment * 3 / 4;
+    // keep a little time back for talking to the GUI
+    time.min(left.saturating_sub(50)).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// an engine after some commands.
+    fn engine(lines: &[&str]) -> Engine {
+        let mut engine = Engine::new();
+        for line in lines {
+            assert!(engine.handle(line));
+        }
+        engine
+    }
+
+    #[test]
+    fn positions() {
+        let engine = engine(&["position startpos moves e2e4 e7e5 g1f3"]);
+        assert_eq!(
+            engine.board.to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+        let fen = "8/8/8/4k3/8/8/8/3QK3 w - - 0 1";
+        let engine = self::engine(&[&format!("position fen {fen} moves d1d4")]);
+        assert_eq!(engine.board.to_fen(), "8/8/8/4k3/3Q4/8/8/4K3 b - - 1 1");
+        // a bad position leaves the last one
+        let mut engine = self::engine(&["position startpos moves e2e4"]);
+        assert!(engine.set_position(&["startpos", "moves", "e2e5"]).is_err());
+        assert!(engine.set_position(&["nowhere"]).is_err());
+        assert_eq!(engine.board.color, Color::Black);
+    }
+
+    #[test]
+    fn search_limits() {
+        let engine = engine(&["position startpos"]);
+        let (limits, infinite) = engine.limits(&["depth", "6", "nodes", "1000"]);
+        assert_eq!(
+            (limits.depth, limits.nodes, infinite),
+            (Some(6), Some(1000), false)
+        );
+        let (limits, _) = engine.limits(&["movetime", "250"]);
+        assert_eq!(limits.movetime, Some(Duration::from_millis(250)));
+        let (limits, infinite) = engine.limits(&["infinite"]);
+        assert_eq!((limits.movetime, infinite), (None, true));
+        // the clock of the side to move decides
+        let (limits, _) = engine.limits(&["wtime", "60000", "btime", "1000", "winc", "1000"]);
+        assert_eq!(limits.movetime, Some(Duration::from_millis(2750)));
+        // a clock that has run out still limits the search
+        let (limits, _) = engine.limits(&["wtime", "-120", "btime", "5000"]);
+        assert_eq!(limits.movetime, Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn time_left_is_shared_out() {
+        assert_eq!(time_for_move(30_000, 0, None), 1_000);
+        assert_eq!(time_for_move(10_000, 0, Some(5)), 2_000);
+        // never more than is left, keeping some back
+        assert_eq!(time_for_move(100, 10_000, None), 50);
+        assert_eq!(time_for_move(0, 0, Some(0)), 1);
+    }
+
+    #[test]
+    fn options() {
+        let engine = engine(&[
+            "setoption name MultiPV value 3",
+            "setoption name Threads value 0",
+            "setoption name NullMove value false",
+            "setoption name Hash value 100000",
+        ]);
+        let search = engine.search.as_ref().unwrap();
+        assert_eq!(search.multi_pv, 3);
+        assert_eq!(search.threads, 1);
+        assert!(!search.options.null_move);
+    }
+
+    #[test]
+    fn new_commands_stop_an_infinite_search() {
+        let mut engine = engine(&["position startpos", "go infinite"]);
+        assert!(engine.search.is_none());
+        assert!(engine.handle("position startpos moves e2e4"));
+        assert!(engine.search.is_some());
+        assert!(engine.handle("go depth 2"));
+        assert!(!engine.handle("quit"));
+        engine.stop_search();
+        assert!(engine.search.is_some());
+    }
+}
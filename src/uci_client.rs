@@ -0,0 +1,372 @@
+//! Talks to an outside chess engine over the Universal Chess Interface,
+//! so the board can be played against any engine installed on the machine.
+
+use crate::board::{Board, Move};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the engine gets to answer "uci" and "isready".
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How much longer than the asked for move time the engine may take before it is given up on.
+const MOVE_GRACE: Duration = Duration::from_secs(5);
+/// How long the engine gets to exit after "quit" before it is killed.
+const QUIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A running engine process.
+pub struct UciEngine {
+    child: Child,
+    stdin: ChildStdin,
+    /// every line the engine writes, read on another thread so the game never blocks on it.
+    lines: Receiver<String>,
+    /// the name the engine gave in "id name".
+    pub name: String,
+    /// when the engine was asked for a move and how long it was given.
+    thinking: Option<(Instant, Duration)>,
+    /// the answer the engine has to give before it is ready, like "uciok",
+    /// and how long it has to give it.
+    waiting: Option<(&'static str, Instant)>,
+    /// how many moves are still to come from searches stopped by a new game.
+    stopped_searches: usize,
+}
+
+impl UciEngine {
+    /// starts the engine at the given path. It is ready once poll has read its answers,
+    /// so a slow engine never holds up the game.
+    pub fn spawn(path: &str) -> Result<Self, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("could not start engine {path}: {e}"))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        let mut engine = Self {
+            child,
+            stdin,
+            lines,
+            name: path.to_owned(),
+            thinking: None,
+            waiting: None,
+            stopped_searches: 0,
+        };
+        engine.send("uci")?;
+        engine.wait_for("uciok");
+        Ok(engine)
+    }
+
+    /// writes a line to the engine.
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{line}")
+            .and_then(|_| self.stdin.flush())
+            .map_err(|_| format!("{} stopped responding", self.name))
+    }
+
+    /// expects the engine to answer with a word before it is ready.
+    fn wait_for(&mut self, word: &'static str) {
+        self.waiting = Some((word, Instant::now() + HANDSHAKE_TIMEOUT));
+    }
+
+    /// tells the engine a new game is starting. It is ready again once poll has read
+    /// its answer.
+    pub fn new_game(&mut self) -> Result<(), String> {
+        // a move from the last game must not be taken as the answer to the next one
+        if self.thinking.take().is_some() {
+            self.send("stop")?;
+            self.stopped_searches += 1;
+        }
+        // an engine still answering "uci" starts its first game after that
+        if self.waiting.is_some_and(|(word, _)| word == "uciok") {
+            return Ok(());
+        }
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.wait_for("readyok");
+        Ok(())
+    }
+
+    /// true once the engine has answered everything it was asked before a game.
+    pub fn is_ready(&self) -> bool {
+        self.waiting.is_none()
+    }
+
+    /// true while the engine is working on a move.
+    pub fn is_thinking(&self) -> bool {
+        self.thinking.is_some()
+    }
+
+    /// sends the game so far and asks the engine for a move.
+    /// The answer is picked up later with poll.
+    pub fn go(
+        &mut self,
+        start_fen: &str,
+        moves: &[Move],
+        movetime: Duration,
+    ) -> Result<(), String> {
+        let limits = format!("movetime {}", movetime.as_millis());
+        self.go_with(start_fen, moves, &limits, movetime)
+    }
+
+    /// like go, but with the words that follow "go", like "depth 8" or "nodes 10000".
+    /// The engine is given up on if it takes much longer than the expected time.
+    pub fn go_with(
+        &mut self,
+        start_fen: &str,
+        moves: &[Move],
+        limits: &str,
+        expected: Duration,
+    ) -> Result<(), String> {
+        let mut position = format!("position fen {start_fen}");
+        if !moves.is_empty() {
+            let moves: Vec<String> = moves.iter().map(|m| m.to_uci()).collect();
+            position += " moves ";
+            position += &moves.join(" ");
+        }
+        self.send(&position)?;
+        self.send(&format!("go {limits}"))?;
+        self.thinking = Some((Instant::now(), expected));
+        Ok(())
+    }
+
+    /// reads what the engine has written without waiting, and returns its move once it has
+    /// picked one. board must be the position the engine was asked about.
+    pub fn poll(&mut self, board: &mut Board) -> Result<Option<Move>, String> {
+        let word = match self.read()? {
+            Some(word) => word,
+            None => return Ok(None),
+        };
+        match board.parse_uci_move(&word) {
+            Some(m) => Ok(Some(m)),
+            None => Err(format!("{} played an illegal move {word}", self.name)),
+        }
+    }
+
+    /// waits for the engine to be ready, for tools that can wait.
+    pub fn wait_until_ready(&mut self) -> Result<(), String> {
+        while !self.is_ready() {
+            self.read()?;
+            thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+
+    /// reads the lines the engine has written so far, going on with the handshake,
+    /// and returns the move it answered with once there is one.
+    fn read(&mut self) -> Result<Option<String>, String> {
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => {
+                    if let Some(name) = line.strip_prefix("id name ") {
+                        self.name = name.to_owned();
+                    }
+                    let mut words = line.split_whitespace();
+                    let word = words.next();
+                    match (word, self.waiting) {
+                        (Some("uciok"), Some(("uciok", _))) => {
+                            self.waiting = None;
+                            self.new_game()?;
+                            continue;
+                        }
+                        (Some("readyok"), Some(("readyok", _))) => {
+                            self.waiting = None;
+                            continue;
+                        }
+                        _ => (),
+                    }
+                    if word != Some("bestmove") {
+                        continue;
+                    }
+                    if self.stopped_searches > 0 {
+                        self.stopped_searches -= 1;
+                        continue;
+                    }
+                    if self.thinking.take().is_none() {
+                        continue;
+                    }
+                    return Ok(Some(words.next().unwrap_or("").to_owned()));
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.thinking = None;
+                    return Err(format!("{} crashed", self.name));
+                }
+            }
+        }
+        if let Some((word, deadline)) = self.waiting {
+            if Instant::now() > deadline {
+                return Err(format!("{} did not answer with {word}", self.name));
+            }
+        }
+        if let Some((start, movetime)) = self.thinking {
+            if start.elapsed() > movetime + MOVE_GRACE {
+                self.thinking = None;
+                return Err(format!("{} ran out of time", self.name));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for UciEngine {
+    /// asks the engine to quit, and makes sure it does.
+    fn drop(&mut self) {
+        let _ = self.send("stop");
+        let _ = self.send("quit");
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::board::START_FEN;
+    use std::os::unix::fs::PermissionsExt;
+
+    const MOVETIME: Duration = Duration::from_millis(100);
+
+    /// writes a fake engine as a shell script that answers "uci" and "go" with the given
+    /// commands, and starts it. The script removes itself once it is running.
+    fn spawn_fake(name: &str, on_uci: &str, on_go: &str) -> Result<UciEngine, String> {
+        let path = std::env::temp_dir().join(format!("fake-{name}-{}", std::process::id()));
+        let script = format!(
+            "#!/bin/sh\n\
+             rm -f \"$0\"\n\
+             while read line; do\n\
+             case \"$line\" in\n\
+             uci) echo \"id name Fake {name}\"; {on_uci} ;;\n\
+             isready) echo readyok ;;\n\
+             go*) {on_go} ;;\n\
+             quit) exit 0 ;;\n\
+             esac\n\
+             done\n"
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path = path.to_str().unwrap();
+        // another test starting a process at the same moment can keep the script open for
+        // writing a little longer, which makes starting it fail
+        let mut engine = UciEngine::spawn(path);
+        for _ in 0..20 {
+            match engine {
+                Err(ref e) if e.contains("busy") => {
+                    thread::sleep(Duration::from_millis(50));
+                    engine = UciEngine::spawn(path);
+                }
+                _ => break,
+            }
+        }
+        engine
+    }
+
+    /// asks for a move from the start and polls until the engine answers or gives up.
+    fn play(engine: &mut UciEngine) -> Result<Move, String> {
+        engine.wait_until_ready()?;
+        let mut board = Board::from_fen(START_FEN).unwrap();
+        engine.go(START_FEN, &[], MOVETIME)?;
+        assert!(engine.is_thinking());
+        let start = Instant::now();
+        loop {
+            if let Some(m) = engine.poll(&mut board)? {
+                return Ok(m);
+            }
+            assert!(
+                start.elapsed() < MOVETIME + MOVE_GRACE * 2,
+                "poll never gave up"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn plays_a_move() {
+        let mut engine = spawn_fake(
+            "normal",
+            "echo uciok",
+            "echo 'info depth 1 score cp 20'; echo 'bestmove e2e4'",
+        )
+        .unwrap();
+        assert_eq!(play(&mut engine).unwrap().to_uci(), "e2e4");
+        assert_eq!(engine.name, "Fake normal");
+        assert!(!engine.is_thinking());
+        // and again in a new game
+        engine.new_game().unwrap();
+        assert_eq!(play(&mut engine).unwrap().to_uci(), "e2e4");
+    }
+
+    #[test]
+    fn crash_is_an_error() {
+        let mut engine = spawn_fake("crash", "echo uciok", "exit 1").unwrap();
+        let e = play(&mut engine).unwrap_err();
+        assert!(e.ends_with("crashed"), "{}", e);
+        assert!(!engine.is_thinking());
+    }
+
+    #[test]
+    fn silence_runs_out_of_time() {
+        let mut engine = spawn_fake("silent", "echo uciok", ":").unwrap();
+        let start = Instant::now();
+        let e = play(&mut engine).unwrap_err();
+        assert!(e.ends_with("ran out of time"), "{}", e);
+        assert!(start.elapsed() >= MOVETIME + MOVE_GRACE);
+    }
+
+    #[test]
+    fn illegal_move_is_an_error() {
+        let mut engine = spawn_fake("illegal", "echo uciok", "echo 'bestmove e2e5'").unwrap();
+        let e = play(&mut engine).unwrap_err();
+        assert!(e.ends_with("played an illegal move e2e5"), "{}", e);
+    }
+
+    #[test]
+    fn starts_without_waiting() {
+        let start = Instant::now();
+        let mut engine = spawn_fake("slow", "sleep 1; echo uciok", "echo 'bestmove e2e4'").unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(!engine.is_ready());
+        engine.wait_until_ready().unwrap();
+        assert_eq!(engine.name, "Fake slow");
+        assert_eq!(play(&mut engine).unwrap().to_uci(), "e2e4");
+    }
+
+    #[test]
+    fn stopped_search_is_not_the_next_move() {
+        // the first search answers a2a3, every later one e2e4
+        let on_go = "n=$((n + 1)); if [ $n = 1 ]; then echo 'bestmove a2a3'; \
+                     else echo 'bestmove e2e4'; fi";
+        let mut engine = spawn_fake("stopped", "echo uciok", on_go).unwrap();
+        engine.wait_until_ready().unwrap();
+        engine.go(START_FEN, &[], MOVETIME).unwrap();
+        engine.new_game().unwrap();
+        assert!(!engine.is_thinking());
+        assert_eq!(play(&mut engine).unwrap().to_uci(), "e2e4");
+    }
+
+    #[test]
+    fn missing_engine_is_an_error() {
+        assert!(UciEngine::spawn("/nonexistent/engine").is_err());
+    }
+}